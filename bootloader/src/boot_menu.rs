@@ -0,0 +1,231 @@
+//! A text boot menu on the UEFI console, for choosing the kernel image, the GOP mode, the log level and KASLR.
+//!
+//! The menu only opens if a key is pressed before the timeout, so the boot is not interrupted by default.
+
+use core::fmt::Write;
+
+use uefi::prelude::*;
+use uefi::data_types::CStr16;
+use uefi::proto::console::gop::GraphicsOutput;
+use uefi::proto::console::text::Key;
+use uefi::proto::media::file::{Directory, FileAttribute, FileInfo};
+use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams};
+
+use shared::{LogLevel, MAX_KERNEL_NAME_LEN};
+
+/// Seconds to wait for a keypress before booting with the defaults.
+pub const MENU_TIMEOUT_SECS: usize = 3;
+
+/// The maximum number of kernel images listed.
+const MAX_KERNELS: usize = 9;
+
+/// A kernel file name in UCS-2, kept without allocation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KernelName {
+    buf: [u16; MAX_KERNEL_NAME_LEN + 1],
+}
+
+impl KernelName {
+    /// Returns None if the name is too long.
+    pub fn new(name: &CStr16) -> Option<Self> {
+        let name = name.to_u16_slice_with_nul();
+        if name.len() > MAX_KERNEL_NAME_LEN + 1 { return None; }
+
+        let mut buf = [0; MAX_KERNEL_NAME_LEN + 1];
+        buf[..name.len()].copy_from_slice(name);
+        Some(Self { buf })
+    }
+
+    pub fn as_cstr16(&self) -> &CStr16 {
+        let len = self.buf.iter().position(|&c| c == 0).unwrap();
+        CStr16::from_u16_with_nul(&self.buf[..=len]).unwrap()
+    }
+
+    /// Write the name in ASCII, for `BootOptions`. Returns the length.
+    pub fn to_ascii(&self, out: &mut [u8; MAX_KERNEL_NAME_LEN]) -> usize {
+        let mut len = 0;
+        for (dest, &c) in out.iter_mut().zip(self.buf.iter().take_while(|&&c| c != 0)) {
+            *dest = if c < 0x80 { c as u8 } else { b'?' };
+            len += 1;
+        }
+        len
+    }
+}
+
+/// The choices in the menu.
+pub struct MenuChoice {
+    pub kernel: KernelName,
+    /// The GOP mode to set, or None to keep the firmware's mode.
+    pub gop_mode: Option<u32>,
+    pub log_level: LogLevel,
+    /// Load a PIE kernel at a randomized address.
+    pub kaslr: bool,
+}
+
+/// Wait for the timeout, and run the menu if a key is pressed.
+/// Returns the defaults if not, or if the menu fails.
+pub fn run(
+    image_handle: Handle,
+    system_table: &mut SystemTable<Boot>,
+    root_dir: &mut Directory,
+    default_kernel: &CStr16,
+) -> MenuChoice {
+    let default = MenuChoice {
+        kernel: KernelName::new(default_kernel).unwrap(),
+        gop_mode: None,
+        log_level: LogLevel::Info,
+        kaslr: false,
+    };
+
+    if !wait_for_menu_key(system_table) { return default; }
+
+    let _ = system_table.stdout().clear();
+    writeln!(system_table.stdout(), "GYUR OS boot menu").unwrap();
+
+    // kernel images
+    let mut kernels = [default.kernel; MAX_KERNELS];
+    let kernel_count = find_kernels(root_dir, &mut kernels);
+    writeln!(system_table.stdout(), "\nKernel images:").unwrap();
+    for (i, kernel) in kernels[..kernel_count].iter().enumerate() {
+        writeln!(system_table.stdout(), "  [{}] {}", i, kernel.as_cstr16()).unwrap();
+    }
+    let kernel = kernels[select(system_table, "Kernel", kernel_count, 0)];
+
+    // GOP modes. the protocol is not opened exclusively, so that the console keeps working.
+    let gop_mode = match list_gop_modes(image_handle, system_table) {
+        Some(mode_count) if mode_count > 0 => {
+            writeln!(system_table.stdout(), "  [{}] keep the current mode", mode_count).unwrap();
+            let mode = select(system_table, "GOP mode", mode_count + 1, mode_count);
+            (mode < mode_count).then_some(mode as u32)
+        },
+        _ => None,
+    };
+
+    // log levels
+    writeln!(system_table.stdout(), "\nLog levels:").unwrap();
+    for (i, level) in LogLevel::ALL.iter().enumerate() {
+        writeln!(system_table.stdout(), "  [{}] {}", i, level.name()).unwrap();
+    }
+    let default_level = LogLevel::ALL.iter().position(|&level| level == default.log_level).unwrap();
+    let log_level = LogLevel::ALL[select(system_table, "Log level", LogLevel::ALL.len(), default_level)];
+
+    // KASLR
+    writeln!(system_table.stdout(), "\nKASLR (PIE kernels only):\n  [0] off\n  [1] on").unwrap();
+    let kaslr = select(system_table, "KASLR", 2, default.kaslr as usize) == 1;
+
+    MenuChoice { kernel, gop_mode, log_level, kaslr }
+}
+
+/// Count down the timeout. Returns true if a key is pressed.
+fn wait_for_menu_key(system_table: &mut SystemTable<Boot>) -> bool {
+    let _ = system_table.stdin().reset(false);
+    for remaining in (1..=MENU_TIMEOUT_SECS).rev() {
+        write!(system_table.stdout(), "\rPress any key for the boot menu ({}s) ", remaining).unwrap();
+        for _ in 0..100 {
+            if let Ok(Some(_)) = system_table.stdin().read_key() {
+                writeln!(system_table.stdout()).unwrap();
+                return true;
+            }
+            system_table.boot_services().stall(10_000);
+        }
+    }
+    writeln!(system_table.stdout()).unwrap();
+    false
+}
+
+/// Find `*.elf` files in the root directory, after the default one in `kernels[0]`. Returns the count.
+fn find_kernels(root_dir: &mut Directory, kernels: &mut [KernelName; MAX_KERNELS]) -> usize {
+    // `FileInfo` with the longest FAT file name.
+    const ENTRY_BUF_SIZE: usize = 640;
+    let mut entry_buf = [0u64; ENTRY_BUF_SIZE / 8]; // aligned for `FileInfo`.
+    let entry_buf = unsafe {
+        core::slice::from_raw_parts_mut(entry_buf.as_mut_ptr() as *mut u8, ENTRY_BUF_SIZE)
+    };
+
+    let mut count = 1;
+    let _ = root_dir.reset_entry_readout();
+    while count < MAX_KERNELS {
+        let Ok(Some(info)) = root_dir.read_entry(&mut *entry_buf) else { break };
+        if info.attribute().contains(FileAttribute::DIRECTORY) || !is_elf(info) { continue; }
+
+        let Some(name) = KernelName::new(info.file_name()) else { continue };
+        if kernels[..count].contains(&name) { continue; }
+        kernels[count] = name;
+        count += 1;
+    }
+    count
+}
+
+/// FAT file names are case-insensitive, so is the extension.
+fn is_elf(info: &FileInfo) -> bool {
+    let name = info.file_name().to_u16_slice();
+    let Some(ext) = name.len().checked_sub(4).map(|start| &name[start..]) else { return false };
+    ext.iter()
+        .map(|&c| if (0x41..=0x5A).contains(&c) { c + 0x20 } else { c })
+        .eq(".elf".encode_utf16())
+}
+
+/// Print the GOP modes, and returns the number of modes.
+fn list_gop_modes(image_handle: Handle, system_table: &mut SystemTable<Boot>) -> Option<usize> {
+    // stdout is borrowed mutably while the protocol is open.
+    let system_table_clone = unsafe { system_table.unsafe_clone() };
+    let boot_services = system_table_clone.boot_services();
+
+    let gop_handle = boot_services.get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    let gop = unsafe {
+        boot_services.open_protocol::<GraphicsOutput>(
+            OpenProtocolParams { handle: gop_handle, agent: image_handle, controller: None },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }.ok()?;
+
+    writeln!(system_table.stdout(), "\nGOP modes:").unwrap();
+    let current = gop.current_mode_info();
+    let mut count = 0;
+    for (i, mode) in gop.modes(boot_services).enumerate() {
+        let (width, height) = mode.info().resolution();
+        let mark = if mode.info().resolution() == current.resolution() { " (current)" } else { "" };
+        writeln!(system_table.stdout(), "  [{}] {}x{}{}", i, width, height, mark).unwrap();
+        count += 1;
+    }
+    Some(count)
+}
+
+/// Read a number in `0..count` followed by Enter. An empty input selects the default.
+fn select(system_table: &mut SystemTable<Boot>, prompt: &str, count: usize, default: usize) -> usize {
+    loop {
+        write!(system_table.stdout(), "{} [{}]: ", prompt, default).unwrap();
+
+        let mut input = 0;
+        let mut digits = 0;
+        loop {
+            let key = match system_table.stdin().read_key() {
+                Ok(Some(key)) => key,
+                _ => {
+                    system_table.boot_services().stall(10_000);
+                    continue;
+                },
+            };
+            let Key::Printable(c) = key else { continue };
+            let c = char::from(c);
+
+            if c == '\r' {
+                writeln!(system_table.stdout()).unwrap();
+                break;
+            } else if let Some(digit) = c.to_digit(10) {
+                if digits >= 4 { continue; }
+                input = input * 10 + digit as usize;
+                digits += 1;
+                write!(system_table.stdout(), "{}", c).unwrap();
+            } else if c == '\x08' && digits > 0 {
+                input /= 10;
+                digits -= 1;
+                write!(system_table.stdout(), "\x08 \x08").unwrap();
+            }
+        }
+
+        if digits == 0 { return default; }
+        if input < count { return input; }
+        writeln!(system_table.stdout(), "Out of range.").unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Kernel image validation, so that a broken kernel is reported before anything is loaded.
+
+use core::fmt;
+
+use elf::ElfBytes;
+use elf::endian::AnyEndian;
+use elf::file::Class;
+use elf::segment::ProgramHeader;
+
+use uefi::Status;
+use uefi::table::boot::PAGE_SIZE;
+
+/// Errors while loading a kernel image, printed on the UEFI console.
+#[derive(Debug)]
+pub enum BootError {
+    /// A UEFI call failed.
+    Uefi(Status),
+    /// The kernel file doesn't exist.
+    NotFound,
+    /// The kernel path is a directory.
+    NotAFile,
+    /// Failed to read the kernel file.
+    Read(Status),
+    /// The kernel file is not a valid ELF.
+    Parse(elf::ParseError),
+    /// The kernel is not a 64-bit little endian ELF.
+    NotElf64,
+    /// The kernel is not for x86-64.
+    WrongMachine(u16),
+    /// The kernel is neither an executable nor a position-independent executable.
+    NotExecutable(u16),
+    /// There are no `PT_LOAD` segments.
+    NoLoadSegments,
+    /// A `PT_LOAD` segment is not contained in the file, or larger in the file than in memory.
+    BadSegment { vaddr: u64 },
+    /// Two `PT_LOAD` segments overlap in memory.
+    OverlappingSegments { first: u64, second: u64 },
+    /// The entry point is not in any loaded segment.
+    EntryOutsideSegments(u64),
+    /// The relocation type is not supported. Only `R_X86_64_RELATIVE` is.
+    UnsupportedRelocation(u32),
+    /// A relocation, or the relocation table, is outside the kernel image.
+    BadRelocation { offset: u64 },
+    /// The pages at the kernel addresses are not available.
+    Allocate { base: u64, status: Status },
+    /// No kernel image could be loaded.
+    NoKernel,
+}
+
+impl BootError {
+    /// The status returned to the firmware.
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Uefi(status) | Self::Read(status) | Self::Allocate { status, .. } => *status,
+            Self::NotFound | Self::NoKernel => Status::NOT_FOUND,
+            _ => Status::LOAD_ERROR,
+        }
+    }
+}
+
+impl From<uefi::Error> for BootError {
+    fn from(err: uefi::Error) -> Self {
+        Self::Uefi(err.status())
+    }
+}
+
+impl From<elf::ParseError> for BootError {
+    fn from(err: elf::ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uefi(status) => write!(f, "UEFI error {:?}", status),
+            Self::NotFound => write!(f, "file not found"),
+            Self::NotAFile => write!(f, "not a regular file"),
+            Self::Read(status) => write!(f, "failed to read the file ({:?})", status),
+            Self::Parse(err) => write!(f, "malformed ELF: {}", err),
+            Self::NotElf64 => write!(f, "not a 64-bit little endian ELF"),
+            Self::WrongMachine(machine) => write!(f, "not an x86-64 executable (machine {:#x})", machine),
+            Self::NotExecutable(e_type) => write!(f, "not an executable (type {})", e_type),
+            Self::NoLoadSegments => write!(f, "no loadable segments"),
+            Self::BadSegment { vaddr } => write!(f, "segment at {:#x} exceeds the file", vaddr),
+            Self::OverlappingSegments { first, second } => {
+                write!(f, "segments at {:#x} and {:#x} overlap", first, second)
+            },
+            Self::EntryOutsideSegments(entry) => write!(f, "entry point {:#x} is not in any segment", entry),
+            Self::UnsupportedRelocation(r_type) => write!(f, "unsupported relocation type {}", r_type),
+            Self::BadRelocation { offset } => write!(f, "relocation at {:#x} is outside the image", offset),
+            Self::Allocate { base, status } => {
+                write!(f, "cannot allocate pages at {:#x} ({:?})", base, status)
+            },
+            Self::NoKernel => write!(f, "no bootable kernel image"),
+        }
+    }
+}
+
+/// The address range of a validated kernel, in link addresses.
+#[derive(Clone, Copy, Debug)]
+pub struct KernelLayout {
+    pub entry: u64,
+    /// The lowest address of the loaded segments.
+    pub base: u64,
+    /// The end of the loaded segments, e.g. including `.bss`.
+    pub bound: u64,
+    /// The largest segment alignment, which the load bias should be a multiple of.
+    pub align: u64,
+    /// True for a PIE kernel (`ET_DYN`), which can be loaded anywhere.
+    pub relocatable: bool,
+}
+
+/// `PT_LOAD` segments of the kernel.
+pub fn load_segments<'a>(elf: &'a ElfBytes<AnyEndian>) -> impl Iterator<Item = ProgramHeader> + 'a {
+    elf.segments()
+        .into_iter()
+        .flat_map(|segments| segments.iter())
+        .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+}
+
+fn end(phdr: &ProgramHeader) -> u64 {
+    phdr.p_vaddr.saturating_add(phdr.p_memsz)
+}
+
+/// Check the ELF header and the segments of the kernel, and returns its layout.
+pub fn validate(elf: &ElfBytes<AnyEndian>, file_size: usize) -> Result<KernelLayout, BootError> {
+    if elf.ehdr.class != Class::ELF64 || elf.ehdr.endianness != AnyEndian::Little {
+        return Err(BootError::NotElf64);
+    }
+    if elf.ehdr.e_machine != elf::abi::EM_X86_64 {
+        return Err(BootError::WrongMachine(elf.ehdr.e_machine));
+    }
+    let relocatable = match elf.ehdr.e_type {
+        elf::abi::ET_EXEC => false,
+        elf::abi::ET_DYN => true,
+        e_type => return Err(BootError::NotExecutable(e_type)),
+    };
+
+    let mut base = u64::MAX;
+    let mut bound = u64::MIN;
+    let mut align = PAGE_SIZE as u64;
+    for (i, phdr) in load_segments(elf).enumerate() {
+        let file_end = phdr.p_offset.checked_add(phdr.p_filesz);
+        if file_end.map_or(true, |file_end| file_end > file_size as u64) || phdr.p_filesz > phdr.p_memsz {
+            return Err(BootError::BadSegment { vaddr: phdr.p_vaddr });
+        }
+
+        // there are only a few segments, so compare all pairs.
+        if let Some(other) = load_segments(elf)
+            .skip(i + 1)
+            .find(|other| other.p_vaddr < end(&phdr) && phdr.p_vaddr < end(other))
+        {
+            return Err(BootError::OverlappingSegments { first: phdr.p_vaddr, second: other.p_vaddr });
+        }
+
+        base = base.min(phdr.p_vaddr);
+        bound = bound.max(end(&phdr));
+        if phdr.p_align.is_power_of_two() { align = align.max(phdr.p_align); }
+    }
+    if base >= bound {
+        return Err(BootError::NoLoadSegments);
+    }
+
+    let entry = elf.ehdr.e_entry;
+    if !load_segments(elf).any(|phdr| phdr.p_vaddr <= entry && entry < end(&phdr)) {
+        return Err(BootError::EntryOutsideSegments(entry));
+    }
+
+    Ok(KernelLayout { entry, base, bound, align, relocatable })
+}
+
+/// `DT_RELR`, packed relative relocations, which are not supported.
+const DT_RELR: i64 = 36;
+const DT_RELRSZ: i64 = 35;
+
+/// The size of `Elf64_Rela`.
+const RELA_SIZE: usize = 24;
+
+/// A `R_X86_64_RELATIVE` relocation: the word at `offset` (a link address) becomes `bias + addend`.
+#[derive(Clone, Copy, Debug)]
+pub struct Relocation {
+    pub offset: u64,
+    pub addend: i64,
+}
+
+/// The relocation table of the kernel, in the file.
+#[derive(Clone, Copy, Debug)]
+pub struct Relocations<'a> {
+    table: &'a [u8],
+}
+
+impl<'a> Relocations<'a> {
+    /// Relative relocations. Others are `R_X86_64_NONE`, which are checked in `relocations`.
+    pub fn iter(&self) -> impl Iterator<Item = Relocation> + 'a {
+        self.table.chunks_exact(RELA_SIZE).filter_map(|rela| {
+            let word = |i: usize| u64::from_le_bytes(rela[8 * i..8 * (i + 1)].try_into().unwrap());
+            (word(1) as u32 == elf::abi::R_X86_64_RELATIVE).then(|| Relocation {
+                offset: word(0),
+                addend: word(2) as i64,
+            })
+        })
+    }
+}
+
+/// The file offset of a link address, if it is in the file part of a segment.
+fn file_offset(elf: &ElfBytes<AnyEndian>, vaddr: u64) -> Option<usize> {
+    load_segments(elf)
+        .find(|phdr| phdr.p_vaddr <= vaddr && vaddr < phdr.p_vaddr + phdr.p_filesz)
+        .map(|phdr| (phdr.p_offset + (vaddr - phdr.p_vaddr)) as usize)
+}
+
+/// Find the relocation table in the dynamic section, and check that every relocation can be applied.
+/// Empty if the kernel has no dynamic section.
+pub fn relocations<'a>(
+    elf: &ElfBytes<'a, AnyEndian>,
+    data: &'a [u8],
+    layout: &KernelLayout,
+) -> Result<Relocations<'a>, BootError> {
+    let Some(dynamic) = elf.dynamic()? else { return Ok(Relocations { table: &[] }) };
+
+    let (mut rela, mut rela_size, mut rela_ent) = (0, 0, RELA_SIZE as u64);
+    for entry in dynamic.iter() {
+        match entry.d_tag {
+            elf::abi::DT_RELA => rela = entry.d_ptr(),
+            elf::abi::DT_RELASZ => rela_size = entry.d_val(),
+            elf::abi::DT_RELAENT => rela_ent = entry.d_val(),
+            // the linker emits these with `-z pack-relative-relocs`, or for 32-bit relocations.
+            DT_RELR | DT_RELRSZ | elf::abi::DT_REL if entry.d_val() != 0 => {
+                return Err(BootError::UnsupportedRelocation(entry.d_tag as u32));
+            },
+            _ => {},
+        }
+    }
+    if rela_size == 0 { return Ok(Relocations { table: &[] }); }
+    if rela_ent != RELA_SIZE as u64 { return Err(BootError::BadRelocation { offset: rela }); }
+
+    let table = file_offset(elf, rela)
+        .and_then(|start| data.get(start..start.checked_add(rela_size as usize)?))
+        .ok_or(BootError::BadRelocation { offset: rela })?;
+
+    for rela in table.chunks_exact(RELA_SIZE) {
+        let offset = u64::from_le_bytes(rela[0..8].try_into().unwrap());
+        let r_type = u64::from_le_bytes(rela[8..16].try_into().unwrap()) as u32;
+        match r_type {
+            elf::abi::R_X86_64_NONE => {},
+            elf::abi::R_X86_64_RELATIVE => {
+                if offset < layout.base || offset.saturating_add(8) > layout.bound {
+                    return Err(BootError::BadRelocation { offset });
+                }
+            },
+            _ => return Err(BootError::UnsupportedRelocation(r_type)),
+        }
+    }
+    Ok(Relocations { table })
+}
@@ -2,6 +2,9 @@
 
 // pub use shared::uefi;
 
+pub mod boot_menu;
+pub mod kernel_image;
+
 // copy implementation from the answer of:
 // https://stackoverflow.com/questions/50200268/how-can-i-use-the-format-macro-in-a-no-std-environment
 
@@ -3,7 +3,7 @@
 // #![feature(never_type)]
 // #![feature(abi_efiapi)]
 
-use shared::KernelArgs;
+use shared::{BootOptions, Initrd, KernelArgs, KernelImage, KernelSymbols};
 use shared::uefi_memory::{
     MemoryMap,
     MemoryType,
@@ -14,6 +14,8 @@ use uefi::{
     // self,
     prelude::*,
     data_types::{Char16, CStr16},
+    table::boot::BootServices,
+    table::cfg::{ACPI2_GUID, ACPI_GUID},
     proto::{
         loaded_image::LoadedImage,
         device_path::DevicePath,
@@ -25,6 +27,9 @@ use uefi::{
     cstr16,
 };
 
+use bootloader::boot_menu::{self, KernelName};
+use bootloader::kernel_image::{self, BootError};
+
 use elf::ElfBytes;
 use elf::endian::AnyEndian;
 
@@ -35,9 +40,289 @@ use core::fmt::Write;
 
 // use bootloader::ArrayWriter;
 
+/// Copy a section of the kernel file into loader data pages, which are kept after exiting boot services.
+fn copy_section(
+    boot_services: &BootServices,
+    elf: &ElfBytes<AnyEndian>,
+    name: &str,
+) -> uefi::Result<Option<(*const u8, usize)>> {
+    let Some(shdr) = elf.section_header_by_name(name).ok().flatten() else { return Ok(None) };
+    let Ok((data, None)) = elf.section_data(&shdr) else { return Ok(None) };
+
+    let ptr = boot_services.allocate_pool(MemoryType::LOADER_DATA, data.len())?;
+    unsafe {
+        core::ptr::copy(data.as_ptr(), ptr, data.len());
+    }
+    Ok(Some((ptr as *const u8, data.len())))
+}
+
+/// Kernel images to try, in order, after the one chosen in the boot menu.
+/// The old kernel is a fallback when the new one is broken.
+const KERNEL_FILE_NAMES: [&CStr16; 2] = [cstr16!("kernel.elf"), cstr16!("kernel.old.elf")];
+
+/// The initial ramdisk, which is optional.
+const INITRD_FILE_NAME: &CStr16 = cstr16!("initrd.tar");
+
+/// The longest file name, for the file info buffer.
+const MAX_FILE_NAME_LEN: usize = 32;
+
+/// A temporary pool buffer, which is freed on drop.
+struct PoolBuffer<'a> {
+    boot_services: &'a BootServices,
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl<'a> PoolBuffer<'a> {
+    fn new(boot_services: &'a BootServices, len: usize) -> uefi::Result<Self> {
+        let ptr = boot_services.allocate_pool(MemoryType::LOADER_DATA, len)?;
+        Ok(Self { boot_services, ptr, len })
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe{ from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for PoolBuffer<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.boot_services.free_pool(self.ptr);
+        }
+    }
+}
+
+struct LoadedKernel {
+    entry_addr: u64,
+    image: KernelImage,
+    symbols: KernelSymbols,
+}
+
+fn open_file(root_dir: &mut Directory, file_name: &CStr16) -> Result<RegularFile, BootError> {
+    root_dir
+        .open(file_name, FileMode::Read, FileAttribute::empty())
+        .map_err(|err| match err.status() {
+            Status::NOT_FOUND => BootError::NotFound,
+            status => BootError::Uefi(status),
+        })?
+        .into_regular_file().ok_or(BootError::NotAFile)
+}
+
+fn file_size(file: &mut RegularFile) -> Result<usize, BootError> {
+    const FILE_INFO_BUF_SIZE: usize = 3 * size_of::<u64>() + 3 * size_of::<uefi::table::runtime::Time>() + size_of::<FileAttribute>() + (MAX_FILE_NAME_LEN + 5) * size_of::<Char16>(); //5 is for extra padding
+    // attempted `const BUF_SIZE = size_of::<FileInfo>() + KERNEL_FILE_NAME_LEN * size_of::<Char16>();` but unfortunately `FileInfo` is not sized..
+
+    let mut file_info_buffer = [0u8; FILE_INFO_BUF_SIZE];
+    let file_info = file.get_info::<FileInfo>(&mut file_info_buffer)
+        .map_err(|err| err.to_err_without_payload() )?;
+
+    Ok(file_info.file_size() as usize)
+}
+
+/// Read, validate, load and relocate a kernel image.
+/// Nothing is allocated at the kernel addresses unless the image is valid, so that another image can be tried on failure.
+fn load_kernel(
+    boot_services: &BootServices,
+    root_dir: &mut Directory,
+    file_name: &CStr16,
+    kaslr: bool,
+) -> Result<LoadedKernel, BootError> {
+    // read kernel file
+    // relavent `uefi::fs::FileSystem` method: `root_fs.metadata(...)` and `root_fs.read(...)` which returns a vector result.
+    let mut kernel_file = open_file(root_dir, file_name)?;
+
+    // writeln!(system_table.stdout(), "Kernel file read success");
+
+    // retrieve kernel info and allocate page
+    let kernel_file_size = file_size(&mut kernel_file)?;
+
+    // read the kernel and load into temporarily allocated area, which is freed when this function returns.
+    let mut kernel_buffer = PoolBuffer::new(boot_services, kernel_file_size)?;
+    let kernel_buffer_slice = kernel_buffer.as_mut_slice();
+    let read_size = kernel_file.read(kernel_buffer_slice)
+        .map_err(|err| BootError::Read(err.status()))?;
+    if read_size != kernel_file_size {
+        return Err(BootError::Read(Status::END_OF_FILE));
+    }
+    let kernel_bytes: &[u8] = kernel_buffer_slice;
+
+    // parse and validate the kernel.
+    let elf = ElfBytes::<AnyEndian>::minimal_parse(kernel_bytes)?;
+    // by loading each segment manually, 0x1000 displacement bug is resolved.
+    let layout = kernel_image::validate(&elf, kernel_file_size)?;
+    let relocations = kernel_image::relocations(&elf, kernel_bytes, &layout)?;
+
+    // allocate real pages.
+    // a non-relocatable kernel is loaded at its link address (the address specified as `--image-base` linker option),
+    // and a PIE kernel is loaded at any free range, shifted by the load bias.
+    // the bound includes e.g. .bss section.
+    let image_start = layout.base & !(PAGE_SIZE as u64 - 1);
+    let page_count = ((layout.bound - image_start) as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+    let load_start = if layout.relocatable {
+        allocate_anywhere(boot_services, page_count, layout.align, kaslr)?
+    } else {
+        boot_services.allocate_pages(
+            AllocateType::Address(image_start),
+            MemoryType::LOADER_DATA,
+            page_count
+        ).map_err(|err| BootError::Allocate { base: image_start, status: err.status() })?
+    };
+    let bias = load_start.wrapping_sub(image_start);
+
+    // copy segment data into real target, and fill zero if necessary
+    for phdr in kernel_image::load_segments(&elf) {
+        let dest = phdr.p_vaddr.wrapping_add(bias) as *mut u8;
+        unsafe{
+            core::ptr::copy(
+                kernel_bytes.as_ptr().add(phdr.p_offset as usize),
+                dest,
+                phdr.p_filesz as usize
+            );
+            core::ptr::write_bytes(
+                dest.add(phdr.p_filesz as usize),
+                0,
+                (phdr.p_memsz - phdr.p_filesz) as usize
+            );
+        }
+    }
+
+    // relocate. the relocations are checked to be inside the image.
+    for relocation in relocations.iter() {
+        unsafe {
+            core::ptr::write_unaligned(
+                relocation.offset.wrapping_add(bias) as *mut u64,
+                bias.wrapping_add(relocation.addend as u64)
+            );
+        }
+    }
+
+    // keep the symbol table for kernel backtraces. the kernel still boots without it.
+    let symbols = match (
+        copy_section(boot_services, &elf, ".symtab").unwrap_or(None),
+        copy_section(boot_services, &elf, ".strtab").unwrap_or(None),
+    ) {
+        (Some((symtab, symtab_size)), Some((strtab, strtab_size))) => KernelSymbols {
+            symtab,
+            symtab_size,
+            strtab,
+            strtab_size,
+        },
+        _ => KernelSymbols::empty(),
+    };
+
+    Ok(LoadedKernel {
+        entry_addr: layout.entry.wrapping_add(bias),
+        image: KernelImage {
+            base: layout.base.wrapping_add(bias),
+            size: layout.bound - layout.base,
+            bias,
+        },
+        symbols,
+    })
+}
+
+/// PIE kernels are loaded below 4GB, where the kernel identity-maps.
+const KERNEL_MAX_ADDR: u64 = 0xFFFF_FFFF;
+
+/// The range and the granularity of randomized kernel addresses.
+const KASLR_MIN_ADDR: u64 = 0x100_0000; // 16MB
+const KASLR_ALIGN: u64 = 0x20_0000; // 2MB
+const KASLR_ATTEMPTS: usize = 64;
+
+/// A random number from RDRAND if available, or from the TSC.
+fn random_u64() -> u64 {
+    use core::arch::x86_64::{__cpuid, _rdtsc};
+
+    #[target_feature(enable = "rdrand")]
+    unsafe fn rdrand() -> Option<u64> {
+        let mut value = 0;
+        // RDRAND may fail transiently.
+        (0..10).find(|_| core::arch::x86_64::_rdrand64_step(&mut value) == 1).map(|_| value)
+    }
+
+    let has_rdrand = unsafe { __cpuid(1) }.ecx & (1 << 30) != 0;
+    if has_rdrand {
+        if let Some(value) = unsafe { rdrand() } { return value; }
+    }
+    // mix the TSC, whose low bits are the most random.
+    let tsc = unsafe { _rdtsc() };
+    tsc.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(32)
+}
+
+/// Allocate pages for a PIE kernel, aligned to `align`, below `KERNEL_MAX_ADDR`.
+/// With KASLR, the address is randomized. Returns the start address.
+fn allocate_anywhere(boot_services: &BootServices, page_count: usize, align: u64, kaslr: bool) -> Result<u64, BootError> {
+    let size = (page_count * PAGE_SIZE) as u64;
+
+    let kaslr_align = align.max(KASLR_ALIGN);
+    let slot_count = (KERNEL_MAX_ADDR + 1 - KASLR_MIN_ADDR).saturating_sub(size) / kaslr_align;
+    if kaslr && slot_count > 0 {
+        for _ in 0..KASLR_ATTEMPTS {
+            let start = KASLR_MIN_ADDR + random_u64() % slot_count * kaslr_align;
+            if boot_services.allocate_pages(AllocateType::Address(start), MemoryType::LOADER_DATA, page_count).is_ok() {
+                return Ok(start);
+            }
+        }
+        // fall back to any free range.
+    }
+
+    // allocate extra pages for the alignment. the unused head is left allocated.
+    let extra_page_count = (align as usize - PAGE_SIZE) / PAGE_SIZE;
+    let start = boot_services.allocate_pages(
+        AllocateType::MaxAddress(KERNEL_MAX_ADDR),
+        MemoryType::LOADER_DATA,
+        page_count + extra_page_count
+    ).map_err(|err| BootError::Allocate { base: 0, status: err.status() })?;
+    Ok((start + align - 1) & !(align - 1))
+}
+
+/// Read the initial ramdisk into loader data pages, which are kept after exiting boot services.
+fn load_initrd(boot_services: &BootServices, root_dir: &mut Directory) -> Result<Initrd, BootError> {
+    let mut initrd_file = open_file(root_dir, INITRD_FILE_NAME)?;
+    let initrd_size = file_size(&mut initrd_file)?;
+    if initrd_size == 0 { return Ok(Initrd::empty()); }
+
+    let page_count = (initrd_size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let initrd_base = boot_services.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count)?;
+    let initrd_slice = unsafe{ from_raw_parts_mut(initrd_base as *mut u8, initrd_size) };
+
+    let read_result = match initrd_file.read(initrd_slice) {
+        Ok(read_size) if read_size == initrd_size => Ok(()),
+        Ok(_) => Err(BootError::Read(Status::END_OF_FILE)),
+        Err(err) => Err(BootError::Read(err.status())),
+    };
+    if let Err(err) = read_result {
+        unsafe {
+            let _ = boot_services.free_pages(initrd_base, page_count);
+        }
+        return Err(err);
+    }
+
+    Ok(Initrd {
+        base: initrd_base as *const u8,
+        size: initrd_size,
+    })
+}
+
+/// The ACPI RSDP from the configuration table, preferring ACPI 2.0 or later. Zero if not found.
+fn find_rsdp(system_table: &SystemTable<Boot>) -> u64 {
+    let find = |guid| system_table.config_table().iter()
+        .find(|entry| entry.guid == guid)
+        .map(|entry| entry.address as u64);
+    find(ACPI2_GUID).or_else(|| find(ACPI_GUID)).unwrap_or(0)
+}
+
+/// Wait for a keypress, ignoring keys pressed before.
+fn wait_for_key(system_table: &mut SystemTable<Boot>) {
+    let _ = system_table.stdin().reset(false);
+    while let Ok(None) = system_table.stdin().read_key() {
+        system_table.boot_services().stall(10_000);
+    }
+}
+
 #[inline]
 fn uefi_boot(image_handle: Handle, system_table: &mut SystemTable<Boot>)
--> uefi::Result<(extern "sysv64" fn(MemoryMap<'static>, KernelArgs), KernelArgs)>
+-> Result<(extern "sysv64" fn(MemoryMap<'static>, KernelArgs), KernelArgs), BootError>
 {
     uefi_services::init(system_table)?;
 
@@ -62,92 +347,62 @@ fn uefi_boot(image_handle: Handle, system_table: &mut SystemTable<Boot>)
             .open_volume()?
     };
 
-    // read kernel file
-    // relavent `uefi::fs::FileSystem` method: `root_fs.metadata(...)` and `root_fs.read(...)` which returns a vector result.
-    const KERNEL_FILE_NAME: &CStr16 = cstr16!("kernel.elf");
-
-    let mut kernel_file = root_dir
-        .open(KERNEL_FILE_NAME, FileMode::Read, FileAttribute::empty())?
-        .into_regular_file().unwrap();
-
-    // writeln!(system_table.stdout(), "Kernel file read success");
-
-    // retrieve kernel info and allocate page
-    
-    let kernel_file_size = {
-        const KERNEL_FILE_NAME_LEN: usize = KERNEL_FILE_NAME.to_u16_slice_with_nul().len();
-        const KERNEL_FILE_INFO_BUF_SIZE: usize = 3 * size_of::<u64>() + 3 * size_of::<uefi::table::runtime::Time>() + size_of::<FileAttribute>() + (KERNEL_FILE_NAME_LEN + 5) * size_of::<Char16>(); //5 is for extra padding
-        // attempted `const BUF_SIZE = size_of::<FileInfo>() + KERNEL_FILE_NAME_LEN * size_of::<Char16>();` but unfortunately `FileInfo` is not sized..
-
-        let mut kernel_file_info_buffer = [0u8; KERNEL_FILE_INFO_BUF_SIZE];
-        let kernel_file_info = kernel_file.get_info::<FileInfo>(&mut kernel_file_info_buffer)
-            .map_err(|err| err.to_err_without_payload() )?;
+    // let the user choose the kernel and options, if requested.
+    let menu_choice = boot_menu::run(image_handle, system_table, &mut root_dir, KERNEL_FILE_NAMES[0]);
 
-        kernel_file_info.file_size() as usize
-    };
+    // the chosen kernel comes first, and the rest are fallbacks. don't try the same file twice.
+    let mut candidates = [
+        Some(menu_choice.kernel),
+        KernelName::new(KERNEL_FILE_NAMES[0]),
+        KernelName::new(KERNEL_FILE_NAMES[1]),
+    ];
+    for i in 1..candidates.len() {
+        if candidates[..i].contains(&candidates[i]) { candidates[i] = None; }
+    }
 
-    // refering elf program headers, determine kernel base and bound addresses.
-    // load all segments and determine the kernel entry point address.
-    let kernel_entry_addr = {
-        // read the kernel and load into temporarily allocated area
-        let kernel_buffer_ptr = system_table.boot_services().allocate_pool(
-            MemoryType::LOADER_DATA,
-            kernel_file_size
-        )?;
-        let kernel_buffer_slice = unsafe{ from_raw_parts_mut(kernel_buffer_ptr as *mut u8, kernel_file_size) };
-        kernel_file.read(kernel_buffer_slice)?;
-
-        // parse the kernel.
-        let elf = ElfBytes::<AnyEndian>::minimal_parse(kernel_buffer_slice).unwrap();
-        // we can easily get entry address by peeking file header.
-        // but we have to load all segments (and free the pool) before exiting this block
-        // by loading each segment manually, 0x1000 displacement bug is resolved.
-        let kernel_entry_addr = elf.ehdr.e_entry;
-
-        // determine base and bound addresses.
-        let mut kernel_base_addr = u64::MAX; // after the iteration, this should be 0x200000 (or the address specified as `--image-base` linker option, in target json config)
-        let mut kernel_bound_addr = u64::MIN; // naively this is kernel_base_addr + kernel_file_size, but we may have e.g. .bss section.
-        for phdr in elf.segments().unwrap().iter() {
-            if phdr.p_type != elf::abi::PT_LOAD { continue; }
-            // honestly, we want methods for 'assign if greater' and 'assign if less'.
-            kernel_base_addr = kernel_base_addr.min(phdr.p_vaddr);
-            kernel_bound_addr = kernel_bound_addr.max(phdr.p_vaddr + phdr.p_memsz);
+    // refering elf program headers, load all segments and determine the kernel entry point address.
+    // if the kernel is broken, fall back to the next one.
+    let mut loaded_kernel = None;
+    let mut candidates = candidates.iter().flatten().peekable();
+    while let Some(file_name) = candidates.next() {
+        match load_kernel(system_table.boot_services(), &mut root_dir, file_name.as_cstr16(), menu_choice.kaslr) {
+            Ok(kernel) => {
+                loaded_kernel = Some((kernel, *file_name));
+                break;
+            },
+            Err(err) => {
+                writeln!(system_table.stderr(), "Failed to load {}: {}", file_name.as_cstr16(), err).unwrap();
+                // only offer the fallbacks which are on the disk.
+                while candidates.next_if(|next| open_file(&mut root_dir, next.as_cstr16()).is_err()).is_some() {}
+                if let Some(next_file_name) = candidates.peek() {
+                    writeln!(system_table.stdout(), "Press any key to boot {} instead.", next_file_name.as_cstr16()).unwrap();
+                    wait_for_key(system_table);
+                }
+            },
         }
+    }
+    let (LoadedKernel {
+        entry_addr: kernel_entry_addr,
+        image: kernel_image_info,
+        symbols: kernel_symbols,
+    }, kernel_name) = loaded_kernel.ok_or(BootError::NoKernel)?;
 
-        // allocate real pages
-        let kernel_byte_count = (kernel_bound_addr - kernel_base_addr) as usize;
-        system_table.boot_services().allocate_pages(
-            AllocateType::Address(kernel_base_addr),
-            MemoryType::LOADER_DATA,
-            (kernel_byte_count + PAGE_SIZE - 1) / PAGE_SIZE
-        )?;
-
-        // copy segment data into real target, and fill zero if necessary
-        for phdr in elf.segments().unwrap().iter() {
-            if phdr.p_type != elf::abi::PT_LOAD { continue; }
-
-            unsafe{
-                core::ptr::copy(
-                    kernel_buffer_ptr.add(phdr.p_offset as usize),
-                    phdr.p_vaddr as *mut u8,
-                    phdr.p_filesz as usize
-                );
-                core::ptr::write_bytes(
-                    (phdr.p_vaddr as *mut u8).add(phdr.p_filesz as usize),
-                    0,
-                    phdr.p_memsz.saturating_sub(phdr.p_filesz) as usize
-                );
-            }
-        }
+    // the kernel boots without the initrd, with an empty root filesystem.
+    let initrd = match load_initrd(system_table.boot_services(), &mut root_dir) {
+        Ok(initrd) => initrd,
+        Err(BootError::NotFound) => Initrd::empty(),
+        Err(err) => {
+            writeln!(system_table.stderr(), "Failed to load {}: {}", INITRD_FILE_NAME, err).unwrap();
+            Initrd::empty()
+        },
+    };
 
-        // abandon the temp buffer
-        // can we make this auto-drop?
-        unsafe {
-            system_table.boot_services().free_pool(kernel_buffer_ptr)?;
-        }
+    let rsdp = find_rsdp(system_table);
 
-        kernel_entry_addr
-    };
+    let mut options = BootOptions::new();
+    options.kernel_name_len = kernel_name.to_ascii(&mut options.kernel_name);
+    options.log_level = menu_choice.log_level;
+    options.kaslr = menu_choice.kaslr && kernel_image_info.bias != 0;
 
     writeln!(system_table.stdout(), "Executing kernel (Entry {:p})", kernel_entry_addr as *const ()).unwrap();
 
@@ -158,9 +413,18 @@ fn uefi_boot(image_handle: Handle, system_table: &mut SystemTable<Boot>)
         let gop_handle = system_table.boot_services().get_handle_for_protocol::<GraphicsOutput>()?;
         let mut gop = system_table.boot_services().open_protocol_exclusive::<GraphicsOutput>(gop_handle)?;
 
-        // can select other GOP modes (iterate by `gop.modes()`)
-        // but we will choose the default selected mode.
+        // other GOP modes can be selected in the boot menu, otherwise we keep the default selected mode.
         // @TODO: can we set mode at runtime(by kernel)?
+        if let Some(mode_number) = menu_choice.gop_mode {
+            let mode = gop.modes(system_table.boot_services()).nth(mode_number as usize);
+            if let Some(mode) = mode {
+                // on failure, the current mode is kept.
+                if gop.set_mode(&mode).is_ok() {
+                    options.gop_mode = Some(mode_number);
+                }
+            }
+        }
+
         (
             // gop.frame_buffer(),
             unsafe { core::mem::transmute(
@@ -182,6 +446,11 @@ fn uefi_boot(image_handle: Handle, system_table: &mut SystemTable<Boot>)
     let args = KernelArgs {
         gop_frame_buffer,
         gop_mode_info,
+        image: kernel_image_info,
+        symbols: kernel_symbols,
+        initrd,
+        rsdp,
+        options,
     };
 
     Ok((kernel_entry, args))
@@ -204,7 +473,9 @@ fn uefi_start(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Stat
             Status::SUCCESS
         },
         Err(err) => {
-            writeln!(system_table.stderr(), "{:?}", err).unwrap();
+            writeln!(system_table.stderr(), "Boot failed: {}", err).unwrap();
+            writeln!(system_table.stdout(), "Press any key to exit.").unwrap();
+            wait_for_key(&mut system_table);
             err.status()
         },
     }
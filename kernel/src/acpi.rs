@@ -0,0 +1,747 @@
+//! ACPI table discovery and parsing.
+//!
+//! Tables are read in place through the identity mapping, and every table is checksummed before use.
+//! https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html
+
+use core::fmt;
+
+use x86_64::instructions::port::Port;
+
+/// The size of the common table header.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The RSDP or a table has a wrong signature.
+    BadSignature,
+    /// The bytes of a structure don't sum to zero.
+    BadChecksum([u8; 4]),
+    /// A table is shorter than its fixed fields, or its length field.
+    BadLength([u8; 4]),
+}
+
+/// Sum of bytes, which is zero for a valid structure.
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// The root system description pointer, passed by the bootloader.
+#[derive(Clone, Copy, Debug)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub rsdt_address: u32,
+    /// Zero for ACPI 1.0.
+    pub xsdt_address: u64,
+}
+
+impl Rsdp {
+    const SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+    const V1_SIZE: usize = 20;
+    const V2_SIZE: usize = 36;
+
+    /// Parse and checksum the RSDP. `bytes` should be at least 36 bytes for ACPI 2.0 or later.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let v1 = bytes.get(..Self::V1_SIZE).ok_or(AcpiError::BadLength(*b"RSDP"))?;
+        if &v1[..8] != Self::SIGNATURE { return Err(AcpiError::BadSignature); }
+        if checksum(v1) != 0 { return Err(AcpiError::BadChecksum(*b"RSDP")); }
+
+        let revision = v1[15];
+        let xsdt_address = if revision >= 2 {
+            let v2 = bytes.get(..Self::V2_SIZE).ok_or(AcpiError::BadLength(*b"RSDP"))?;
+            if checksum(v2) != 0 { return Err(AcpiError::BadChecksum(*b"RSDP")); }
+            u64_at(v2, 24)
+        } else {
+            0
+        };
+
+        Ok(Self {
+            revision,
+            oem_id: v1[9..15].try_into().unwrap(),
+            rsdt_address: u32_at(v1, 16),
+            xsdt_address,
+        })
+    }
+
+    /// Parse the RSDP at a physical address.
+    ///
+    /// # Safety
+    /// The address should be the RSDP from the firmware, and identity-mapped.
+    pub unsafe fn from_phys(addr: u64) -> Result<Self, AcpiError> {
+        let v1 = core::slice::from_raw_parts(addr as *const u8, Self::V1_SIZE);
+        let size = if v1[15] >= 2 { Self::V2_SIZE } else { Self::V1_SIZE };
+        Self::parse(core::slice::from_raw_parts(addr as *const u8, size))
+    }
+}
+
+/// A system description table: the common header, followed by the table body.
+#[derive(Clone, Copy)]
+pub struct Sdt<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Sdt<'a> {
+    /// Check the length and the checksum of the table.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let signature = bytes.get(..4).map_or(*b"????", |sig| sig.try_into().unwrap());
+        if bytes.len() < SDT_HEADER_SIZE { return Err(AcpiError::BadLength(signature)); }
+
+        let length = u32_at(bytes, 4) as usize;
+        let bytes = bytes.get(..length).filter(|_| length >= SDT_HEADER_SIZE)
+            .ok_or(AcpiError::BadLength(signature))?;
+        if checksum(bytes) != 0 { return Err(AcpiError::BadChecksum(signature)); }
+
+        Ok(Self { bytes })
+    }
+
+    /// The table at a physical address.
+    ///
+    /// # Safety
+    /// The address should be of an ACPI table, and identity-mapped. The table should never be freed.
+    pub unsafe fn from_phys(addr: u64) -> Result<Sdt<'static>, AcpiError> {
+        let header = core::slice::from_raw_parts(addr as *const u8, SDT_HEADER_SIZE);
+        let length = (u32_at(header, 4) as usize).max(SDT_HEADER_SIZE);
+        Sdt::new(core::slice::from_raw_parts(addr as *const u8, length))
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        self.bytes[..4].try_into().unwrap()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.bytes[8]
+    }
+
+    pub fn oem_id(&self) -> [u8; 6] {
+        self.bytes[10..16].try_into().unwrap()
+    }
+
+    /// The whole table, including the header.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// The table after the header.
+    pub fn body(&self) -> &'a [u8] {
+        &self.bytes[SDT_HEADER_SIZE..]
+    }
+
+    /// The body, if it has at least `len` bytes of fixed fields.
+    fn body_with(&self, len: usize) -> Result<&'a [u8], AcpiError> {
+        Some(self.body()).filter(|body| body.len() >= len).ok_or(AcpiError::BadLength(self.signature()))
+    }
+}
+
+impl fmt::Debug for Sdt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sdt")
+            .field("signature", &Signature(self.signature()))
+            .field("length", &self.bytes.len())
+            .field("revision", &self.revision())
+            .finish()
+    }
+}
+
+/// A printable table signature.
+pub struct Signature(pub [u8; 4]);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &b in self.0.iter() {
+            let c = if b.is_ascii_graphic() { b as char } else { '?' };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// The root table (XSDT, or RSDT for ACPI 1.0), which lists all other tables.
+#[derive(Clone, Copy, Debug)]
+pub struct RootTable<'a> {
+    sdt: Sdt<'a>,
+    /// 8 for XSDT entries, 4 for RSDT entries.
+    entry_size: usize,
+}
+
+impl<'a> RootTable<'a> {
+    pub fn new(sdt: Sdt<'a>) -> Result<Self, AcpiError> {
+        let entry_size = match &sdt.signature() {
+            b"XSDT" => 8,
+            b"RSDT" => 4,
+            _ => return Err(AcpiError::BadSignature),
+        };
+        Ok(Self { sdt, entry_size })
+    }
+
+    /// Physical addresses of the tables.
+    pub fn entries(&self) -> impl Iterator<Item = u64> + 'a {
+        let entry_size = self.entry_size;
+        self.sdt.body().chunks_exact(entry_size).map(move |entry| match entry_size {
+            8 => u64_at(entry, 0),
+            _ => u32_at(entry, 0) as u64,
+        })
+    }
+}
+
+/// A register location in the Generic Address Structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 for system memory, 1 for system I/O, 2 for the PCI configuration space.
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+    pub const PCI_CONFIG: u8 = 2;
+    const SIZE: usize = 12;
+
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            address_space: bytes[0],
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address: u64_at(bytes, 4),
+        }
+    }
+
+    /// The I/O port, if this is in the system I/O space.
+    pub fn io_port(&self) -> Option<u16> {
+        (self.address_space == Self::SYSTEM_IO && self.address != 0).then_some(self.address as u16)
+    }
+}
+
+/// An entry of the MADT interrupt controller structures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic { processor_id: u8, apic_id: u8, flags: u32 },
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    /// An ISA IRQ connected to a different GSI, or with a different polarity and trigger mode.
+    InterruptSourceOverride { bus: u8, source: u8, gsi: u32, flags: u16 },
+    LocalApicNmi { processor_id: u8, flags: u16, lint: u8 },
+    LocalApicAddressOverride { address: u64 },
+    LocalX2Apic { x2apic_id: u32, flags: u32, processor_uid: u32 },
+    Other { entry_type: u8 },
+}
+
+impl MadtEntry {
+    /// The processor is enabled, or it can be enabled. (`Enabled` or `Online Capable`)
+    pub const fn is_usable_cpu(flags: u32) -> bool {
+        flags & 0b11 != 0
+    }
+}
+
+/// Multiple APIC Description Table.
+#[derive(Clone, Copy, Debug)]
+pub struct Madt<'a> {
+    pub local_apic_address: u32,
+    /// Bit 0 is set if the legacy 8259 PICs are installed.
+    pub flags: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> Madt<'a> {
+    pub const SIGNATURE: [u8; 4] = *b"APIC";
+    /// The legacy 8259 PICs are installed, and should be masked if APICs are used.
+    pub const PCAT_COMPAT: u32 = 1;
+
+    pub fn parse(sdt: Sdt<'a>) -> Result<Self, AcpiError> {
+        let body = sdt.body_with(8)?;
+        Ok(Self {
+            local_apic_address: u32_at(body, 0),
+            flags: u32_at(body, 4),
+            entries: &body[8..],
+        })
+    }
+
+    /// The interrupt controller structures. A malformed entry ends the iteration.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + 'a {
+        let mut rest = self.entries;
+        core::iter::from_fn(move || {
+            let &[entry_type, length, ..] = rest else { return None };
+            let entry = rest.get(..length as usize).filter(|_| length >= 2)?;
+            rest = &rest[length as usize..];
+
+            let field = |range: core::ops::Range<usize>| entry.get(range);
+            Some(match entry_type {
+                0 => MadtEntry::LocalApic {
+                    processor_id: *entry.get(2)?,
+                    apic_id: *entry.get(3)?,
+                    flags: u32_at(field(4..8)?, 0),
+                },
+                1 => MadtEntry::IoApic {
+                    id: *entry.get(2)?,
+                    address: u32_at(field(4..8)?, 0),
+                    gsi_base: u32_at(field(8..12)?, 0),
+                },
+                2 => MadtEntry::InterruptSourceOverride {
+                    bus: *entry.get(2)?,
+                    source: *entry.get(3)?,
+                    gsi: u32_at(field(4..8)?, 0),
+                    flags: u16_at(field(8..10)?, 0),
+                },
+                4 => MadtEntry::LocalApicNmi {
+                    processor_id: *entry.get(2)?,
+                    flags: u16_at(field(3..5)?, 0),
+                    lint: *entry.get(5)?,
+                },
+                5 => MadtEntry::LocalApicAddressOverride {
+                    address: u64_at(field(4..12)?, 0),
+                },
+                9 => MadtEntry::LocalX2Apic {
+                    x2apic_id: u32_at(field(4..8)?, 0),
+                    flags: u32_at(field(8..12)?, 0),
+                    processor_uid: u32_at(field(12..16)?, 0),
+                },
+                _ => MadtEntry::Other { entry_type },
+            })
+        })
+    }
+
+    /// The local APIC address, with the 64-bit override if any.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    /// APIC IDs of usable processors.
+    pub fn cpu_apic_ids(&self) -> impl Iterator<Item = u32> + 'a {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic { apic_id, flags, .. } if MadtEntry::is_usable_cpu(flags) => Some(apic_id as u32),
+            MadtEntry::LocalX2Apic { x2apic_id, flags, .. } if MadtEntry::is_usable_cpu(flags) => Some(x2apic_id),
+            _ => None,
+        })
+    }
+}
+
+/// The ACPI PM timer, a 3.579545MHz counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PmTimer {
+    pub port: u16,
+    /// The counter is 32 bits wide, instead of 24 bits.
+    pub is_32bit: bool,
+}
+
+impl PmTimer {
+    pub const FREQUENCY: u64 = 3_579_545;
+
+    fn mask(&self) -> u32 {
+        if self.is_32bit { u32::MAX } else { 0xFF_FFFF }
+    }
+
+    pub fn read(&self) -> u32 {
+        unsafe { Port::<u32>::new(self.port).read() & self.mask() }
+    }
+
+    /// Busy-wait for the microseconds. This should be shorter than a counter wraparound. (4 seconds for 24 bits)
+    pub fn delay_us(&self, us: u64) {
+        let ticks = us * Self::FREQUENCY / 1_000_000;
+        let start = self.read();
+        while ((self.read().wrapping_sub(start) & self.mask()) as u64) < ticks {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// The register to write for a system reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResetRegister {
+    pub register: GenericAddress,
+    pub value: u8,
+}
+
+/// Fixed ACPI Description Table, with the fields the kernel uses.
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    /// The physical address of the DSDT.
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: Option<u16>,
+    pub pm1b_control: Option<u16>,
+    pub pm_timer: Option<PmTimer>,
+    pub reset: Option<ResetRegister>,
+    pub flags: u32,
+    /// IA-PC boot architecture flags, from revision 2.
+    pub boot_arch: Option<u16>,
+}
+
+impl Fadt {
+    pub const SIGNATURE: [u8; 4] = *b"FACP";
+
+    // flags
+    const TMR_VAL_EXT: u32 = 1 << 8;
+    const RESET_REG_SUP: u32 = 1 << 10;
+
+    // boot architecture flags
+    const BOOT_ARCH_8042: u16 = 1 << 1;
+
+    // field offsets from the start of the table. later fields are absent in older revisions.
+    const DSDT: usize = 40;
+    const SCI_INT: usize = 46;
+    const SMI_CMD: usize = 48;
+    const ACPI_ENABLE: usize = 52;
+    const PM1A_CNT_BLK: usize = 64;
+    const PM1B_CNT_BLK: usize = 68;
+    const PM_TMR_BLK: usize = 76;
+    const PM_TMR_LEN: usize = 91;
+    const IAPC_BOOT_ARCH: usize = 109;
+    const FLAGS: usize = 112;
+    const RESET_REG: usize = 116;
+    const RESET_VALUE: usize = 128;
+    const X_DSDT: usize = 140;
+    const X_PM1A_CNT_BLK: usize = 172;
+    const X_PM1B_CNT_BLK: usize = 184;
+    const X_PM_TMR_BLK: usize = 208;
+
+    pub fn parse(sdt: Sdt<'_>) -> Result<Self, AcpiError> {
+        let bytes = sdt.bytes();
+        if bytes.len() < Self::FLAGS + 4 { return Err(AcpiError::BadLength(sdt.signature())); }
+
+        // a 64-bit (extended) field, if the table is long enough and the field is set.
+        let gas = |offset: usize| {
+            bytes.get(offset..offset + GenericAddress::SIZE)
+                .map(GenericAddress::parse)
+                .filter(|gas| gas.address != 0)
+        };
+        let port = |offset: usize| Some(u32_at(bytes, offset) as u16).filter(|&port| port != 0);
+
+        let flags = u32_at(bytes, Self::FLAGS);
+
+        let dsdt = bytes.get(Self::X_DSDT..Self::X_DSDT + 8)
+            .map(|x_dsdt| u64_at(x_dsdt, 0))
+            .filter(|&x_dsdt| x_dsdt != 0)
+            .unwrap_or(u32_at(bytes, Self::DSDT) as u64);
+
+        let pm_timer_port = gas(Self::X_PM_TMR_BLK).and_then(|gas| gas.io_port())
+            .or_else(|| port(Self::PM_TMR_BLK).filter(|_| bytes[Self::PM_TMR_LEN] == 4));
+
+        let reset = (flags & Self::RESET_REG_SUP != 0)
+            .then(|| bytes.get(Self::RESET_VALUE).map(|&value| (gas(Self::RESET_REG), value)))
+            .flatten()
+            .and_then(|(register, value)| Some(ResetRegister { register: register?, value }));
+
+        Ok(Self {
+            dsdt,
+            sci_interrupt: u16_at(bytes, Self::SCI_INT),
+            smi_command: u32_at(bytes, Self::SMI_CMD),
+            acpi_enable: bytes[Self::ACPI_ENABLE],
+            pm1a_control: gas(Self::X_PM1A_CNT_BLK).and_then(|gas| gas.io_port()).or_else(|| port(Self::PM1A_CNT_BLK)),
+            pm1b_control: gas(Self::X_PM1B_CNT_BLK).and_then(|gas| gas.io_port()).or_else(|| port(Self::PM1B_CNT_BLK)),
+            pm_timer: pm_timer_port.map(|port| PmTimer { port, is_32bit: flags & Self::TMR_VAL_EXT != 0 }),
+            reset,
+            flags,
+            boot_arch: (sdt.revision() >= 2).then(|| u16_at(bytes, Self::IAPC_BOOT_ARCH)),
+        })
+    }
+
+    /// Whether there's an 8042 (PS/2) controller, if the table tells.
+    pub fn has_8042(&self) -> Option<bool> {
+        self.boot_arch.map(|flags| flags & Self::BOOT_ARCH_8042 != 0)
+    }
+}
+
+/// High Precision Event Timer table.
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    /// The physical address of the HPET registers.
+    pub base: GenericAddress,
+    pub hpet_number: u8,
+    /// The minimum clock ticks for periodic mode without lost interrupts.
+    pub min_tick: u16,
+    /// The number of comparators, from the event timer block ID.
+    pub comparator_count: u8,
+    pub pci_vendor_id: u16,
+}
+
+impl Hpet {
+    pub const SIGNATURE: [u8; 4] = *b"HPET";
+
+    pub fn parse(sdt: Sdt<'_>) -> Result<Self, AcpiError> {
+        let body = sdt.body_with(20)?;
+        let block_id = u32_at(body, 0);
+        Ok(Self {
+            base: GenericAddress::parse(&body[4..16]),
+            hpet_number: body[16],
+            min_tick: u16_at(body, 17),
+            comparator_count: ((block_id >> 8) & 0x1f) as u8 + 1,
+            pci_vendor_id: (block_id >> 16) as u16,
+        })
+    }
+}
+
+/// A PCI Express enhanced configuration space (ECAM) region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// PCI Express memory mapped configuration table.
+#[derive(Clone, Copy, Debug)]
+pub struct Mcfg<'a> {
+    entries: &'a [u8],
+}
+
+impl<'a> Mcfg<'a> {
+    pub const SIGNATURE: [u8; 4] = *b"MCFG";
+
+    pub fn parse(sdt: Sdt<'a>) -> Result<Self, AcpiError> {
+        let body = sdt.body_with(8)?;
+        Ok(Self { entries: &body[8..] }) // 8 reserved bytes
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + 'a {
+        self.entries.chunks_exact(16).map(|entry| McfgEntry {
+            base: u64_at(entry, 0),
+            segment: u16_at(entry, 8),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+    }
+}
+
+/// The `SLP_TYP` values of a sleep state, for PM1a and PM1b control registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SleepType {
+    pub a: u8,
+    pub b: u8,
+}
+
+// AML opcodes, for the fixed-object lookup.
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_WORD_PREFIX: u8 = 0x0B;
+const AML_DWORD_PREFIX: u8 = 0x0C;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_CHAR: u8 = b'\\';
+const AML_ONES_OP: u8 = 0xFF;
+
+/// An integer constant at the start of the AML, and its encoded length.
+fn aml_integer(aml: &[u8]) -> Option<(u64, usize)> {
+    match *aml.first()? {
+        AML_ZERO_OP => Some((0, 1)),
+        AML_ONE_OP => Some((1, 1)),
+        AML_ONES_OP => Some((u64::MAX, 1)),
+        AML_BYTE_PREFIX => Some((*aml.get(1)? as u64, 2)),
+        AML_WORD_PREFIX => Some((u16_at(aml.get(..3)?, 1) as u64, 3)),
+        AML_DWORD_PREFIX => Some((u32_at(aml.get(..5)?, 1) as u64, 5)),
+        _ => None,
+    }
+}
+
+/// Find `Name(\_Sx_, Package() { SLP_TYPa, SLP_TYPb, ... })` in the AML of a definition block.
+///
+/// This is not an interpreter: objects defined inside methods or conditionals are found all the same,
+/// and packages built at runtime are not. It works for the `_Sx_` objects of common firmware.
+pub fn find_sleep_type(aml: &[u8], state: u8) -> Option<SleepType> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    (1..aml.len()).filter(|&i| aml[i..].starts_with(&name)).find_map(|i| {
+        let name_op = match aml[i - 1] {
+            AML_ROOT_CHAR if i >= 2 => aml[i - 2],
+            op => op,
+        };
+        if name_op != AML_NAME_OP { return None; }
+
+        let package = aml.get(i + 4..)?;
+        if *package.first()? != AML_PACKAGE_OP { return None; }
+        // the top 2 bits of PkgLength are the number of bytes following the lead byte.
+        let pkg_length_size = 1 + (*package.get(1)? >> 6) as usize;
+        let elements = package.get(1 + pkg_length_size + 1..)?; // after NumElements
+        let (a, a_size) = aml_integer(elements)?;
+        let (b, _) = aml_integer(&elements[a_size..])?;
+        Some(SleepType { a: a as u8, b: b as u8 })
+    })
+}
+
+/// The ACPI tables found from the RSDP.
+#[derive(Clone, Copy, Debug)]
+pub struct Acpi {
+    pub rsdp: Rsdp,
+    root: RootTable<'static>,
+}
+
+impl Acpi {
+    /// Find the root table from the RSDP, preferring the XSDT.
+    ///
+    /// # Safety
+    /// The address should be the RSDP from the firmware. ACPI tables should be identity-mapped, and never freed.
+    pub unsafe fn from_rsdp(rsdp_addr: u64) -> Result<Self, AcpiError> {
+        let rsdp = Rsdp::from_phys(rsdp_addr)?;
+        let root_addr = if rsdp.xsdt_address != 0 { rsdp.xsdt_address } else { rsdp.rsdt_address as u64 };
+        let root = RootTable::new(Sdt::from_phys(root_addr)?)?;
+        Ok(Self { rsdp, root })
+    }
+
+    /// All tables listed in the root table. Tables with a bad checksum are skipped.
+    pub fn tables(&self) -> impl Iterator<Item = Sdt<'static>> {
+        self.root.entries().filter_map(|addr| unsafe { Sdt::from_phys(addr) }.ok())
+    }
+
+    /// The first table with the signature.
+    pub fn find(&self, signature: [u8; 4]) -> Option<Sdt<'static>> {
+        self.tables().find(|sdt| sdt.signature() == signature)
+    }
+
+    pub fn madt(&self) -> Option<Madt<'static>> {
+        Madt::parse(self.find(Madt::SIGNATURE)?).ok()
+    }
+
+    pub fn fadt(&self) -> Option<Fadt> {
+        Fadt::parse(self.find(Fadt::SIGNATURE)?).ok()
+    }
+
+    pub fn hpet(&self) -> Option<Hpet> {
+        Hpet::parse(self.find(Hpet::SIGNATURE)?).ok()
+    }
+
+    pub fn mcfg(&self) -> Option<Mcfg<'static>> {
+        Mcfg::parse(self.find(Mcfg::SIGNATURE)?).ok()
+    }
+
+    /// The DSDT, which holds the AML of the system. It is not listed in the root table, but in the FADT.
+    pub fn dsdt(&self) -> Option<Sdt<'static>> {
+        let dsdt = self.fadt()?.dsdt;
+        if dsdt == 0 { return None; }
+        unsafe { Sdt::from_phys(dsdt) }.ok()
+    }
+
+    /// The `SLP_TYP` values of a sleep state (5 for soft-off), from the DSDT or the SSDTs.
+    pub fn sleep_type(&self, state: u8) -> Option<SleepType> {
+        self.dsdt().into_iter()
+            .chain(self.tables().filter(|sdt| sdt.signature() == *b"SSDT"))
+            .find_map(|sdt| find_sleep_type(sdt.body(), state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table with a valid header and checksum.
+    fn table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; SDT_HEADER_SIZE];
+        bytes[..4].copy_from_slice(signature);
+        bytes[4..8].copy_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        bytes[8] = 1;
+        bytes.extend_from_slice(body);
+        bytes[9] = 0u8.wrapping_sub(checksum(&bytes));
+        bytes
+    }
+
+    #[test]
+    fn checks_tables() {
+        let bytes = table(b"TEST", &[1, 2, 3]);
+        let sdt = Sdt::new(&bytes).unwrap();
+        assert_eq!(sdt.signature(), *b"TEST");
+        assert_eq!(sdt.body(), [1, 2, 3]);
+
+        let mut corrupted = bytes.clone();
+        corrupted[37] ^= 1;
+        assert_eq!(Sdt::new(&corrupted).unwrap_err(), AcpiError::BadChecksum(*b"TEST"));
+        assert_eq!(Sdt::new(&bytes[..38]).unwrap_err(), AcpiError::BadLength(*b"TEST"));
+    }
+
+    #[test]
+    fn reads_8042_flag_from_revision_2() {
+        let mut body = vec![0; 244 - SDT_HEADER_SIZE];
+        body[Fadt::IAPC_BOOT_ARCH - SDT_HEADER_SIZE] = 0x02;
+        let mut bytes = table(b"FACP", &body);
+        assert_eq!(Fadt::parse(Sdt::new(&bytes).unwrap()).unwrap().has_8042(), None);
+
+        bytes[8] = 3;
+        bytes[9] = bytes[9].wrapping_sub(2);
+        assert_eq!(Fadt::parse(Sdt::new(&bytes).unwrap()).unwrap().has_8042(), Some(true));
+    }
+
+    #[test]
+    fn parses_rsdp() {
+        let mut rsdp = [0u8; 36];
+        rsdp[..8].copy_from_slice(b"RSD PTR ");
+        rsdp[15] = 2;
+        rsdp[16..20].copy_from_slice(&0x1234u32.to_le_bytes());
+        rsdp[20..24].copy_from_slice(&36u32.to_le_bytes());
+        rsdp[24..32].copy_from_slice(&0x5678u64.to_le_bytes());
+        rsdp[8] = 0u8.wrapping_sub(checksum(&rsdp[..20]));
+        rsdp[32] = 0u8.wrapping_sub(checksum(&rsdp));
+
+        let parsed = Rsdp::parse(&rsdp).unwrap();
+        assert_eq!((parsed.revision, parsed.rsdt_address, parsed.xsdt_address), (2, 0x1234, 0x5678));
+
+        rsdp[32] ^= 1;
+        assert_eq!(Rsdp::parse(&rsdp).unwrap_err(), AcpiError::BadChecksum(*b"RSDP"));
+    }
+
+    #[test]
+    fn parses_madt_entries() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]); // LAPIC 0, enabled
+        body.extend_from_slice(&[0, 8, 1, 1, 0, 0, 0, 0]); // LAPIC 1, disabled
+        body.extend_from_slice(&[1, 12, 2, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]); // IOAPIC 2
+        body.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]); // IRQ 0 -> GSI 2
+        body.extend_from_slice(&[0x7f, 3, 0]); // unknown
+        body.extend_from_slice(&[0, 9]); // truncated
+
+        let bytes = table(b"APIC", &body);
+        let madt = Madt::parse(Sdt::new(&bytes).unwrap()).unwrap();
+        let entries: Vec<_> = madt.entries().collect();
+        assert_eq!(entries, [
+            MadtEntry::LocalApic { processor_id: 0, apic_id: 0, flags: 1 },
+            MadtEntry::LocalApic { processor_id: 1, apic_id: 1, flags: 0 },
+            MadtEntry::IoApic { id: 2, address: 0xfec0_0000, gsi_base: 0 },
+            MadtEntry::InterruptSourceOverride { bus: 0, source: 0, gsi: 2, flags: 0 },
+            MadtEntry::Other { entry_type: 0x7f },
+        ]);
+        assert_eq!(madt.cpu_apic_ids().collect::<Vec<_>>(), [0]);
+        assert_eq!(madt.local_apic_address(), 0xfee0_0000);
+    }
+
+    #[test]
+    fn finds_sleep_types() {
+        // Name(\_S5_, Package(0x04) { 0x05, Zero, Zero, Zero }), after an unrelated `_S5_` string.
+        let aml = [
+            0x0D, b'_', b'S', b'5', b'_', 0x00,
+            0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x07, 0x04, 0x0A, 0x05, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(find_sleep_type(&aml, 5), Some(SleepType { a: 5, b: 0 }));
+        assert_eq!(find_sleep_type(&aml, 3), None);
+
+        // Name(_S5_, Package(0x02) { One, 0x0107 }), with a 2-byte PkgLength.
+        let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x40, 0x00, 0x02, 0x01, 0x0B, 0x07, 0x01];
+        assert_eq!(find_sleep_type(&aml, 5), Some(SleepType { a: 1, b: 7 }));
+    }
+}
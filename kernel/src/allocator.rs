@@ -7,43 +7,228 @@ extern crate alloc;
 use core::ptr::NonNull;
 use core::ops::Deref;
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use alloc::alloc::{Allocator, Global};
 // use alloc::alloc::{Allocator, AllocError};
 
 use spin::mutex::Mutex;
+use linked_list_allocator::hole::HoleList;
 use linked_list_allocator::Heap; // `LockedHeap` uses lock in the `spinning_top` crate. We will use `spin` instead.
 
-#[repr(transparent)]
-pub struct GlobalHeap(Mutex<Heap>);
+use crate::pgmgr::{PageManager, FrameID, KERNEL_PAGE_SIZE};
+use crate::sync::CpuMutex;
+use crate::slab::SlabAllocator;
+
+/// The maximum number of arenas.
+/// A new arena is added only if the current last arena cannot be extended in place.
+const MAX_ARENAS: usize = 16;
+
+/// The minimum frame count requested on each growth. (2MB)
+const GROW_FRAME_CNT: usize = 512;
+
+/// An arena, and how far it has been handed out.
+struct Arena {
+    heap: Heap,
+    /// The end of the highest block allocated so far. The rest of the arena was never split.
+    frontier: usize,
+}
+
+impl Arena {
+    fn new(heap: Heap) -> Self {
+        let frontier = heap.bottom() as usize;
+        Self { heap, frontier }
+    }
+
+    /// The free block above the frontier.
+    fn untouched(&self) -> usize {
+        self.heap.top() as usize - self.frontier
+    }
+}
+
+/// The heap arenas and statistics counters.
+pub struct Arenas {
+    arenas: heapless::Vec<Arena, MAX_ARENAS>,
+    page_manager: &'static CpuMutex<PageManager>,
+
+    peak: usize,
+    alloc_count: usize,
+}
+
+impl Arenas {
+    fn arena_of(&mut self, ptr: *mut u8) -> Option<&mut Arena> {
+        self.arenas.iter_mut().find(|arena| arena.heap.bottom() <= ptr && ptr < arena.heap.top())
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        self.arenas.iter_mut().find_map(|arena| {
+            let ptr = arena.heap.allocate_first_fit(layout).ok()?;
+            // the hole list rounds the size up the same way.
+            let end = ptr.as_ptr() as usize + HoleList::align_layout(layout).size();
+            arena.frontier = arena.frontier.max(end);
+            Some(ptr)
+        })
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let arena = self.arena_of(ptr.as_ptr()).expect("Deallocating a pointer outside of the heap");
+        unsafe {
+            arena.heap.deallocate(ptr, layout);
+        }
+        // an empty arena is a single hole again.
+        if arena.heap.used() == 0 {
+            arena.frontier = arena.heap.bottom() as usize;
+        }
+    }
+
+    /// Request more frames from the page manager, enough to hold the given layout.
+    /// The last arena is extended if the frames right after it are vacant.
+    fn grow(&mut self, layout: Layout) -> Option<()> {
+        // leave room for the alignment and the hole header.
+        let min_size = layout.size() + layout.align() + 2 * core::mem::size_of::<usize>();
+        let frame_cnt = GROW_FRAME_CNT.max(min_size.div_ceil(KERNEL_PAGE_SIZE));
+
+        let mut mgr = self.page_manager.lock();
+
+        if let Some(last) = self.arenas.last_mut() {
+            let top = last.heap.top() as usize;
+            if top % KERNEL_PAGE_SIZE == 0 && mgr.allocate_at(FrameID(top / KERNEL_PAGE_SIZE), frame_cnt).is_ok() {
+                unsafe {
+                    last.heap.extend(frame_cnt * KERNEL_PAGE_SIZE);
+                }
+                return Some(());
+            }
+        }
+
+        if self.arenas.is_full() { return None; }
+        let frame_id = mgr.allocate(frame_cnt).ok()?;
+        let arena = unsafe {
+            Heap::new(frame_id.addr() as *mut u8, frame_cnt * KERNEL_PAGE_SIZE)
+        };
+        self.arenas.push(Arena::new(arena)).ok()
+    }
+
+    fn used(&self) -> usize {
+        self.arenas.iter().map(|arena| arena.heap.used()).sum()
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.arenas.iter().map(|arena| arena.heap.size()).sum(),
+            used: self.used(),
+            free: self.arenas.iter().map(|arena| arena.heap.free()).sum(),
+            largest_free: self.arenas.iter().map(Arena::untouched).max().unwrap_or(0),
+            peak: self.peak,
+            alloc_count: self.alloc_count,
+            arena_count: self.arenas.len(),
+        }
+    }
+}
+
+/// Live heap statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Total size of the arenas, in bytes.
+    pub size: usize,
+    /// Allocated bytes.
+    pub used: usize,
+    /// Free bytes.
+    pub free: usize,
+    /// The largest free block that is known without walking the holes: the untouched end of an arena.
+    /// The real one may be larger, so the gap to `free` is an upper bound of the fragmentation.
+    pub largest_free: usize,
+    /// The maximum of `used` so far.
+    pub peak: usize,
+    /// The number of live allocations in the arenas. Small objects are counted by their slabs.
+    pub alloc_count: usize,
+    /// The number of arenas.
+    pub arena_count: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heap {} KB in {} arena(s): used {} KB (peak {} KB), free {} KB (largest block >= {} KB), {} allocs",
+            self.size / 1024, self.arena_count,
+            self.used / 1024, self.peak / 1024,
+            self.free / 1024, self.largest_free / 1024,
+            self.alloc_count,
+        )
+    }
+}
+
+/// The kernel heap, which grows itself by requesting frames from the page manager.
+/// Small objects go to the slab caches in front, whose slabs come from the arenas.
+pub struct GlobalHeap {
+    arenas: Mutex<Arenas>,
+    slab: SlabAllocator<Global>,
+}
 impl GlobalHeap {
-    pub const fn empty() -> Self {
-        Self(Mutex::new(Heap::empty()))
+    pub const fn empty(page_manager: &'static CpuMutex<PageManager>) -> Self {
+        Self {
+            arenas: Mutex::new(Arenas {
+                arenas: heapless::Vec::new(),
+                page_manager,
+                peak: 0,
+                alloc_count: 0,
+            }),
+            slab: SlabAllocator::new(Global),
+        }
     }
 
+    /// Add the initial arena.
     pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
-        self.0.lock().init(heap_bottom, heap_size);
+        let mut arenas = self.arenas.lock();
+        assert!(arenas.arenas.is_empty(), "Heap already initialized");
+        let _ = arenas.arenas.push(Arena::new(Heap::new(heap_bottom, heap_size)));
+    }
+
+    /// Returns the current heap statistics.
+    pub fn stats(&self) -> HeapStats {
+        self.arenas.lock().stats()
     }
 }
 
 impl Deref for GlobalHeap {
-    type Target = Mutex<Heap>;
+    type Target = Mutex<Arenas>;
 
-    fn deref(&self) -> &Mutex<Heap> {
-        &self.0
+    fn deref(&self) -> &Mutex<Arenas> {
+        &self.arenas
     }
 }
 
 unsafe impl GlobalAlloc for GlobalHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
-            .lock()
-            .allocate_first_fit(layout)
-            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+        // a new slab takes its page through `Global`, which lands in the arenas below.
+        if self.slab.serves(layout) {
+            return self.slab.allocate(layout).map_or(core::ptr::null_mut(), |ptr| ptr.cast::<u8>().as_ptr());
+        }
+
+        let mut arenas = self.arenas.lock();
+
+        let allocation = match arenas.allocate(layout) {
+            Some(ptr) => Some(ptr),
+            None => arenas.grow(layout).and_then(|()| arenas.allocate(layout)),
+        };
+
+        match allocation {
+            Some(ptr) => {
+                arenas.alloc_count += 1;
+                arenas.peak = arenas.peak.max(arenas.used());
+                ptr.as_ptr()
+            },
+            None => core::ptr::null_mut(),
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0
-            .lock()
-            .deallocate(NonNull::new_unchecked(ptr), layout)
+        if self.slab.serves(layout) {
+            return self.slab.deallocate(NonNull::new_unchecked(ptr), layout);
+        }
+
+        let mut arenas = self.arenas.lock();
+        arenas.alloc_count -= 1;
+        arenas.deallocate(NonNull::new_unchecked(ptr), layout)
     }
 }
 
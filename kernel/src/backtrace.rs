@@ -0,0 +1,221 @@
+//! Frame pointer based stack walker, and the kernel symbol table to resolve return addresses.
+//!
+//! The kernel is built with frame pointers (see `.cargo/config.toml`),
+//! so `rbp` points to the saved `rbp` of the caller, followed by the return address.
+
+use core::fmt;
+
+use shared::KernelSymbols;
+
+/// The size of `Elf64_Sym`.
+const ELF64_SYM_SIZE: usize = 24;
+/// `STT_FUNC` symbol type.
+const STT_FUNC: u8 = 2;
+
+/// The maximum number of frames to walk.
+pub const MAX_FRAMES: usize = 32;
+
+/// A function symbol.
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// The kernel symbol table, passed by the bootloader.
+#[derive(Clone, Copy)]
+pub struct SymbolTable {
+    symtab: &'static [u8],
+    strtab: &'static [u8],
+    /// Added to symbol values, for a relocated kernel.
+    bias: u64,
+}
+
+impl SymbolTable {
+    /// Returns None if the bootloader couldn't find the symbol table.
+    /// `bias` is the load bias of the kernel, so that symbols are at their runtime addresses.
+    ///
+    /// # Safety
+    /// The sections should be valid and never freed.
+    pub unsafe fn from_raw(raw: KernelSymbols, bias: u64) -> Option<Self> {
+        if raw.symtab.is_null() || raw.strtab.is_null() { return None; }
+
+        Some(Self {
+            symtab: core::slice::from_raw_parts(raw.symtab, raw.symtab_size),
+            strtab: core::slice::from_raw_parts(raw.strtab, raw.strtab_size),
+            bias,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.symtab.len() / ELF64_SYM_SIZE
+    }
+
+    /// The name at the given offset of the string table.
+    fn name(&self, offset: usize) -> Option<&'static str> {
+        let bytes = self.strtab.get(offset..)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+
+    /// The i-th symbol, if it is a function.
+    fn function(&self, i: usize) -> Option<Symbol> {
+        let raw = self.symtab.get(i * ELF64_SYM_SIZE..(i + 1) * ELF64_SYM_SIZE)?;
+        let st_name = u32::from_le_bytes(raw[0..4].try_into().unwrap());
+        let st_info = raw[4];
+        let st_value = u64::from_le_bytes(raw[8..16].try_into().unwrap());
+        let st_size = u64::from_le_bytes(raw[16..24].try_into().unwrap());
+
+        if st_info & 0xf != STT_FUNC { return None; }
+        Some(Symbol {
+            name: self.name(st_name as usize)?,
+            addr: st_value.wrapping_add(self.bias),
+            size: st_size,
+        })
+    }
+
+    /// Find the function containing the address.
+    pub fn lookup(&self, addr: u64) -> Option<Symbol> {
+        (0..self.len())
+            .filter_map(|i| self.function(i))
+            .find(|sym| sym.addr <= addr && addr < sym.addr + sym.size.max(1))
+    }
+}
+
+/// A demangled symbol name. Only the legacy Rust mangling (`_ZN...E`) is demangled;
+/// other names are displayed as-is.
+pub struct Demangle<'a>(pub &'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN") else {
+            return f.write_str(self.0);
+        };
+
+        let mut first = true;
+        while let Some(len_end) = rest.find(|c: char| !c.is_ascii_digit()) {
+            if len_end == 0 { break; } // reached the terminating 'E'
+            let Ok(len) = rest[..len_end].parse::<usize>() else { break };
+            let Some(ident) = rest.get(len_end..len_end + len) else { break };
+            rest = &rest[len_end + len..];
+
+            // the last component is the hash, like `h0123456789abcdef`.
+            if rest == "E" && is_hash(ident) { break; }
+
+            if !first { f.write_str("::")?; }
+            first = false;
+            write_ident(f, ident)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_hash(ident: &str) -> bool {
+    ident.len() == 17 && ident.starts_with('h') && ident[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Write an identifier, replacing the escapes of the legacy mangling.
+fn write_ident(f: &mut fmt::Formatter<'_>, ident: &str) -> fmt::Result {
+    const ESCAPES: [(&str, &str); 17] = [
+        ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+        ("$LP$", "("), ("$RP$", ")"), ("$C$", ","),
+        ("$u20$", " "), ("$u22$", "\""), ("$u27$", "'"), ("$u2b$", "+"), ("$u3b$", ";"),
+        ("$u5b$", "["), ("$u5d$", "]"), ("$u7b$", "{"), ("$u7d$", "}"),
+    ];
+
+    // an identifier starting with an escape is prefixed by '_'.
+    let mut rest = if ident.starts_with("_$") { &ident[1..] } else { ident };
+    while !rest.is_empty() {
+        if let Some(s) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = s;
+            continue;
+        }
+        if let Some((escape, c)) = ESCAPES.iter().find(|(escape, _)| rest.starts_with(escape)) {
+            f.write_str(c)?;
+            rest = &rest[escape.len()..];
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        write!(f, "{}", c)?;
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(())
+}
+
+/// Read the frame pointer of the caller.
+#[inline(always)]
+pub fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    rbp
+}
+
+/// An iterator over return addresses, walking the frame pointer chain.
+///
+/// `readable` tells whether the address can be read without faulting.
+/// The walk stops at a null or unreadable frame, or if the chain doesn't go up the stack.
+pub struct Frames<F> {
+    rbp: u64,
+    depth: usize,
+    readable: F,
+}
+
+pub fn walk<F: Fn(u64) -> bool>(rbp: u64, readable: F) -> Frames<F> {
+    Frames { rbp, depth: 0, readable }
+}
+
+impl<F: Fn(u64) -> bool> Iterator for Frames<F> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let rbp = self.rbp;
+        if rbp == 0 || rbp % 8 != 0 || self.depth >= MAX_FRAMES { return None; }
+        if !(self.readable)(rbp) || !(self.readable)(rbp + 15) { return None; }
+
+        let (next_rbp, ret_addr) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        if ret_addr == 0 { return None; }
+
+        self.rbp = if next_rbp > rbp { next_rbp } else { 0 };
+        self.depth += 1;
+        Some(ret_addr)
+    }
+}
+
+/// A printable backtrace.
+pub struct Backtrace<F> {
+    /// The faulting instruction, which comes before the return addresses.
+    pub rip: Option<u64>,
+    pub rbp: u64,
+    pub symbols: Option<SymbolTable>,
+    pub readable: F,
+}
+
+impl<F: Fn(u64) -> bool + Copy> fmt::Display for Backtrace<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Backtrace:")?;
+        if self.symbols.is_none() {
+            write!(f, " (no symbol table)")?;
+        }
+
+        // a return address points after the call, so look up the byte before it.
+        let addrs = self.rip.map(|rip| (rip, rip))
+            .into_iter()
+            .chain(walk(self.rbp, self.readable).map(|ret| (ret, ret - 1)));
+
+        for (i, (addr, lookup_addr)) in addrs.enumerate() {
+            write!(f, "\n  #{:<2} {:#018x}", i, addr)?;
+            match self.symbols.and_then(|symbols| symbols.lookup(lookup_addr)) {
+                Some(sym) => write!(f, " {}+{:#x}", Demangle(sym.name), addr - sym.addr)?,
+                None => write!(f, " ??")?,
+            }
+        }
+        Ok(())
+    }
+}
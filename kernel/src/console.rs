@@ -8,13 +8,13 @@ use crate::sysfont::{
 };
 
 use core::cell::OnceCell;
-use spin::mutex::Mutex;
+use crate::sync::CpuMutex;
 
-const CONSOLE_ROWS: usize = 25;
-const CONSOLE_COLS: usize = 80;
+pub const CONSOLE_ROWS: usize = 25;
+pub const CONSOLE_COLS: usize = 80;
 
 pub struct Console {
-    screen: &'static Mutex<OnceCell<Screen>>,
+    screen: &'static CpuMutex<OnceCell<Screen>>,
     // note: methods accessing `screen` should be limited to `render()` and `render_one()`,
     // to avoid requiring lock twice ("self-deadlock")
 
@@ -29,7 +29,7 @@ pub struct Console {
 }
 
 impl Console{
-    pub fn new(screen: &'static Mutex<OnceCell<Screen>>) -> Self {
+    pub fn new(screen: &'static CpuMutex<OnceCell<Screen>>) -> Self {
         let console = Self {
             screen,
             fg: ColorCode::WHITE,
@@ -95,6 +95,16 @@ impl Console{
         // self.buffer[CONSOLE_ROWS-1] = [b' '; CONSOLE_COLS];
     }
 
+    /// The characters in the given row, counted from the top.
+    pub fn row(&self, i: usize) -> &[u8; CONSOLE_COLS] {
+        &self.buffer[i]
+    }
+
+    /// The column of the next character, in the last row.
+    pub fn cursor_col(&self) -> usize {
+        self.cur_col
+    }
+
     /// Add new line.
     pub fn newline(&mut self){
         self.carrige_return();
@@ -109,6 +119,14 @@ impl Console{
 
         match ch { // @todo : more control characters support
             b'\n' => self.newline(),
+            0x08 => { // backspace, erasing within the row.
+                if self.cur_col > 0 {
+                    self.cur_col -= 1;
+                    let (i, j) = (CONSOLE_ROWS - 1, self.cur_col);
+                    self.buffer[i][j] = b' ';
+                    self.render_one((i, j), b' ');
+                }
+            },
             ch => {
                 if self.cur_col >= CONSOLE_COLS {
                     self.newline();
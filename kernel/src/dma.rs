@@ -0,0 +1,136 @@
+//! DMA-capable allocator.
+//!
+//! Frames are identity-mapped, so the virtual address of a DMA buffer is its physical address.
+//! What this allocator adds is the guarantee on where the buffer lies:
+//! alignment, boundary crossing and the upper physical address limit.
+
+use core::alloc::{Allocator, AllocError, Layout};
+use core::ptr::NonNull;
+
+use x86_64::PhysAddr;
+
+use crate::pgmgr::{PageManager, FrameID, KERNEL_PAGE_SIZE, KB, GB};
+use crate::slab::{SlabAllocator, SLAB_SIZE};
+use crate::sync::CpuMutex;
+
+/// Placement constraints on DMA buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmaConstraints {
+    /// Minimum alignment of a buffer.
+    pub align: usize,
+    /// A buffer never crosses a multiple of this. Should be a power of two, no less than `SLAB_SIZE`.
+    pub boundary: usize,
+    /// The physical address limit (exclusive).
+    pub max_addr: usize,
+}
+
+impl DmaConstraints {
+    /// xHCI data structures: 64-byte aligned, no 64KB boundary crossing, and below 4GB.
+    ///
+    /// These also cover most of other PCI devices, so this is the default of the global DMA allocator.
+    pub const XHCI: Self = Self {
+        align: 64,
+        boundary: 64 * KB,
+        max_addr: 4 * GB,
+    };
+}
+
+/// Page-granular DMA buffers, taken directly from the page manager.
+#[derive(Clone, Copy)]
+pub struct DmaPages {
+    page_manager: &'static CpuMutex<PageManager>,
+    constraints: DmaConstraints,
+}
+
+impl DmaPages {
+    pub const fn new(page_manager: &'static CpuMutex<PageManager>, constraints: DmaConstraints) -> Self {
+        Self { page_manager, constraints }
+    }
+}
+
+unsafe impl Allocator for DmaPages {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let size = layout.size().max(1);
+        let page_cnt = size.div_ceil(KERNEL_PAGE_SIZE);
+
+        // a buffer aligned to its power-of-two size never crosses a boundary larger than the size.
+        let mut align = layout.align().max(self.constraints.align).max(KERNEL_PAGE_SIZE);
+        if size <= self.constraints.boundary {
+            align = align.max(size.next_power_of_two());
+        } else {
+            return Err(AllocError);
+        }
+
+        let frame_id = self.page_manager.lock()
+            .allocate_constrained(
+                page_cnt,
+                align / KERNEL_PAGE_SIZE,
+                FrameID(self.constraints.max_addr / KERNEL_PAGE_SIZE),
+            )
+            .map_err(|_| AllocError)?;
+
+        let ptr = NonNull::new(frame_id.addr() as *mut u8).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, page_cnt * KERNEL_PAGE_SIZE))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let page_cnt = layout.size().max(1).div_ceil(KERNEL_PAGE_SIZE);
+        let _ = self.page_manager.lock()
+            .free(FrameID(ptr.as_ptr() as usize / KERNEL_PAGE_SIZE), page_cnt);
+    }
+}
+
+/// The DMA allocator.
+///
+/// Small buffers are served from slab caches over DMA pages.
+/// Since a slab object is aligned to its size class and never crosses a slab,
+/// it meets the boundary constraint as long as the boundary is no less than `SLAB_SIZE`.
+pub struct DmaAllocator {
+    slab: SlabAllocator<DmaPages>,
+    constraints: DmaConstraints,
+}
+
+impl DmaAllocator {
+    pub const fn new(page_manager: &'static CpuMutex<PageManager>, constraints: DmaConstraints) -> Self {
+        assert!(constraints.boundary.is_power_of_two() && constraints.boundary >= SLAB_SIZE);
+        assert!(constraints.align.is_power_of_two());
+
+        Self {
+            slab: SlabAllocator::new(DmaPages::new(page_manager, constraints)),
+            constraints,
+        }
+    }
+
+    pub fn constraints(&self) -> DmaConstraints {
+        self.constraints
+    }
+
+    /// The physical address of a buffer allocated from this allocator.
+    pub fn phys_addr<T: ?Sized>(&self, ptr: NonNull<T>) -> PhysAddr {
+        // identity-mapped.
+        PhysAddr::new(ptr.cast::<u8>().as_ptr() as u64)
+    }
+
+    fn adjust(&self, layout: Layout) -> Result<Layout, AllocError> {
+        layout.align_to(self.constraints.align).map_err(|_| AllocError)
+    }
+}
+
+unsafe impl Allocator for DmaAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let buf = self.slab.allocate(self.adjust(layout)?)?;
+
+        debug_assert!({
+            let start = buf.cast::<u8>().as_ptr() as usize;
+            let end = start + layout.size().max(1) - 1;
+            end < self.constraints.max_addr
+                && start / self.constraints.boundary == end / self.constraints.boundary
+        });
+        Ok(buf)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // `adjust` succeeded on allocation, hence it succeeds here.
+        self.slab.deallocate(ptr, self.adjust(layout).unwrap())
+    }
+}
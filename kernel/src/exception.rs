@@ -0,0 +1,212 @@
+//! CPU exception entry stubs and the saved register context.
+//!
+//! `x86-interrupt` handlers do not expose general purpose registers,
+//! so exceptions enter through the assembly stubs below instead.
+//! Each stub pushes a uniform frame and calls `exception_dispatch`, which is defined in `globals::interrupts`.
+
+use core::fmt;
+
+use x86_64::VirtAddr;
+use x86_64::structures::idt::InterruptStackFrameValue;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+
+/// The number of architectural exception vectors.
+pub const EXCEPTION_COUNT: usize = 32;
+
+/// The size of each entry stub. Stub of vector `i` is located at `exception_stubs + i * STUB_SIZE`.
+const STUB_SIZE: usize = 16;
+
+/// Returns true if the CPU pushes an error code for the vector.
+pub const fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 8 | 10 | 11 | 12 | 13 | 14 | 17 | 21 | 29 | 30)
+}
+
+/// The mnemonic and the name of the exception.
+pub fn exception_name(vector: u8) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("#DE", "Divide Error"),
+        1 => ("#DB", "Debug"),
+        2 => ("NMI", "Non-maskable Interrupt"),
+        3 => ("#BP", "Breakpoint"),
+        4 => ("#OF", "Overflow"),
+        5 => ("#BR", "Bound Range Exceeded"),
+        6 => ("#UD", "Invalid Opcode"),
+        7 => ("#NM", "Device Not Available"),
+        8 => ("#DF", "Double Fault"),
+        9 => ("", "Coprocessor Segment Overrun"),
+        10 => ("#TS", "Invalid TSS"),
+        11 => ("#NP", "Segment Not Present"),
+        12 => ("#SS", "Stack-Segment Fault"),
+        13 => ("#GP", "General Protection"),
+        14 => ("#PF", "Page Fault"),
+        16 => ("#MF", "x87 Floating-Point Error"),
+        17 => ("#AC", "Alignment Check"),
+        18 => ("#MC", "Machine Check"),
+        19 => ("#XM", "SIMD Floating-Point Exception"),
+        20 => ("#VE", "Virtualization Exception"),
+        21 => ("#CP", "Control Protection Exception"),
+        28 => ("#HV", "Hypervisor Injection Exception"),
+        29 => ("#VC", "VMM Communication Exception"),
+        30 => ("#SX", "Security Exception"),
+        _ => ("", "Reserved"),
+    }
+}
+
+/// The registers saved by the entry stub, from the lowest address.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ExceptionContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+
+    pub vector: u64,
+    /// The error code, or zero if the exception doesn't have one.
+    pub error_code: u64,
+
+    /// The frame pushed by the CPU.
+    pub frame: InterruptStackFrameValue,
+}
+
+impl ExceptionContext {
+    pub fn vector(&self) -> u8 {
+        self.vector as u8
+    }
+}
+
+/// A register and stack dump of an exception context.
+///
+/// `stack` is the top of the interrupted stack, if it is readable.
+pub struct ExceptionDump<'a> {
+    pub context: &'a ExceptionContext,
+    pub stack: Option<&'a [u64]>,
+}
+
+impl fmt::Display for ExceptionDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ctx = self.context;
+        let (mnemonic, name) = exception_name(ctx.vector());
+
+        writeln!(f, "Exception {} {} (vector {}, error code {:#x})", mnemonic, name, ctx.vector, ctx.error_code)?;
+        writeln!(
+            f, "RIP {:016x} CS  {:04x}  RFLAGS {:016x}",
+            ctx.frame.instruction_pointer.as_u64(), ctx.frame.code_segment, ctx.frame.cpu_flags
+        )?;
+        writeln!(
+            f, "RSP {:016x} SS  {:04x}",
+            ctx.frame.stack_pointer.as_u64(), ctx.frame.stack_segment
+        )?;
+        writeln!(f, "RAX {:016x} RBX {:016x} RCX {:016x}", ctx.rax, ctx.rbx, ctx.rcx)?;
+        writeln!(f, "RDX {:016x} RSI {:016x} RDI {:016x}", ctx.rdx, ctx.rsi, ctx.rdi)?;
+        writeln!(f, "RBP {:016x} R8  {:016x} R9  {:016x}", ctx.rbp, ctx.r8, ctx.r9)?;
+        writeln!(f, "R10 {:016x} R11 {:016x} R12 {:016x}", ctx.r10, ctx.r11, ctx.r12)?;
+        writeln!(f, "R13 {:016x} R14 {:016x} R15 {:016x}", ctx.r13, ctx.r14, ctx.r15)?;
+        writeln!(
+            f, "CR0 {:016x} CR2 {:016x} CR3 {:016x} CR4 {:016x}",
+            Cr0::read_raw(), Cr2::read().as_u64(), Cr3::read_raw().0.start_address().as_u64(), Cr4::read_raw()
+        )?;
+
+        match self.stack {
+            Some(stack) => {
+                write!(f, "Stack:")?;
+                for (i, qword) in stack.iter().enumerate() {
+                    if i % 4 == 0 { write!(f, "\n  {:016x}:", ctx.frame.stack_pointer.as_u64() + 8 * i as u64)?; }
+                    write!(f, " {:016x}", qword)?;
+                }
+                Ok(())
+            },
+            None => write!(f, "Stack: (unreadable)"),
+        }
+    }
+}
+
+extern "sysv64" {
+    /// The start of entry stubs. Not a real function.
+    fn exception_stubs();
+}
+
+/// The entry stub address of the vector.
+pub fn stub_addr(vector: u8) -> VirtAddr {
+    assert!((vector as usize) < EXCEPTION_COUNT);
+    VirtAddr::new(exception_stubs as usize as u64 + (vector as usize * STUB_SIZE) as u64)
+}
+
+// Entry stubs push a dummy error code if the CPU doesn't, then push the vector number.
+// The common part saves general purpose registers and passes the frame to `exception_dispatch`.
+// The CPU aligns RSP to 16 bytes before pushing its 5-qword frame. With the error code, the vector
+// and 15 registers, 22 qwords are pushed in total, so RSP is 16-byte aligned again at the call.
+macro_rules! exception_stubs_asm {
+    ($($vector:literal),*) => {
+        core::arch::global_asm!(
+            ".section .text",
+            ".align 16",
+            ".global exception_stubs",
+            "exception_stubs:",
+            $(
+                ".align 16",
+                concat!(".if ", $vector, " == 8 || ", $vector, " == 10 || ", $vector, " == 11 || ", $vector, " == 12 || ",
+                    $vector, " == 13 || ", $vector, " == 14 || ", $vector, " == 17 || ", $vector, " == 21 || ",
+                    $vector, " == 29 || ", $vector, " == 30"),
+                ".else",
+                "push 0",
+                ".endif",
+                concat!("push ", $vector),
+                "jmp exception_common",
+            )*
+            ".align 16",
+            "exception_common:",
+            "push rax",
+            "push rbx",
+            "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push rbp",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            "mov rdi, rsp",
+            "cld",
+            "call exception_dispatch",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rbp",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rbx",
+            "pop rax",
+            "add rsp, 16", // vector and error code
+            "iretq",
+        );
+    };
+}
+
+exception_stubs_asm!(
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31
+);
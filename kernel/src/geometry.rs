@@ -166,4 +166,96 @@ impl Rect2D {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn rect_normalizes_points() {
+        let r = Rect2D::from_points((10, 2).into(), (4, 8).into());
+        assert_eq!(r, Rect2D::from_ranges(4..10, 2..8));
+        assert_eq!(r.size(), (6, 6).into());
+    }
+
+    #[test]
+    fn bound_of_disjoint_rect_is_empty() {
+        let r = Rect2D::from_ranges(20..30, 20..30);
+        let bounded = r.bound(Rect2D::from_ranges(0..10, 0..10));
+        assert_eq!(bounded.width() * bounded.height(), 0);
+    }
+
+    fn coord() -> impl Strategy<Value = isize> {
+        -1000isize..1000
+    }
+
+    fn pos() -> impl Strategy<Value = Pos2D> {
+        (coord(), coord()).prop_map(Pos2D::from)
+    }
+
+    fn disp() -> impl Strategy<Value = Disp2D> {
+        (coord(), coord()).prop_map(Disp2D::from)
+    }
+
+    fn rect() -> impl Strategy<Value = Rect2D> {
+        (pos(), pos()).prop_map(|(p1, p2)| Rect2D::from_points(p1, p2))
+    }
+
+    proptest! {
+        #[test]
+        fn pos_disp_round_trip(p in pos(), d in disp()) {
+            prop_assert_eq!((p + d) - p, d);
+
+            let mut q = p;
+            q += d;
+            prop_assert_eq!(q, p + d);
+        }
+
+        #[test]
+        fn disp_addition_commutes(d1 in disp(), d2 in disp()) {
+            prop_assert_eq!(d1 + d2, d2 + d1);
+            prop_assert_eq!((d1 + d2).width(), (d1.dx + d2.dx).abs());
+        }
+
+        #[test]
+        fn rect_size_is_nonnegative(r in rect()) {
+            prop_assert!(r.width() >= 0 && r.height() >= 0);
+        }
+
+        /// A bounded rect lies in both the rect and the boundary.
+        #[test]
+        fn bound_is_contained(r in rect(), boundary in rect()) {
+            let bounded = r.bound(boundary);
+            prop_assert_eq!(bounded.bound(boundary), bounded);
+            prop_assert!(bounded.width() <= r.width() && bounded.height() <= r.height());
+            prop_assert!(bounded.width() <= boundary.width() && bounded.height() <= boundary.height());
+        }
+
+        /// `iterate_disp_bounded` visits the points of the rect inside the boundary, relative to the rect.
+        #[test]
+        fn iterate_disp_bounded_visits_intersection(
+            r in (pos(), 0isize..20, 0isize..20).prop_map(|(p, w, h)| Rect2D::from_points(p, p + Disp2D::from((w, h)))),
+            boundary in rect(),
+        ) {
+            let mut count = 0;
+            r.iterate_disp_bounded(boundary, |d| {
+                let p = r.ltop + d;
+                assert!(boundary.ltop.x <= p.x && p.x < boundary.rbot.x);
+                assert!(boundary.ltop.y <= p.y && p.y < boundary.rbot.y);
+                count += 1;
+            });
+
+            let bounded = r.bound(boundary);
+            prop_assert_eq!(count, bounded.width() * bounded.height());
+        }
+
+        #[test]
+        fn iterate_disp_counts_area(r in (pos(), 0isize..20, 0isize..20).prop_map(|(p, w, h)| Rect2D::from_points(p, p + Disp2D::from((w, h))))) {
+            let mut count = 0;
+            r.iterate_disp(|_| count += 1);
+            prop_assert_eq!(count, r.width() * r.height());
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::acpi::{Acpi, Fadt, MadtEntry, Signature};
+
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+
+/// The ACPI tables. None if the bootloader didn't find the RSDP, or the root table is broken.
+pub static ACPI: Mutex<OnceCell<Option<Acpi>>> = Mutex::new(OnceCell::new());
+
+/// The FADT, which is looked up once since the delays and the power functions need it.
+static FADT: Mutex<OnceCell<Option<Fadt>>> = Mutex::new(OnceCell::new());
+
+#[inline]
+pub fn init(rsdp: u64) {
+    ACPI.lock().get_or_init(|| {
+        if rsdp == 0 {
+            log::warn!("ACPI: no RSDP");
+            return None;
+        }
+
+        // the tables are in ACPI memory, which is identity-mapped and never freed.
+        let acpi = match unsafe { Acpi::from_rsdp(rsdp) } {
+            Ok(acpi) => acpi,
+            Err(err) => {
+                log::warn!("ACPI: {:?}", err);
+                return None;
+            },
+        };
+
+        log::info!("ACPI: revision {}, RSDP at {:#x}", acpi.rsdp.revision, rsdp);
+        for sdt in acpi.tables() {
+            log::debug!("ACPI: {:?}", sdt);
+        }
+        if let Some(madt) = acpi.madt() {
+            let io_apic_count = madt.entries().filter(|entry| matches!(entry, MadtEntry::IoApic { .. })).count();
+            log::info!(
+                "ACPI: {} CPUs, {} IOAPICs, LAPIC at {:#x}",
+                madt.cpu_apic_ids().count(), io_apic_count, madt.local_apic_address(),
+            );
+        }
+        if let Some(hpet) = acpi.hpet() {
+            log::info!("ACPI: HPET at {:#x}", hpet.base.address);
+        }
+        for entry in acpi.mcfg().iter().flat_map(|mcfg| mcfg.entries()) {
+            log::info!("ACPI: ECAM at {:#x} for buses {}..={}", entry.base, entry.start_bus, entry.end_bus);
+        }
+        if acpi.fadt().is_none() {
+            log::warn!("ACPI: no valid {}", Signature(Fadt::SIGNATURE));
+        }
+
+        Some(acpi)
+    });
+    FADT.lock().get_or_init(|| acpi().and_then(|acpi| acpi.fadt()));
+}
+
+/// The ACPI tables, if found.
+pub fn acpi() -> Option<Acpi> {
+    ACPI.lock().get().copied().flatten()
+}
+
+/// The FADT, if found.
+pub fn fadt() -> Option<Fadt> {
+    FADT.lock().get().copied().flatten()
+}
+
+/// Busy-wait with the ACPI PM timer, or the TSC without it.
+pub fn delay_us(us: u64) {
+    match fadt().and_then(|fadt| fadt.pm_timer) {
+        Some(timer) => timer.delay_us(us),
+        None => if !super::logger::tsc_delay_us(us) {
+            // no timer at all. this is a guess of about 1ns per iteration, which may be far off.
+            (0..us * 1000).for_each(|_| core::hint::spin_loop());
+        },
+    }
+}
@@ -2,29 +2,45 @@
 extern crate alloc;
 
 use crate::pgmgr::KERNEL_PAGE_SIZE;
-use crate::allocator::GlobalHeap;
+use crate::allocator::{GlobalHeap, HeapStats};
+use crate::dma::{DmaAllocator, DmaConstraints};
 
 use super::pgmgr::PAGE_MANAGER;
 
-#[global_allocator]
-static GLOBAL_HEAP: GlobalHeap = GlobalHeap::empty();
+#[cfg_attr(not(test), global_allocator)] // host tests use the system allocator
+static GLOBAL_HEAP: GlobalHeap = GlobalHeap::empty(&PAGE_MANAGER);
 
-/// Heap frame count. set to 32 * 2MB.
-const HEAP_FRAME_CNT: usize = 32 * 512; // 64 * 512 failed on QEMU
+/// Initial heap frame count. set to 2 * 2MB.
+/// The heap grows itself when exhausted.
+const HEAP_INIT_FRAME_CNT: usize = 2 * 512;
 
 pub fn init() {
     unsafe {
         let heap_bottom = PAGE_MANAGER.lock()
-            .allocate(HEAP_FRAME_CNT).unwrap()
+            .allocate(HEAP_INIT_FRAME_CNT).unwrap()
             .addr() as *mut u8;
 
         log::info!("Heap bottom {:?}", heap_bottom);
         
-        GLOBAL_HEAP.init(heap_bottom, HEAP_FRAME_CNT * KERNEL_PAGE_SIZE);
+        GLOBAL_HEAP.init(heap_bottom, HEAP_INIT_FRAME_CNT * KERNEL_PAGE_SIZE);
     }
 }
 
+/// Returns the current heap statistics.
+pub fn heap_stats() -> HeapStats {
+    GLOBAL_HEAP.stats()
+}
+
 /// Create an instance of global allocator.
 pub fn global_allocator() -> alloc::alloc::Global {
     alloc::alloc::Global
-}
\ No newline at end of file
+}
+
+/// DMA buffers for device drivers.
+/// xHCI takes every object from here, as device entries and class drivers embed their transfer buffers.
+pub static DMA_ALLOCATOR: DmaAllocator = DmaAllocator::new(&PAGE_MANAGER, DmaConstraints::XHCI);
+
+/// Create an instance of DMA allocator.
+pub fn dma_allocator() -> &'static DmaAllocator {
+    &DMA_ALLOCATOR
+}
@@ -4,11 +4,11 @@ use crate::console::Console;
 
 use core::fmt::{Arguments, Write};
 use core::cell::OnceCell;
-use spin::mutex::Mutex;
+use crate::sync::CpuMutex;
 
 use x86_64::instructions::interrupts::without_interrupts;
 
-pub static CONSOLE: Mutex<OnceCell<Console>> = Mutex::new(OnceCell::new());
+pub static CONSOLE: CpuMutex<OnceCell<Console>> = CpuMutex::new(OnceCell::new());
 
 /// Init [`CONSOLE`].
 /// Should be called after initializing screen.
@@ -21,10 +21,30 @@ pub fn init(){
 
 pub fn _console_print(args: Arguments){
     without_interrupts(|| {
-        CONSOLE.lock().get_mut().unwrap().write_fmt(args).unwrap();
+        // the console locks the screen while writing,
+        // so a CPU already holding either of them falls back to the emergency path.
+        if super::SCREEN.held_by_current_cpu() {
+            return super::serial::emergency_print(args);
+        }
+        match CONSOLE.lock_checked() {
+            Ok(mut console) => match console.get_mut() {
+                Some(console) => { console.write_fmt(args).unwrap(); },
+                None => super::serial::emergency_print(args), // not initialized yet
+            },
+            Err(_) => super::serial::emergency_print(args),
+        }
     });
 }
 
+/// Release the console and the screen, regardless of their holders.
+///
+/// # Safety
+/// Only for panics, where the holders never continue.
+pub unsafe fn force_unlock() {
+    CONSOLE.force_unlock();
+    super::SCREEN.force_unlock();
+}
+
 #[macro_export]
 macro_rules! console_print {
     ($($arg:tt)*) => ($crate::globals::console::_console_print(format_args!($($arg)*)));
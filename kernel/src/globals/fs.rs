@@ -0,0 +1,44 @@
+use crate::tarfs::TarFs;
+
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+
+use shared::Initrd;
+
+/// The root filesystem, which is the initial ramdisk mounted read-only.
+/// None if the bootloader didn't pass an initrd, or it is not a valid archive.
+pub static ROOT_FS: Mutex<OnceCell<Option<TarFs<'static>>>> = Mutex::new(OnceCell::new());
+
+#[inline]
+pub fn init(initrd: Initrd) {
+    ROOT_FS.lock().get_or_init(|| {
+        if initrd.base.is_null() {
+            log::info!("no initrd");
+            return None;
+        }
+
+        // the archive is on loader data pages, which are never freed.
+        let data = unsafe { core::slice::from_raw_parts(initrd.base, initrd.size) };
+        match TarFs::new(data) {
+            Ok(fs) => {
+                log::info!("initrd: {} entries, {} bytes", fs.entries().count(), fs.size());
+                Some(fs)
+            },
+            Err(err) => {
+                log::warn!("initrd is broken: {:?}", err);
+                None
+            },
+        }
+    });
+}
+
+/// The root filesystem, if mounted.
+/// The filesystem is read-only and borrows nothing from the lock, so it is copied out.
+pub fn root() -> Option<TarFs<'static>> {
+    ROOT_FS.lock().get().copied().flatten()
+}
+
+/// The contents of a file in the root filesystem.
+pub fn read(path: &str) -> Option<&'static [u8]> {
+    root()?.read(path)
+}
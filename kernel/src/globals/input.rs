@@ -0,0 +1,15 @@
+use crate::xhci::class::{KeyboardReport, MouseReport};
+
+// input events from every source (xHCI, PS/2) end up here, so the rest of the kernel doesn't care where they come from.
+
+pub fn keyboard(report: KeyboardReport) {
+    log::debug!("Keyboard Report) modifier : {}", report.modifier);
+
+    super::shell::keyboard(&report);
+}
+
+pub fn mouse(report: MouseReport) {
+    log::debug!("Mouse Report) {}, {:?}", report.buttons, report.disp);
+
+    super::SCREEN.lock().get_mut().unwrap().move_cursor(report.disp.into());
+}
@@ -1,59 +1,288 @@
-
-use super::{APIC, MSG_QUEUE};
+use super::APIC;
+use super::segments::{DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX};
 
 use x86_64::structures::idt::{
+    Entry,
+    HandlerFunc,
     InterruptDescriptorTable,
     InterruptStackFrame,
     PageFaultErrorCode
 };
 use x86_64::registers::control::Cr2;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::VirtAddr;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::mutex::Mutex;
+
+use crate::exception::{
+    self,
+    ExceptionContext,
+    ExceptionDump,
+};
+use crate::paging::{PageFaultReport, RegionKind, MAX_INSTRUCTION_LEN};
+use crate::ioapic::{PIC_SPURIOUS_MASTER, PIC_SPURIOUS_SLAVE};
+use crate::vectors::VectorAllocator;
 
-// pub const IDT_VEC_BP: usize = 0x03;
-// pub const IDT_VEC_PF: usize = 0x0E;
-pub const IDT_VEC_XHCI: usize = 0x40;
+pub const IDT_VEC_DF: u8 = 0x08;
+pub const IDT_VEC_BP: u8 = 0x03;
+pub const IDT_VEC_PF: u8 = 0x0E;
+pub const IDT_VEC_NMI: u8 = 0x02;
+pub const IDT_VEC_CP: u8 = 0x15;
+pub const IDT_VEC_COM1: usize = 0x42;
+pub const IDT_VEC_PS2_KEYBOARD: usize = 0x43;
+pub const IDT_VEC_PS2_MOUSE: usize = 0x44;
 // const IDT_VEC_LAPIC_TIMER: usize = 0x41;
+pub const IDT_VEC_SPURIOUS: usize = 0xFF;
+
+// vectors handed out at runtime, by `allocate_vector`.
+const IDT_VEC_DYNAMIC_FIRST: u8 = 0x50;
+const IDT_VEC_DYNAMIC_LAST: u8 = 0xEF;
 
 // This is static to make its lifetime `'static`.
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
+/// The number of qwords dumped from the interrupted stack.
+const STACK_DUMP_QWORDS: usize = 16;
+
 #[inline]
 pub fn init(){
     unsafe {
-        IDT.breakpoint.set_handler_fn(breakpoint_handler);
-        IDT.page_fault.set_handler_fn(page_fault_handler);
-        IDT[IDT_VEC_XHCI]
-            .set_handler_fn(xhci_handler)
+        // every architectural exception enters through its stub.
+        // reserved vectors are never raised, and #CP is set below.
+        macro_rules! set_stubs {
+            ($($field:ident = $vector:expr),* $(,)?) => {
+                $( IDT.$field.set_handler_addr(exception::stub_addr($vector)); )*
+            };
+        }
+        set_stubs!(
+            divide_error = 0,
+            debug = 1,
+            non_maskable_interrupt = IDT_VEC_NMI,
+            breakpoint = IDT_VEC_BP,
+            overflow = 4,
+            bound_range_exceeded = 5,
+            invalid_opcode = 6,
+            device_not_available = 7,
+            invalid_tss = 10,
+            segment_not_present = 11,
+            stack_segment_fault = 12,
+            general_protection_fault = 13,
+            x87_floating_point = 16,
+            alignment_check = 17,
+            machine_check = 18,
+            simd_floating_point = 19,
+            virtualization = 20,
+            vmm_communication_exception = 29,
+            security_exception = 30,
+        );
+        IDT.double_fault
+            .set_handler_addr(exception::stub_addr(IDT_VEC_DF))
+            .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        IDT.page_fault
+            .set_handler_addr(exception::stub_addr(IDT_VEC_PF))
+            .set_stack_index(PAGE_FAULT_IST_INDEX);
+        // the IDT of x86_64 0.14 counts #CP as reserved, so its entry is reached by the offset. (16 bytes each)
+        let cp = (core::ptr::addr_of_mut!(IDT) as *mut Entry<HandlerFunc>).add(IDT_VEC_CP as usize);
+        (*cp).set_handler_addr(exception::stub_addr(IDT_VEC_CP));
+
+        IDT[IDT_VEC_COM1]
+            .set_handler_fn(super::serial::com1_handler)
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+        ;
+        IDT[IDT_VEC_PS2_KEYBOARD]
+            .set_handler_fn(super::ps2::keyboard_handler)
             .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
         ;
+        IDT[IDT_VEC_PS2_MOUSE]
+            .set_handler_fn(super::ps2::mouse_handler)
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+        ;
+        IDT[IDT_VEC_SPURIOUS]
+            .set_handler_fn(spurious_handler)
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+        ;
+        IDT[PIC_SPURIOUS_MASTER as usize]
+            .set_handler_fn(spurious_handler)
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+        ;
+        IDT[PIC_SPURIOUS_SLAVE as usize]
+            .set_handler_fn(pic_spurious_slave_handler)
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+        ;
+        // the stubs are installed once, and `allocate_vector` only sets their handlers.
+        for (i, &stub) in VECTOR_STUBS.iter().flatten().enumerate() {
+            IDT[IDT_VEC_DYNAMIC_FIRST as usize + i]
+                .set_handler_fn(stub)
+                .set_privilege_level(x86_64::PrivilegeLevel::Ring0)
+            ;
+        }
         IDT.load();
     }
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    log::info!("Breakpoint occured");
-    log::info!("{:#?}", stack_frame);
+/// Load the IDT on an application processor. The IDT is shared by every CPU.
+pub fn load() {
+    unsafe { IDT.load(); }
+}
+
+/// Read the top of the interrupted stack, if it is mapped.
+fn read_stack(sp: VirtAddr) -> Option<[u64; STACK_DUMP_QWORDS]> {
+    let last = sp + (8 * STACK_DUMP_QWORDS - 1);
+    if !sp.is_aligned(8u64) || !super::paging::is_readable(sp) || !super::paging::is_readable(last) {
+        return None;
+    }
+    Some(unsafe { (sp.as_u64() as *const [u64; STACK_DUMP_QWORDS]).read() })
+}
+
+/// Log the register and stack dump.
+fn dump(ctx: &ExceptionContext) {
+    let stack = read_stack(ctx.frame.stack_pointer);
+    log::error!("{}", ExceptionDump {
+        context: ctx,
+        stack: stack.as_ref().map(|qwords| &qwords[..]),
+    });
+    log::error!("{}", super::symbols::backtrace(Some(ctx.frame.instruction_pointer), ctx.rbp));
+}
+
+/// The common exception handler, called by the entry stubs.
+#[no_mangle]
+extern "sysv64" fn exception_dispatch(ctx: &mut ExceptionContext) {
+    match ctx.vector() {
+        IDT_VEC_BP => {
+            log::info!("Breakpoint occured");
+            dump(ctx);
+        },
+        IDT_VEC_NMI => {
+            // NMIs come from hardware errors or watchdogs, and the interrupted code can go on.
+            log::warn!("NMI at {:#018x}", ctx.frame.instruction_pointer.as_u64());
+        },
+        IDT_VEC_PF => page_fault_handler(ctx),
+        IDT_VEC_DF => double_fault_handler(ctx),
+        _ => {
+            dump(ctx);
+            let (mnemonic, name) = exception::exception_name(ctx.vector());
+            panic!("Unhandled Exception {} {}", mnemonic, name);
+        },
+    }
+}
+
+fn page_fault_handler(ctx: &mut ExceptionContext) {
+    let addr = Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(ctx.error_code);
+
+    // faults on lazy regions are resolved here, and the faulting instruction is retried.
+    let cause = match super::paging::handle_page_fault(addr, error_code) {
+        Ok(()) => return,
+        Err(cause) => cause,
+    };
+
+    let rip = ctx.frame.instruction_pointer;
+    let instruction = if super::paging::is_readable(rip) && super::paging::is_readable(rip + MAX_INSTRUCTION_LEN) {
+        Some(unsafe {
+            (rip.as_u64() as *const [u8; MAX_INSTRUCTION_LEN]).read_unaligned()
+        })
+    } else { None };
+
+    let report = PageFaultReport {
+        addr,
+        error_code,
+        stack_frame: &ctx.frame,
+        region: super::paging::find_region(addr),
+        cause,
+        instruction,
+    };
+    log::error!("{}", report);
+    dump(ctx);
+
+    // Every code runs on behalf of the kernel itself for now, so there's no task to kill instead.
+    panic!("Unresolved Page Fault");
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode
-) {
-    log::error!("Exception: Page Fault");
-    log::error!("Accessed Address: {:?}", Cr2::read());
+fn double_fault_handler(ctx: &mut ExceptionContext) -> ! {
+    // a double fault is most likely caused by a page fault which couldn't be delivered,
+    // so the last page fault address tells what happened.
+    let addr = Cr2::read();
+    match super::paging::find_region(addr) {
+        Some(region) if region.kind == RegionKind::Guard => {
+            log::error!("Kernel stack overflow in \"{}\" (accessed {:#018x})", region.name, addr.as_u64());
+        },
+        _ => {
+            log::error!("Last page fault address {:#018x}", addr.as_u64());
+        },
+    }
+    dump(ctx);
+
+    panic!("Double Fault");
+}
 
-    log::error!("Error Code: {:?}", error_code);
-    log::error!("{:#?}", stack_frame);
+/// Spurious interrupts need no EOI.
+extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {}
 
-    panic!("Page Fault Handling not implemented");
+/// A spurious IRQ of the slave PIC. The master saw a real cascade IRQ, which needs its EOI.
+extern "x86-interrupt" fn pic_spurious_slave_handler(_stack_frame: InterruptStackFrame) {
+    unsafe { crate::ioapic::pic_cascade_eoi() };
 }
 
-extern "x86-interrupt" fn xhci_handler(_stack_frame: InterruptStackFrame) {
-    MSG_QUEUE.enqueue(
-        crate::message::Message::XHCIInterrupt
-    )
-        .expect("Message Queue Full");
+/// A device interrupt handler, called with its vector on the CPU the message was sent to.
+/// The EOI is signaled after it returns.
+pub type VectorHandler = fn(u8);
+
+static VECTORS: Mutex<VectorAllocator> = Mutex::new(VectorAllocator::new(IDT_VEC_DYNAMIC_FIRST..=IDT_VEC_DYNAMIC_LAST));
 
-    APIC.end_of_interrupt().signal(); // Do we really need this?
+/// The handlers of the dynamic vectors, as addresses. Zero if the vector is free.
+static HANDLERS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+extern "x86-interrupt" fn vector_stub<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    let handler = HANDLERS[VECTOR as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: VectorHandler = unsafe { core::mem::transmute(handler) };
+        handler(VECTOR);
+    }
+    APIC.end_of_interrupt().signal();
+}
+
+type VectorStub = extern "x86-interrupt" fn(InterruptStackFrame);
+
+// a row of 16 stubs, from `$hi * 16`.
+macro_rules! vector_stub_row {
+    ($hi:literal) => {
+        [
+            vector_stub::<{ $hi * 16 }>, vector_stub::<{ $hi * 16 + 1 }>,
+            vector_stub::<{ $hi * 16 + 2 }>, vector_stub::<{ $hi * 16 + 3 }>,
+            vector_stub::<{ $hi * 16 + 4 }>, vector_stub::<{ $hi * 16 + 5 }>,
+            vector_stub::<{ $hi * 16 + 6 }>, vector_stub::<{ $hi * 16 + 7 }>,
+            vector_stub::<{ $hi * 16 + 8 }>, vector_stub::<{ $hi * 16 + 9 }>,
+            vector_stub::<{ $hi * 16 + 10 }>, vector_stub::<{ $hi * 16 + 11 }>,
+            vector_stub::<{ $hi * 16 + 12 }>, vector_stub::<{ $hi * 16 + 13 }>,
+            vector_stub::<{ $hi * 16 + 14 }>, vector_stub::<{ $hi * 16 + 15 }>,
+        ]
+    };
 }
 
+/// The entries of `IDT_VEC_DYNAMIC_FIRST..=IDT_VEC_DYNAMIC_LAST`.
+static VECTOR_STUBS: [[VectorStub; 16]; 10] = [
+    vector_stub_row!(0x5), vector_stub_row!(0x6), vector_stub_row!(0x7), vector_stub_row!(0x8), vector_stub_row!(0x9),
+    vector_stub_row!(0xA), vector_stub_row!(0xB), vector_stub_row!(0xC), vector_stub_row!(0xD), vector_stub_row!(0xE),
+];
 
+/// Allocate a vector for the handler. None if every dynamic vector is in use.
+pub fn allocate_vector(handler: VectorHandler) -> Option<u8> {
+    allocate_vectors(1, handler)
+}
+
+/// Allocate `count` vectors for the handler, aligned to `count`, which should be a power of two.
+/// Returns the first vector.
+pub fn allocate_vectors(count: u8, handler: VectorHandler) -> Option<u8> {
+    let first = without_interrupts(|| VECTORS.lock().allocate_block(count))?;
+    for vector in first..=first + (count - 1) {
+        HANDLERS[vector as usize].store(handler as usize, Ordering::Release);
+    }
+    Some(first)
+}
+
+/// Return a vector. The device should not send it anymore.
+pub fn free_vector(vector: u8) {
+    HANDLERS[vector as usize].store(0, Ordering::Release);
+    without_interrupts(|| VECTORS.lock().free(vector));
+}
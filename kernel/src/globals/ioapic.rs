@@ -0,0 +1,86 @@
+use crate::acpi::{Madt, MadtEntry};
+use crate::ioapic::{self, IoApic, IsaRoute, Polarity, RedirectionEntry, TriggerMode};
+
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+
+use super::acpi::acpi;
+
+/// The maximum number of I/O APICs. Most machines have one.
+const MAX_IOAPICS: usize = 8;
+
+/// I/O APICs from the MADT. Empty if there's no MADT.
+pub static IOAPICS: Mutex<OnceCell<heapless::Vec<IoApic, MAX_IOAPICS>>> = Mutex::new(OnceCell::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteError {
+    /// No I/O APIC delivers the GSI.
+    NoIoApic(u32),
+}
+
+/// Mask the 8259 PICs, and every entry of the I/O APICs.
+#[inline]
+pub fn init() {
+    // UEFI leaves the PICs masked usually, but they're remapped anyway,
+    // so that a spurious IRQ hits the handlers for it instead of an exception.
+    unsafe { ioapic::disable_pic() };
+
+    IOAPICS.lock().get_or_init(|| {
+        let mut ioapics = heapless::Vec::new();
+        let Some(madt) = acpi().and_then(|acpi| acpi.madt()) else {
+            log::warn!("IOAPIC: no MADT, legacy IRQs are not delivered");
+            return ioapics;
+        };
+
+        for entry in madt.entries() {
+            let MadtEntry::IoApic { id, address, gsi_base } = entry else { continue };
+            let mut ioapic = unsafe { IoApic::new(id, address as usize, gsi_base) };
+            ioapic.mask_all();
+            log::info!(
+                "IOAPIC: id {} at {:#x}, GSI {}..{}",
+                id, address, gsi_base, gsi_base + ioapic.entry_count,
+            );
+            if ioapics.push(ioapic).is_err() {
+                log::warn!("IOAPIC: too many IOAPICs, ignoring id {}", id);
+            }
+        }
+        ioapics
+    });
+}
+
+/// Route a GSI to the vector on the LAPIC, and unmask it.
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    apic_id: u8,
+    trigger: TriggerMode,
+    polarity: Polarity,
+) -> Result<(), RouteError> {
+    let entry = RedirectionEntry { vector, polarity, trigger, masked: false, destination: apic_id };
+    with_ioapic(gsi, |ioapic, index| ioapic.set_entry(index, entry))
+}
+
+/// Route an ISA IRQ to the vector on the LAPIC, applying the interrupt source overrides. Returns the GSI.
+pub fn route_isa_irq(irq: u8, vector: u8, apic_id: u8) -> Result<u32, RouteError> {
+    let madt = acpi().and_then(|acpi| acpi.madt());
+    let IsaRoute { gsi, polarity, trigger } = IsaRoute::resolve(irq, madt.iter().flat_map(Madt::entries));
+    route(gsi, vector, apic_id, trigger, polarity)?;
+    Ok(gsi)
+}
+
+pub fn mask(gsi: u32) -> Result<(), RouteError> {
+    with_ioapic(gsi, |ioapic, index| {
+        let entry = ioapic.entry(index);
+        ioapic.set_entry(index, RedirectionEntry { masked: true, ..entry });
+    })
+}
+
+fn with_ioapic(gsi: u32, f: impl FnOnce(&mut IoApic, u32)) -> Result<(), RouteError> {
+    let mut ioapics = IOAPICS.lock();
+    let ioapic = ioapics.get_mut()
+        .and_then(|ioapics| ioapics.iter_mut().find(|ioapic| ioapic.handles(gsi)))
+        .ok_or(RouteError::NoIoApic(gsi))?;
+    let index = gsi - ioapic.gsi_base;
+    f(ioapic, index);
+    Ok(())
+}
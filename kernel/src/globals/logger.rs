@@ -1,27 +1,159 @@
-/// The console logger.
+use crate::logger::{level_filter, LevelFilters, LineBuf, LogLine, LogRing, LogSink, TscClock};
 
-static LOGGER: Logger = Logger { filter: log::LevelFilter::Info };
+use core::fmt::Write;
+use spin::mutex::Mutex;
 
+use log::LevelFilter;
+use shared::LogLevel;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// The kernel logger.
+static LOGGER: Logger = Logger::new(LevelFilter::Info);
+
+/// The number of records kept in the ring.
+const RING_SIZE: usize = 256;
+const MAX_SINKS: usize = 4;
+
+/// Initialize the logger, with the default level chosen in the boot menu.
 #[inline]
-pub fn init() {
+pub fn init(level: LogLevel) {
+    LOGGER.clock.init();
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(LOGGER.filter);
+    set_default_level(level_filter(level));
+
+    register_sink(&ConsoleSink);
+    register_sink(&SerialSink);
 }
 
 pub struct Logger {
-    pub filter: log::LevelFilter,
+    filters: Mutex<LevelFilters>,
+    ring: LogRing<RING_SIZE>,
+    clock: TscClock,
+    sinks: Mutex<heapless::Vec<&'static dyn LogSink, MAX_SINKS>>,
+}
+
+impl Logger {
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            filters: Mutex::new(LevelFilters::new(default)),
+            ring: LogRing::new(),
+            clock: TscClock::new(),
+            sinks: Mutex::new(heapless::Vec::new()),
+        }
+    }
 }
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.filter
+        // if the filters are being changed, fall back to the global max level.
+        let level = self.filters.try_lock()
+            .map_or(log::max_level(), |filters| filters.level_for(metadata.target()));
+        metadata.level() <= level
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            crate::console_println!("[{}] {}", record.level(), record.args());
+        if !self.enabled(record.metadata()) { return; }
+
+        let timestamp = self.clock.now();
+        let mut text = LineBuf::new();
+        let _ = write!(text, "{}", record.args());
+
+        let seq = self.ring.push(timestamp, record.level(), text.as_str());
+        let line = LogLine {
+            seq,
+            timestamp,
+            level: record.level(),
+            target: record.target(),
+            text: text.as_str(),
+        };
+
+        // sinks are only registered at init, so this fails only if a record is logged in the middle of it.
+        if let Some(sinks) = self.sinks.try_lock() {
+            for sink in sinks.iter() {
+                sink.write(&line);
+            }
         }
     }
 
     fn flush(&self) { }
-}
\ No newline at end of file
+}
+
+/// Add a sink. Returns false if there are too many sinks.
+pub fn register_sink(sink: &'static dyn LogSink) -> bool {
+    without_interrupts(|| LOGGER.sinks.lock().push(sink).is_ok())
+}
+
+/// Set the level of a module (e.g. `kernel::xhci`) and its submodules.
+/// Returns None if there are too many module filters.
+pub fn set_module_level(module: &str, level: LevelFilter) -> Option<()> {
+    without_interrupts(|| {
+        let mut filters = LOGGER.filters.lock();
+        filters.set(module, level)?;
+        log::set_max_level(filters.max_level());
+        Some(())
+    })
+}
+
+/// Make the module follow its parent's level again.
+pub fn unset_module_level(module: &str) {
+    without_interrupts(|| {
+        let mut filters = LOGGER.filters.lock();
+        filters.unset(module);
+        log::set_max_level(filters.max_level());
+    })
+}
+
+/// Set the level of modules without their own filter.
+pub fn set_default_level(level: LevelFilter) {
+    without_interrupts(|| {
+        let mut filters = LOGGER.filters.lock();
+        filters.set_default(level);
+        log::set_max_level(filters.max_level());
+    })
+}
+
+/// Busy-wait with the TSC clock of the timestamps. Returns false if its frequency is unknown.
+pub fn tsc_delay_us(us: u64) -> bool {
+    LOGGER.clock.delay_us(us)
+}
+
+/// Visit the records kept in the ring, from the oldest. (dmesg)
+pub fn for_each_record(f: impl FnMut(&LogLine)) {
+    LOGGER.ring.for_each(LOGGER.clock.now().ticks_per_us, f)
+}
+
+/// Writes records to the screen console.
+/// Another CPU using the console is waited for, but a record is dropped if this CPU is using it,
+/// e.g. when logging from an interrupt handler.
+pub struct ConsoleSink;
+
+impl LogSink for ConsoleSink {
+    fn name(&self) -> &'static str { "console" }
+
+    fn write(&self, line: &LogLine) -> bool {
+        without_interrupts(|| {
+            // the console locks the screen while writing, so check it first.
+            if super::SCREEN.held_by_current_cpu() { return false; }
+            let Ok(mut console) = super::CONSOLE.lock_checked() else { return false };
+            let Some(console) = console.get_mut() else { return false };
+
+            writeln!(console, "{}", line).is_ok()
+        })
+    }
+}
+
+/// Writes records to COM1. Like the console, a record is dropped only if this CPU is using the port.
+pub struct SerialSink;
+
+impl LogSink for SerialSink {
+    fn name(&self) -> &'static str { "serial" }
+
+    fn write(&self, line: &LogLine) -> bool {
+        without_interrupts(|| {
+            let Ok(mut com1) = super::serial::COM1.lock_checked() else { return false };
+            let Some(port) = com1.get_mut() else { return false };
+
+            writeln!(port, "{}", line).is_ok()
+        })
+    }
+}
@@ -1,17 +1,31 @@
 
+pub mod acpi;
 pub mod apic;
 
+pub mod serial;
+
 pub mod screen;
 pub mod console;
 pub mod logger;
+pub mod symbols;
+pub mod fs;
 
+pub mod stacks;
 pub mod segments;
 pub mod paging;
 pub mod pgmgr;
 pub mod allocator;
 
 pub mod interrupts;
+pub mod msi;
+pub mod smp;
+pub mod ioapic;
+pub mod input;
+pub mod ps2;
+pub mod pci;
 pub mod xhci;
+pub mod power;
+pub mod shell;
 
 pub mod message;
 
@@ -24,20 +38,33 @@ pub fn init(
     mmap: MemoryMap<'static>,
     args: KernelArgs
 ){
+    serial::init(); // COM1 first, to capture everything.
+
     // MMIO frame buffer and basic console, logging.
     screen::init(args.gop_frame_buffer, args.gop_mode_info);
     console::init(); // console depends on screen
-    logger::init(); // logger depends on console
+    logger::init(args.options.log_level); // logger depends on console
+    log::info!("booted {}", args.options.kernel_name());
+    symbols::init(args.symbols, args.image);
+    fs::init(args.initrd); // mount the initrd as the root filesystem.
 
     // paging and memory.
-    segments::init(); // load GDT and set segment registers.
+    segments::init(); // load GDT and TSS, and set segment registers.
     paging::init(); // load the identity(kernel) page table.
     pgmgr::init(&mmap);
+    stacks::init(); // unmap guard pages. this depends on page manager.
     allocator::init(); // allocator depends on page manager.
 
     // interrupts and peripharals.
+    acpi::init(args.rsdp); // ACPI tables are in ACPI memory, which the page manager doesn't touch.
     interrupts::init(); // load IDT. actuall interrupts should occur AFTER xhci controller is set.
-    xhci::init(); // xHCI depends on allocation
+    smp::init(); // per-CPU data, and start the APs. this depends on ACPI, the heap and the IDT.
+    ioapic::init(); // mask the 8259 PICs and the IOAPICs. this depends on ACPI.
+    serial::route_irq(); // COM1 IRQ to `IDT_VEC_COM1`.
+    ps2::init(); // PS/2 keyboard and mouse, the fallback input if xHCI fails.
+    pci::init(); // enumerate the buses once, before any driver touches the devices.
+    pci::register_driver(&xhci::DRIVER);
+    pci::probe_all(); // bind the drivers. xHCI depends on allocation.
 
     x86_64::instructions::interrupts::enable();
 }
@@ -0,0 +1,70 @@
+extern crate alloc;
+
+use crate::msi::{MsiMessage, Msix, MsixError};
+use crate::pci::PciDevice;
+
+use alloc::vec::Vec;
+
+use super::interrupts::{allocate_vector, free_vector, VectorHandler};
+use super::smp::CPUS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MsiError {
+    Msix(MsixError),
+    /// More CPUs than the table has entries.
+    TooManyEntries,
+    /// The CPU (by index) is not online, or its APIC ID doesn't fit in a message.
+    NoCpu(usize),
+    NoVector,
+}
+
+impl From<MsixError> for MsiError {
+    fn from(err: MsixError) -> Self {
+        Self::Msix(err)
+    }
+}
+
+/// The APIC ID of the CPU, for the message destination.
+fn apic_id(cpu: usize) -> Option<u8> {
+    let cpus = CPUS.lock();
+    cpus.get(cpu).and_then(|cpu| u8::try_from(cpu.apic_id).ok())
+}
+
+/// Turn MSI-X on, with entry `i` delivering a new vector to `cpus[i]`, and the rest masked.
+/// Returns the vectors, by entry. The handler gets the vector, to tell the entries apart.
+pub fn enable_msix(device: &PciDevice, cpus: &[usize], handler: VectorHandler) -> Result<Vec<u8>, MsiError> {
+    let access = super::pci::config_access();
+    let mut msix = Msix::new(&access, device)?;
+    if cpus.len() > msix.table_size as usize { return Err(MsiError::TooManyEntries); }
+
+    let mut messages = Vec::with_capacity(cpus.len());
+    for &cpu in cpus {
+        let message = apic_id(cpu).ok_or(MsiError::NoCpu(cpu)).and_then(|apic_id| {
+            let vector = allocate_vector(handler).ok_or(MsiError::NoVector)?;
+            Ok(MsiMessage::fixed(apic_id, vector))
+        });
+        match message {
+            Ok(message) => messages.push(message),
+            Err(err) => {
+                messages.iter().for_each(|message| free_vector(message.data as u8));
+                return Err(err);
+            },
+        }
+    }
+
+    // the table is in MMIO, which is identity-mapped.
+    unsafe {
+        msix.enable(&access, device);
+        for (index, &message) in messages.iter().enumerate() {
+            msix.set_entry(index as u16, message, false);
+        }
+    }
+    log::info!("MSI-X: {} enabled with {:?}", device, messages);
+    Ok(messages.iter().map(|message| message.data as u8).collect())
+}
+
+/// Turn MSI and MSI-X off, and free the vectors. For a driver which fails after enabling them.
+pub fn disable(device: &PciDevice, vectors: &[u8]) {
+    unsafe { crate::msi::disable(&super::pci::config_access(), device) };
+    vectors.iter().for_each(|&vector| free_vector(vector));
+}
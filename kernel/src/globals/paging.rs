@@ -1,5 +1,6 @@
 use core::mem::MaybeUninit;
 use core::ptr::addr_of;
+use core::cell::OnceCell;
 
 use x86_64::structures::paging::{
     page_table::{
@@ -8,11 +9,30 @@ use x86_64::structures::paging::{
         PageTableFlags,
     },
     frame::PhysFrame,
+    OffsetPageTable,
 };
-use x86_64::PhysAddr;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::{PhysAddr, VirtAddr};
 use x86_64::registers::control::{
     Cr3, Cr3Flags
 };
+use x86_64::instructions::tlb;
+
+use crate::paging::{
+    Area,
+    Region,
+    RegionKind,
+    RegionTable,
+    FaultCause,
+};
+use crate::sync::{CpuMutex, LockError};
+use crate::pgmgr::{
+    GB,
+    KERNEL_PAGE_SIZE,
+    Result as PageResult,
+};
+
+use super::pgmgr::PAGE_MANAGER;
 
 // an identity page mapping tables.
 // we have one PML4 table, (using only index `0` -> PML3 table)
@@ -27,6 +47,13 @@ static mut ID_PML2_ARR: MaybeUninit<[PageTable; ID_PD_CNT]> = MaybeUninit::zeroe
 
 // [PageTable::new(); ID_PD_CNT];
 
+/// The mapper for the areas beyond the identity mapping.
+/// Since the identity mapping covers the page table frames, the physical memory offset is zero.
+pub static MAPPER: CpuMutex<OnceCell<OffsetPageTable<'static>>> = CpuMutex::new(OnceCell::new());
+
+/// The lazily backed regions.
+pub static REGIONS: CpuMutex<RegionTable> = CpuMutex::new(RegionTable::new());
+
 macro_rules! phys_addr {
     ($it:expr) => {
         PhysAddr::new(addr_of!($it) as usize as u64)
@@ -63,5 +90,129 @@ pub fn init() {
             Cr3Flags::empty()
         );
     }
+
+    MAPPER.lock().get_or_init(|| unsafe {
+        OffsetPageTable::new(&mut ID_PML4, VirtAddr::zero())
+    });
+}
+
+/// Returns true if the address is covered by the identity mapping.
+pub fn is_identity_mapped(addr: VirtAddr) -> bool {
+    addr.as_u64() < (ID_PD_CNT * GB) as u64
+}
+
+/// Returns true if the page containing the address is mapped, hence reading it would not fault.
+/// This gives up if the page tables are locked.
+pub fn is_readable(addr: VirtAddr) -> bool {
+    use x86_64::structures::paging::mapper::Translate;
+
+    if is_identity_mapped(addr) {
+        // the only holes in the identity mapping are guard pages.
+        REGIONS.try_lock().map_or(false, |regions| {
+            !matches!(regions.find(addr), Some(region) if region.kind == RegionKind::Guard)
+        })
+    } else {
+        MAPPER.try_lock()
+            .and_then(|cell| cell.get().and_then(|mapper| mapper.translate_addr(addr)))
+            .is_some()
+    }
+}
+
+/// Reserve a lazily backed region in the given area.
+/// Frames are mapped on the first access to each page.
+pub fn reserve_lazy(area: Area, name: &'static str, page_cnt: usize) -> Option<Region> {
+    REGIONS.lock().reserve(
+        area,
+        name,
+        RegionKind::Lazy,
+        page_cnt,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+    )
+}
+
+/// Reserve a lazily backed stack with guard pages, in the task stack area.
+pub fn reserve_task_stack(name: &'static str, page_cnt: usize) -> Option<Region> {
+    REGIONS.lock().reserve_guarded(
+        Area::TaskStacks,
+        name,
+        RegionKind::Lazy,
+        page_cnt,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+    )
+}
+
+/// Unmap a page in the identity mapping, and register it as a guard page.
+///
+/// The huge page containing it is split into 4KB pages first,
+/// with the new page table frame taken from the page manager.
+pub fn unmap_guard_page(name: &'static str, addr: VirtAddr) -> PageResult<()> {
+    assert!(is_identity_mapped(addr));
+    assert!(addr.is_aligned(KERNEL_PAGE_SIZE as u64));
+
+    let i = usize::from(addr.p3_index());
+    let j = usize::from(addr.p2_index());
+    let k = usize::from(addr.p1_index());
+
+    unsafe {
+        let pml2_entry = &mut ID_PML2_ARR.assume_init_mut()[i][j];
+
+        if pml2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let huge_base = pml2_entry.addr();
+            let frame_id = PAGE_MANAGER.lock().allocate(1)?;
+
+            // frames are identity-mapped, so we can fill the new table directly.
+            let pml1 = &mut *(frame_id.addr() as *mut PageTable);
+            for (n, entry) in pml1.iter_mut().enumerate() {
+                entry.set_addr(
+                    huge_base + n * KERNEL_PAGE_SIZE,
+                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+                );
+            }
+
+            pml2_entry.set_addr(
+                PhysAddr::new(frame_id.addr() as u64),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+            );
+        }
+
+        let pml1 = &mut *(pml2_entry.addr().as_u64() as *mut PageTable);
+        pml1[k].set_unused();
+    }
+    // the huge page might be cached as a whole.
+    tlb::flush_all();
+
+    REGIONS.lock().register(Region {
+        name,
+        kind: RegionKind::Guard,
+        start: addr,
+        end: addr + KERNEL_PAGE_SIZE,
+        flags: PageTableFlags::empty(),
+    }).expect("Region table full");
+
+    Ok(())
+}
+
+/// Find the region containing the given address.
+/// This gives up if the region table is locked, since this is also called from the page fault handler.
+pub fn find_region(addr: VirtAddr) -> Option<Region> {
+    REGIONS.try_lock()?.find(addr)
 }
 
+/// Try to resolve a page fault.
+///
+/// Locks held by another CPU are waited for, but not the ones held by this CPU:
+/// the fault might have occured while one of them is held by the interrupted code.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), FaultCause> {
+    let reentrant = |_: LockError| FaultCause::LockContention;
+
+    let region = REGIONS.lock_checked()
+        .map_err(reentrant)?
+        .find(addr)
+        .ok_or(FaultCause::NoRegion)?;
+
+    let mut mapper_cell = MAPPER.lock_checked().map_err(reentrant)?;
+    let mapper = mapper_cell.get_mut().ok_or(FaultCause::MapFailed)?;
+    let mut mgr = PAGE_MANAGER.lock_checked().map_err(reentrant)?;
+
+    crate::paging::resolve_fault(mapper, &mut mgr, region, addr, error_code)
+}
@@ -0,0 +1,92 @@
+extern crate alloc;
+
+use crate::acpi::McfgEntry;
+use crate::pci::{self, EcamAccessMethod, LegacyPortAccessMethod, PciConfigAccess, PciDevice, PciDriver};
+
+use alloc::vec::Vec;
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+
+const MAX_DRIVERS: usize = 16;
+
+/// A function found at boot, and the driver bound to it.
+pub struct Entry {
+    pub device: PciDevice,
+    pub driver: Option<&'static str>,
+}
+
+/// The configuration access, chosen by `init`.
+static ACCESS: Mutex<OnceCell<PciConfigAccess>> = Mutex::new(OnceCell::new());
+
+/// The PCI device table, built once at boot.
+pub static DEVICES: Mutex<OnceCell<Vec<Entry>>> = Mutex::new(OnceCell::new());
+
+/// The drivers, tried in the order of registration.
+static DRIVERS: Mutex<heapless::Vec<&'static PciDriver, MAX_DRIVERS>> = Mutex::new(heapless::Vec::new());
+
+/// Enumerate the buses into [`DEVICES`], with ECAM if the MCFG lists its regions.
+/// This depends on ACPI and the heap, and should precede any driver.
+#[inline]
+pub fn init() {
+    let access = *ACCESS.lock().get_or_init(|| {
+        let regions: Vec<McfgEntry> = super::acpi::acpi().and_then(|acpi| acpi.mcfg())
+            .into_iter()
+            .flat_map(|mcfg| mcfg.entries())
+            .collect();
+        if regions.is_empty() {
+            log::info!("PCI: no MCFG, using the legacy configuration ports");
+            return PciConfigAccess::Legacy(LegacyPortAccessMethod);
+        }
+        // the regions are copied out of the table, and live forever.
+        PciConfigAccess::Ecam(unsafe { EcamAccessMethod::new(regions.leak()) })
+    });
+
+    DEVICES.lock().get_or_init(|| {
+        let devices = unsafe { pci::enumerate(&access) };
+        for device in devices.iter() {
+            log::debug!("PCI: {}", device);
+        }
+        log::info!("PCI: {} functions", devices.len());
+        devices.into_iter().map(|device| Entry { device, driver: None }).collect()
+    });
+}
+
+/// The configuration access of the system. The legacy ports before `init`.
+pub fn config_access() -> PciConfigAccess {
+    ACCESS.lock().get().copied().unwrap_or(PciConfigAccess::Legacy(LegacyPortAccessMethod))
+}
+
+/// Add a driver, which is probed by [`probe_all`]. Returns false if there are too many drivers.
+pub fn register_driver(driver: &'static PciDriver) -> bool {
+    DRIVERS.lock().push(driver).is_ok()
+}
+
+/// Offer every unbound device to the matching drivers, until one takes it.
+pub fn probe_all() {
+    let drivers = DRIVERS.lock().clone();
+    let count = DEVICES.lock().get().map_or(0, |devices| devices.len());
+
+    for index in 0..count {
+        // probes may take long, and may look up the table, so they run without the lock.
+        let Some(device) = DEVICES.lock().get()
+            .and_then(|devices| devices.get(index))
+            .filter(|entry| entry.driver.is_none())
+            .map(|entry| entry.device.clone())
+        else { continue };
+
+        let Some(driver) = drivers.iter().filter(|driver| driver.matches(&device)).find(|driver| (driver.probe)(&device))
+        else { continue };
+
+        log::info!("PCI: {} bound to {}", device, driver.name);
+        if let Some(entry) = DEVICES.lock().get_mut().and_then(|devices| devices.get_mut(index)) {
+            entry.driver = Some(driver.name);
+        }
+    }
+}
+
+/// Visit the devices in the order of enumeration.
+pub fn for_each_device(mut f: impl FnMut(&PciDevice, Option<&'static str>)) {
+    for entry in DEVICES.lock().get().into_iter().flatten() {
+        f(&entry.device, entry.driver);
+    }
+}
@@ -4,7 +4,7 @@ use crate::pgmgr::{
     KERNEL_PAGE_SIZE,
 };
 
-use spin::mutex::Mutex;
+use crate::sync::CpuMutex;
 
 use shared::uefi_memory::{
     MemoryMap,
@@ -12,7 +12,8 @@ use shared::uefi_memory::{
     PAGE_SIZE as UEFI_PAGE_SIZE,
 };
 
-pub static PAGE_MANAGER: Mutex<PageManager> = Mutex::new(PageManager::new());
+/// The page manager. The page fault handler takes this too, hence the `CpuMutex`.
+pub static PAGE_MANAGER: CpuMutex<PageManager> = CpuMutex::new(PageManager::new());
 
 fn is_available(ty: MemoryType) -> bool {
     [
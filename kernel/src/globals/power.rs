@@ -0,0 +1,47 @@
+use crate::power::{self, PowerError};
+
+use super::acpi::{acpi, delay_us, fadt};
+
+/// The ACPI soft-off state.
+const S5: u8 = 5;
+
+/// Reset the system, trying the FADT reset register, the keyboard controller, and a triple fault in order.
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    log::info!("rebooting");
+
+    if let Some(reset) = fadt().and_then(|fadt| fadt.reset) {
+        match unsafe { power::write_reset_register(reset) } {
+            Ok(()) => delay_us(500_000), // the reset may take a while.
+            Err(err) => log::warn!("reset register: {:?}", err),
+        }
+    }
+
+    unsafe { power::pulse_keyboard_controller_reset() };
+    delay_us(500_000);
+
+    log::warn!("reset failed, triple faulting");
+    unsafe { power::triple_fault() }
+}
+
+/// Power off with the ACPI S5 state. This returns only on failure.
+pub fn shutdown() -> PowerError {
+    let Some(acpi) = acpi() else { return PowerError::NoAcpi };
+    let Some(fadt) = fadt() else { return PowerError::NoAcpi };
+    let Some(pm1a_control) = fadt.pm1a_control else { return PowerError::NoPm1Control };
+    let Some(sleep_type) = acpi.sleep_type(S5) else { return PowerError::NoSleepType(S5) };
+
+    if let Err(err) = unsafe {
+        power::enable_acpi(pm1a_control, fadt.smi_command, fadt.acpi_enable, |ms| delay_us(ms * 1000))
+    } {
+        return err;
+    }
+
+    log::info!("powering off");
+    x86_64::instructions::interrupts::disable();
+    unsafe { power::enter_sleep_state(pm1a_control, fadt.pm1b_control, sleep_type) };
+
+    delay_us(500_000);
+    x86_64::instructions::interrupts::enable();
+    PowerError::Ignored
+}
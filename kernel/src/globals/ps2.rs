@@ -0,0 +1,93 @@
+use crate::ps2::{Controller, Device, KeyboardDecoder, MouseDecoder, KEYBOARD_IRQ, MOUSE_IRQ};
+
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+use heapless::mpmc::MpMcQueue;
+
+use x86_64::structures::idt::InterruptStackFrame;
+
+use super::interrupts::{IDT_VEC_PS2_KEYBOARD, IDT_VEC_PS2_MOUSE};
+use super::{APIC, MSG_QUEUE};
+
+/// The i8042 controller. Empty if there's no controller, or it failed to initialize.
+pub static PS2: Mutex<OnceCell<Controller>> = Mutex::new(OnceCell::new());
+
+/// Decoders, used only by the main loop.
+static DECODERS: Mutex<OnceCell<(KeyboardDecoder, MouseDecoder)>> = Mutex::new(OnceCell::new());
+
+const QUEUE_SIZE: usize = 64;
+
+/// Bytes received by the interrupt handlers, which are decoded by the main loop.
+static KEYBOARD_QUEUE: MpMcQueue<u8, QUEUE_SIZE> = MpMcQueue::new();
+static MOUSE_QUEUE: MpMcQueue<u8, QUEUE_SIZE> = MpMcQueue::new();
+
+/// Init [`PS2`], and route the IRQs of the working ports. This depends on ACPI and the IOAPIC.
+#[inline]
+pub fn init() {
+    if super::acpi::fadt().and_then(|fadt| fadt.has_8042()) == Some(false) {
+        log::info!("PS/2: no controller in the FADT");
+        return;
+    }
+
+    let mut controller = unsafe { Controller::new() };
+    let devices = match controller.init() {
+        Ok(devices) => devices,
+        Err(err) => {
+            log::warn!("PS/2: {:?}", err);
+            return;
+        },
+    };
+    log::info!("PS/2: keyboard {}, mouse {}", devices.keyboard, devices.mouse);
+
+    let _ = PS2.lock().set(controller);
+    let _ = DECODERS.lock().set(Default::default());
+
+    let apic_id = APIC.id().read().id();
+    for (present, irq, vector) in [
+        (devices.keyboard, KEYBOARD_IRQ, IDT_VEC_PS2_KEYBOARD),
+        (devices.mouse, MOUSE_IRQ, IDT_VEC_PS2_MOUSE),
+    ] {
+        if !present { continue; }
+        if let Err(err) = super::ioapic::route_isa_irq(irq, vector as u8, apic_id) {
+            log::warn!("PS/2: IRQ {} not routed ({:?})", irq, err);
+        }
+    }
+}
+
+/// Move a received byte into its queue.
+fn receive() {
+    let Some(mut ps2) = PS2.try_lock() else { return };
+    let Some((device, byte)) = ps2.get_mut().and_then(|controller| controller.try_read()) else { return };
+    let queue = match device {
+        Device::Keyboard => &KEYBOARD_QUEUE,
+        Device::Mouse => &MOUSE_QUEUE,
+    };
+    let _ = queue.enqueue(byte); // drop if full.
+}
+
+pub extern "x86-interrupt" fn keyboard_handler(_stack_frame: InterruptStackFrame) {
+    receive();
+    let _ = MSG_QUEUE.enqueue(crate::message::Message::Ps2Interrupt);
+
+    APIC.end_of_interrupt().signal();
+}
+
+pub extern "x86-interrupt" fn mouse_handler(_stack_frame: InterruptStackFrame) {
+    receive();
+    let _ = MSG_QUEUE.enqueue(crate::message::Message::Ps2Interrupt);
+
+    APIC.end_of_interrupt().signal();
+}
+
+/// Decode received bytes, and pass the reports to the input listeners.
+pub fn process_input() {
+    let mut decoders = DECODERS.lock();
+    let Some((keyboard, mouse)) = decoders.get_mut() else { return };
+
+    while let Some(byte) = KEYBOARD_QUEUE.dequeue() {
+        if let Some(report) = keyboard.feed(byte) { super::input::keyboard(report); }
+    }
+    while let Some(byte) = MOUSE_QUEUE.dequeue() {
+        if let Some(report) = mouse.feed(byte) { super::input::mouse(report); }
+    }
+}
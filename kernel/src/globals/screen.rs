@@ -2,9 +2,9 @@ use crate::screen::Screen;
 use shared::uefi_gop::{FrameBuffer, ModeInfo};
 
 use core::cell::OnceCell;
-use spin::mutex::Mutex;
+use crate::sync::CpuMutex;
 
-pub static SCREEN: Mutex<OnceCell<Screen>> = Mutex::new(OnceCell::new());
+pub static SCREEN: CpuMutex<OnceCell<Screen>> = CpuMutex::new(OnceCell::new());
 
 #[inline]
 pub fn init(frame_buffer: FrameBuffer<'static>, mode_info: ModeInfo) {
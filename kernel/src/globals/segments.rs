@@ -6,18 +6,49 @@ use x86_64::registers::segmentation::{
     Segment, SegmentSelector,
     CS, DS, ES, FS, GS, SS
 };
-use x86_64::PrivilegeLevel;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::instructions::tables::load_tss;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::smp::PerCpu;
+
+use super::stacks::{DOUBLE_FAULT_STACK, PAGE_FAULT_STACK};
+
+/// IST index for the double fault handler.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// IST index for the page fault handler.
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
 static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 #[inline]
 pub fn init(){
+    // prepare TSS, so that faults caused by a broken stack can be handled on a separate stack.
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = DOUBLE_FAULT_STACK.top();
+        TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = PAGE_FAULT_STACK.top();
+    }
+
     // load GDT.
     unsafe {
-        GDT.add_entry(Descriptor::kernel_code_segment()); // index 1
-        GDT.add_entry(Descriptor::kernel_data_segment()); // index 2, 64-bit data segment
-        GDT.load();
+        let tss_selector = build(&mut *addr_of_mut!(GDT), &*addr_of!(TSS));
+        load(&*addr_of!(GDT), tss_selector);
     }
+}
+
+/// Add the kernel segments and the TSS to an empty GDT. Returns the TSS selector.
+fn build(gdt: &mut GlobalDescriptorTable, tss: &'static TaskStateSegment) -> SegmentSelector {
+    gdt.add_entry(Descriptor::kernel_code_segment()); // index 1
+    gdt.add_entry(Descriptor::kernel_data_segment()); // index 2, 64-bit data segment
+    gdt.add_entry(Descriptor::tss_segment(tss)) // index 3 and 4
+}
+
+/// Load a GDT built by `build`, set segment registers, and load the TSS.
+pub fn load(gdt: &'static GlobalDescriptorTable, tss_selector: SegmentSelector) {
+    gdt.load();
 
     // set segment registers.
     unsafe {
@@ -29,4 +60,21 @@ pub fn init(){
         SS::set_reg(SegmentSelector::new(2, PrivilegeLevel::Ring0));
         CS::set_reg(SegmentSelector::new(1, PrivilegeLevel::Ring0));
     }
-}
\ No newline at end of file
+
+    // load TSS.
+    unsafe {
+        load_tss(tss_selector);
+    }
+}
+
+/// Prepare the TSS and the GDT in the per-CPU data, with the IST stacks. (double fault, page fault)
+/// Each CPU needs its own TSS, since a loaded TSS is marked busy.
+pub fn init_cpu(cpu: &'static mut PerCpu, ist: [VirtAddr; 2]) -> &'static PerCpu {
+    cpu.tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = ist[0];
+    cpu.tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = ist[1];
+
+    // the data is never freed, so the TSS lives forever.
+    let tss = unsafe { &*addr_of!(cpu.tss) };
+    cpu.tss_selector = build(&mut cpu.gdt, tss);
+    cpu
+}
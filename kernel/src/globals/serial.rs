@@ -0,0 +1,122 @@
+use crate::serial::{SerialPort, COM1_BASE, COM1_IRQ};
+
+use core::fmt::{Arguments, Write};
+use core::cell::OnceCell;
+use crate::sync::CpuMutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::mpmc::MpMcQueue;
+
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use super::{APIC, MSG_QUEUE};
+
+/// COM1. Empty if there's no UART on the port.
+pub static COM1: CpuMutex<OnceCell<SerialPort>> = CpuMutex::new(OnceCell::new());
+
+/// Whether COM1 is present, which can be checked without the lock.
+static COM1_PRESENT: AtomicBool = AtomicBool::new(false);
+
+const RX_QUEUE_SIZE: usize = 64;
+
+/// Bytes received by the interrupt handler, which are consumed by the main loop.
+static RX_QUEUE: MpMcQueue<u8, RX_QUEUE_SIZE> = MpMcQueue::new();
+
+const BAUD: u32 = 115200;
+
+/// Init [`COM1`].
+/// This doesn't depend on anything, so this can be called first to capture the whole boot transcript.
+#[inline]
+pub fn init() {
+    let mut port = unsafe { SerialPort::new(COM1_BASE) };
+    if port.init(BAUD).is_ok() {
+        // the IRQ is not delivered until `route_irq`, so the main loop also polls the port.
+        port.enable_receive_interrupt();
+        let _ = COM1.lock().set(port);
+        COM1_PRESENT.store(true, Ordering::Release);
+    }
+}
+
+/// Route the COM1 IRQ to `IDT_VEC_COM1` on this CPU. This depends on the IOAPIC.
+pub fn route_irq() {
+    if !COM1_PRESENT.load(Ordering::Acquire) { return; }
+
+    let vector = super::interrupts::IDT_VEC_COM1 as u8;
+    match super::ioapic::route_isa_irq(COM1_IRQ, vector, APIC.id().read().id()) {
+        Ok(gsi) => log::info!("COM1: IRQ {} routed to GSI {}", COM1_IRQ, gsi),
+        Err(err) => log::warn!("COM1: IRQ not routed ({:?}), polling", err),
+    }
+}
+
+pub fn _serial_print(args: Arguments) {
+    without_interrupts(|| {
+        match COM1.lock_checked() {
+            Ok(mut com1) => if let Some(port) = com1.get_mut() {
+                port.write_fmt(args).unwrap();
+            },
+            Err(_) => emergency_print(args), // this CPU is in the middle of printing.
+        }
+    });
+}
+
+/// Write to COM1 without taking the lock.
+/// This is the last resort when the console can't be used; the output may interleave with the lock holder's.
+pub fn emergency_print(args: Arguments) {
+    if !COM1_PRESENT.load(Ordering::Acquire) { return; }
+
+    let mut port = unsafe { SerialPort::new(COM1_BASE) };
+    let _ = port.write_fmt(args);
+}
+
+/// Release COM1 regardless of its holder.
+///
+/// # Safety
+/// Only for panics, where the holder never continues.
+pub unsafe fn force_unlock() {
+    COM1.force_unlock();
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::globals::serial::_serial_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Move received bytes into the receive queue.
+fn drain(port: &mut SerialPort) {
+    while let Some(byte) = port.try_receive() {
+        if RX_QUEUE.enqueue(byte).is_err() { break; } // drop the rest.
+    }
+}
+
+/// Take a received byte, polling the port if the receive queue is empty.
+pub fn receive() -> Option<u8> {
+    RX_QUEUE.dequeue().or_else(|| {
+        without_interrupts(|| {
+            COM1.lock().get_mut().and_then(|port| port.try_receive())
+        })
+    })
+}
+
+pub extern "x86-interrupt" fn com1_handler(_stack_frame: InterruptStackFrame) {
+    if let Ok(mut com1) = COM1.lock_checked() {
+        if let Some(port) = com1.get_mut() {
+            drain(port);
+        }
+    }
+    let _ = MSG_QUEUE.enqueue(crate::message::Message::SerialInterrupt);
+
+    APIC.end_of_interrupt().signal();
+}
+
+/// Feed received bytes to the shell, which echoes them to the console and back to COM1.
+pub fn process_input() {
+    while let Some(byte) = receive() {
+        super::shell::input(byte);
+    }
+}
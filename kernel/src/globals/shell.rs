@@ -0,0 +1,72 @@
+use crate::shell::{Command, Edit, LineEditor};
+use crate::xhci::class::KeyboardReport;
+use crate::{console_print, serial_print};
+
+use spin::mutex::Mutex;
+
+const PROMPT: &str = "> ";
+
+// left and right shift, in the modifier byte.
+const MODIFIER_SHIFT: u8 = 0b0010_0010;
+
+/// The line being typed, shared by COM1 and the keyboards. Used only by the main loop.
+static EDITOR: Mutex<LineEditor> = Mutex::new(LineEditor::new());
+
+/// Print to both the console and COM1, so that the shell can be used from either.
+macro_rules! shell_print {
+    ($($arg:tt)*) => {{
+        console_print!($($arg)*);
+        serial_print!($($arg)*);
+    }};
+}
+
+pub fn init() {
+    shell_print!("type `help` for commands.\n{}", PROMPT);
+}
+
+/// Feed a byte typed on COM1 or a keyboard.
+pub fn input(byte: u8) {
+    let mut editor = EDITOR.lock();
+    match editor.feed(byte) {
+        Some(Edit::Insert(byte)) => shell_print!("{}", byte as char),
+        Some(Edit::Erase) => shell_print!("\x08 \x08"),
+        Some(Edit::Submit) => {
+            shell_print!("\n");
+            execute(Command::parse(editor.line()));
+            editor.clear();
+            shell_print!("{}", PROMPT);
+        },
+        None => {},
+    }
+}
+
+/// Feed the keys newly pressed in a keyboard report.
+pub fn keyboard(report: &KeyboardReport) {
+    let shift = report.modifier & MODIFIER_SHIFT != 0;
+    for usage in report.cur_keys.iter_ones().filter(|&usage| !report.prev_keys[usage]) {
+        if let Some(byte) = crate::shell::hid_to_ascii(usage as u8, shift) {
+            input(byte);
+        }
+    }
+}
+
+fn execute(command: Command) {
+    match command {
+        Command::Empty => {},
+        Command::Help => {
+            for (name, description) in Command::HELP {
+                shell_print!("  {:<10}{}\n", name, description);
+            }
+        },
+        Command::Dmesg => super::logger::for_each_record(|line| shell_print!("{}\n", line)),
+        Command::Lspci => super::pci::for_each_device(|device, driver| {
+            shell_print!("{} {}\n", device, driver.unwrap_or("-"));
+        }),
+        Command::Reboot => super::power::reboot(),
+        Command::Shutdown => {
+            let err = super::power::shutdown();
+            shell_print!("shutdown failed: {:?}\n", err);
+        },
+        Command::Unknown(name) => shell_print!("unknown command: {}\n", name),
+    }
+}
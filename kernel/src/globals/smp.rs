@@ -0,0 +1,148 @@
+use crate::pgmgr::FrameID;
+use crate::smp::{self, PerCpu, Trampoline, TrampolineParams, IPI_INIT, IPI_STARTUP};
+
+use spin::mutex::Mutex;
+
+use super::acpi::{acpi, delay_us};
+use super::interrupts::IDT_VEC_SPURIOUS;
+use super::pgmgr::PAGE_MANAGER;
+use super::stacks::{DOUBLE_FAULT_STACK, PAGE_FAULT_STACK};
+use super::APIC;
+
+/// The maximum number of CPUs, including the BSP.
+const MAX_CPUS: usize = 16;
+
+/// The kernel stack of an AP, which is backed on demand. (64KB)
+const AP_STACK_PAGES: usize = 16;
+/// Each IST stack of an AP, with its own guard page. (16KB)
+const AP_IST_STACK_PAGES: usize = 4;
+
+/// Frames below 1MB, where the trampoline should be.
+const LOW_MEMORY_FRAMES: usize = 0x100;
+
+/// The per-CPU data of the CPUs online, by index. The BSP is the first.
+pub static CPUS: Mutex<heapless::Vec<&'static PerCpu, MAX_CPUS>> = Mutex::new(heapless::Vec::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmpError {
+    /// The APIC ID doesn't fit in the xAPIC destination.
+    X2ApicId(u32),
+    /// No memory or no region for the stacks.
+    OutOfMemory,
+    /// The AP didn't reach the entry.
+    Timeout,
+}
+
+fn lapic_base() -> usize {
+    APIC.base_addr.as_ptr() as usize
+}
+
+/// Set up the per-CPU data of the BSP, and start the APs listed in the MADT.
+/// This depends on ACPI, the heap and the IDT.
+#[inline]
+pub fn init() {
+    let bsp = super::segments::init_cpu(
+        PerCpu::leak(0, APIC.id().read().id() as u32),
+        [DOUBLE_FAULT_STACK.top(), PAGE_FAULT_STACK.top()],
+    );
+    super::segments::load(&bsp.gdt, bsp.tss_selector); // the static GDT was for the boot.
+    bsp.install();
+    bsp.set_online();
+    let _ = CPUS.lock().push(bsp);
+
+    let Some(madt) = acpi().and_then(|acpi| acpi.madt()) else {
+        log::warn!("SMP: no MADT, running on the BSP only");
+        return;
+    };
+    let mut slot = install_trampoline();
+
+    for apic_id in madt.cpu_apic_ids().filter(|&apic_id| apic_id != bsp.apic_id) {
+        let index = CPUS.lock().len();
+        if index == MAX_CPUS {
+            log::warn!("SMP: too many CPUs, ignoring the rest");
+            break;
+        }
+        let Some((_, trampoline)) = slot.as_mut() else {
+            log::warn!("SMP: no page below 1MB for the trampoline");
+            break;
+        };
+        match start_ap(trampoline, index, apic_id) {
+            Ok(cpu) => { let _ = CPUS.lock().push(cpu); },
+            Err(SmpError::Timeout) => {
+                log::warn!("SMP: APIC {} not started in time, parked", apic_id);
+                // it might have been about to read the params, so the page is leaked and the rest get a new one.
+                slot = install_trampoline();
+            },
+            Err(err) => log::warn!("SMP: APIC {} not started ({:?})", apic_id, err),
+        }
+    }
+
+    // every AP which used this page has left the trampoline.
+    if let Some((frame_id, _)) = slot {
+        let _ = PAGE_MANAGER.lock().free(frame_id, 1);
+    }
+    log::info!("SMP: {} CPUs online", CPUS.lock().len());
+}
+
+/// Copy the trampoline to a new page below 1MB.
+fn install_trampoline() -> Option<(FrameID, Trampoline)> {
+    let frame_id = PAGE_MANAGER.lock().allocate_constrained(1, 1, FrameID(LOW_MEMORY_FRAMES)).ok()?;
+    Some((frame_id, unsafe { Trampoline::install(frame_id.addr()) }))
+}
+
+/// Start an AP with INIT-SIPI-SIPI, and wait until it reaches the entry.
+fn start_ap(trampoline: &mut Trampoline, index: usize, apic_id: u32) -> Result<&'static PerCpu, SmpError> {
+    let destination = u8::try_from(apic_id).map_err(|_| SmpError::X2ApicId(apic_id))?;
+
+    let stack_top = super::paging::reserve_task_stack("AP kernel stack", AP_STACK_PAGES)
+        .ok_or(SmpError::OutOfMemory)?
+        .end;
+    // the trampoline pushes before the AP loads the IDT, so the top page is faulted in here.
+    unsafe { (stack_top - 8u64).as_mut_ptr::<u64>().write_volatile(0) };
+    let double_fault_top = super::stacks::allocate("AP double fault stack", AP_IST_STACK_PAGES).ok_or(SmpError::OutOfMemory)?;
+    let page_fault_top = super::stacks::allocate("AP page fault stack", AP_IST_STACK_PAGES).ok_or(SmpError::OutOfMemory)?;
+    let cpu = super::segments::init_cpu(
+        PerCpu::leak(index, apic_id),
+        [double_fault_top, page_fault_top],
+    );
+
+    trampoline.set_params(TrampolineParams::current(stack_top, ap_entry as usize, cpu as *const PerCpu as usize));
+
+    // the second STARTUP is only for CPUs which missed the first.
+    unsafe { smp::send_ipi(lapic_base(), destination, IPI_INIT) };
+    delay_us(10_000);
+    for _ in 0..2 {
+        unsafe { smp::send_ipi(lapic_base(), destination, IPI_STARTUP | trampoline.vector() as u32) };
+        for _ in 0..100 {
+            if cpu.is_online() { return Ok(cpu); }
+            delay_us(100);
+        }
+    }
+    // INIT again, so that it waits for a STARTUP instead of running into the next AP's params.
+    unsafe { smp::send_ipi(lapic_base(), destination, IPI_INIT) };
+    Err(SmpError::Timeout)
+}
+
+/// The entry of an AP, called by the trampoline on its own stack.
+extern "sysv64" fn ap_entry(cpu: &'static PerCpu) -> ! {
+    super::segments::load(&cpu.gdt, cpu.tss_selector);
+    cpu.install(); // after the segment registers.
+    super::interrupts::load();
+    unsafe { smp::enable_local_apic(lapic_base(), IDT_VEC_SPURIOUS as u8) };
+    cpu.set_online();
+
+    idle()
+}
+
+/// The idle loop of the APs, until there's a scheduler to take the work.
+fn idle() -> ! {
+    x86_64::instructions::interrupts::enable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// The number of CPUs online.
+pub fn cpu_count() -> usize {
+    CPUS.lock().len()
+}
@@ -0,0 +1,39 @@
+use crate::stack::GuardedStack;
+use crate::pgmgr::KERNEL_PAGE_SIZE;
+
+use x86_64::VirtAddr;
+
+use super::pgmgr::PAGE_MANAGER;
+
+/// The kernel main stack. (1MB)
+pub static KERNEL_MAIN_STACK: GuardedStack<256> = GuardedStack::new();
+
+/// The IST stack for double faults, including kernel stack overflows.
+pub static DOUBLE_FAULT_STACK: GuardedStack<4> = GuardedStack::new();
+/// The IST stack for page faults.
+pub static PAGE_FAULT_STACK: GuardedStack<4> = GuardedStack::new();
+
+/// Unmap the guard pages of the static kernel stacks.
+/// Should be called after initializing the page manager, which provides frames for splitting huge pages.
+pub fn init() {
+    let stacks = [
+        ("kernel main stack", KERNEL_MAIN_STACK.guard_pages()),
+        ("double fault stack", DOUBLE_FAULT_STACK.guard_pages()),
+        ("page fault stack", PAGE_FAULT_STACK.guard_pages()),
+    ];
+
+    for (name, guard_pages) in stacks {
+        for addr in guard_pages {
+            super::paging::unmap_guard_page(name, addr).unwrap();
+        }
+    }
+}
+
+/// Allocate a stack of `page_cnt` pages from the page manager, with a guard page below. Returns the top.
+/// This is for stacks created at runtime, e.g. for application processors.
+pub fn allocate(name: &'static str, page_cnt: usize) -> Option<VirtAddr> {
+    let frame_id = PAGE_MANAGER.lock().allocate(page_cnt + 1).ok()?;
+    let guard = VirtAddr::new(frame_id.addr() as u64);
+    super::paging::unmap_guard_page(name, guard).ok()?;
+    Some(guard + (page_cnt + 1) * KERNEL_PAGE_SIZE)
+}
@@ -0,0 +1,33 @@
+use crate::backtrace::{Backtrace, SymbolTable};
+
+use core::cell::OnceCell;
+use spin::mutex::Mutex;
+
+use shared::{KernelImage, KernelSymbols};
+use x86_64::VirtAddr;
+
+/// The kernel symbol table. Empty if the bootloader didn't pass one.
+pub static SYMBOLS: Mutex<OnceCell<Option<SymbolTable>>> = Mutex::new(OnceCell::new());
+
+#[inline]
+pub fn init(symbols: KernelSymbols, image: KernelImage) {
+    log::info!("kernel loaded at {:#x} (bias {:#x})", image.base, image.bias);
+    SYMBOLS.lock().get_or_init(|| unsafe {
+        SymbolTable::from_raw(symbols, image.bias) // the sections are on loader data pages, which are never freed.
+    });
+}
+
+fn is_readable(addr: u64) -> bool {
+    VirtAddr::try_new(addr).map_or(false, super::paging::is_readable)
+}
+
+/// A backtrace from the given frame pointer, with the faulting instruction if any.
+/// This never blocks, so it can be used in exception handlers and on panic.
+pub fn backtrace(rip: Option<VirtAddr>, rbp: u64) -> Backtrace<fn(u64) -> bool> {
+    Backtrace {
+        rip: rip.map(|rip| rip.as_u64()),
+        rbp,
+        symbols: SYMBOLS.try_lock().and_then(|cell| cell.get().copied().flatten()),
+        readable: is_readable,
+    }
+}
@@ -1,4 +1,6 @@
 
+extern crate alloc;
+
 use crate::xhci::{
     self,
     Controller,
@@ -6,54 +8,86 @@ use crate::xhci::{
     SupportedClassListeners,
 };
 
+use crate::dma::DmaAllocator;
+use crate::pci::{PciDevice, PciDriver};
+
 use core::cell::OnceCell;
 use spin::mutex::Mutex;
 
-use super::interrupts::IDT_VEC_XHCI;
-use super::allocator::global_allocator;
+use super::interrupts::allocate_vector;
+use super::msi::{self, enable_msix, MsiError};
+use super::MSG_QUEUE;
+use crate::message::Message;
+use crate::msi::MsixError;
+use super::allocator::dma_allocator;
+
+pub static XHC: Mutex<OnceCell<Controller<'static, Listeners, &'static DmaAllocator>>> = Mutex::new(OnceCell::new());
+
+/// The xHCI driver. Only the first controller is used.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "xhci",
+    matches: xhci::PCI_MATCHES,
+    probe,
+};
 
-pub static XHC: Mutex<OnceCell<Controller<'static, Listeners>>> = Mutex::new(OnceCell::new());
+fn probe(device: &PciDevice) -> bool {
+    if XHC.lock().get().is_some() { return false; }
 
-#[allow(unused_must_use)]
-#[inline]
-pub fn init() {
     let apic = &*super::APIC;
     log::info!("base {:p} / bsp id {}", apic.base_addr.as_ptr(), apic.id().read().id());
 
-    let xhci_ep_acc = xhci::get_xhci_ep_acc().unwrap();
+    let xhci_ep_acc = xhci::get_xhci_ep_acc(super::pci::config_access(), device.address);
+    let Some(xhci_mmio_base) = xhci::read_mmio_base(device) else {
+        log::warn!("xHCI: no MMIO base");
+        return false;
+    };
 
-    // Enable MSI.
-    let msi_cap_header_acc = xhci::find_msi_cap_acc(&xhci_ep_acc).unwrap();
-    xhci::cfg_msi_fixed_dst(
-        &msi_cap_header_acc,
-        apic.base_addr,
-        apic.id().read().id(), // bootstrap processor LAPIC ID
-        IDT_VEC_XHCI as u8,
-    );
+    // Enable MSI-X, with the primary interrupter to the BSP. Plain MSI if the controller doesn't have it.
+    let vectors = match enable_msix(device, &[0], interrupt) {
+        Ok(vectors) => vectors,
+        Err(MsiError::Msix(MsixError::NoCapability)) => {
+            let Some(msi_cap_header_acc) = xhci::find_msi_cap_acc(&xhci_ep_acc) else {
+                log::warn!("xHCI: no MSI capability");
+                return false;
+            };
+            let Some(vector) = allocate_vector(interrupt) else {
+                log::warn!("xHCI: no free vector");
+                return false;
+            };
+            xhci::cfg_msi_fixed_dst(
+                &msi_cap_header_acc,
+                apic.base_addr,
+                apic.id().read().id(), // bootstrap processor LAPIC ID
+                vector,
+            );
+            alloc::vec![vector]
+        },
+        Err(err) => {
+            log::warn!("xHCI: MSI-X failed: {:?}", err);
+            return false;
+        },
+    };
 
     // Setup xhc controller.
-    let xhci_mmio_base = xhci::read_mmio_base(&xhci_ep_acc).unwrap();
-    XHC.lock().get_or_init(|| {
-        xhci::setup_xhc_controller::<'static, _, _>(xhci_mmio_base, global_allocator()).unwrap()
-    });
+    let Some(xhc) = xhci::setup_xhc_controller::<'static, _, _>(xhci_mmio_base, dma_allocator()) else {
+        log::warn!("xHCI: setup failed");
+        msi::disable(device, &vectors);
+        return false;
+    };
+    let _ = XHC.lock().set(xhc);
+    true
+}
+
+fn interrupt(_vector: u8) {
+    let _ = MSG_QUEUE.enqueue(Message::XHCIInterrupt); // the main loop drains the event ring anyway.
 }
 
 pub struct Listeners;
 impl SupportedClassListeners for Listeners {
     fn keyboard() -> fn(class::KeyboardReport) {
-        fn keyboard_listener(report: class::KeyboardReport) {
-            log::debug!("Keyboard Report) modifier : {}", report.modifier);
-        }
-
-        keyboard_listener
+        super::input::keyboard
     }
     fn mouse() -> fn(class::MouseReport) {
-        fn mouse_listener(report: class::MouseReport) {
-            log::debug!("Mouse Report) {}, {:?}", report.buttons, report.disp);
-
-            crate::globals::SCREEN.lock().get_mut().unwrap().move_cursor(report.disp.into());
-        }
-
-        mouse_listener
+        super::input::mouse
     }
 }
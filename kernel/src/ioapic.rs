@@ -0,0 +1,239 @@
+//! I/O APIC driver, and the legacy 8259 PIC which is masked in favor of it.
+//!
+//! https://wiki.osdev.org/IOAPIC
+//! https://pdos.csail.mit.edu/6.828/2016/readings/ia32/ioapic.pdf
+
+use x86_64::instructions::port::Port;
+
+use crate::acpi::MadtEntry;
+
+// MMIO registers. a register is selected by writing its index to `IOREGSEL`, and accessed by `IOWIN`.
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+// register indices.
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10; // two registers per entry.
+
+// redirection entry bits.
+const RTE_VECTOR: u64 = 0xFF;
+const RTE_ACTIVE_LOW: u64 = 1 << 13;
+const RTE_LEVEL: u64 = 1 << 15;
+const RTE_MASKED: u64 = 1 << 16;
+const RTE_DESTINATION_SHIFT: u64 = 56;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// A redirection table entry, delivered in the fixed mode to a physical destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    pub masked: bool,
+    /// The APIC ID of the destination LAPIC.
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+    /// A masked entry, which every entry is reset to.
+    pub const MASKED: Self = Self {
+        vector: 0,
+        polarity: Polarity::ActiveHigh,
+        trigger: TriggerMode::Edge,
+        masked: true,
+        destination: 0,
+    };
+
+    pub fn to_raw(&self) -> u64 {
+        let mut raw = self.vector as u64 | (self.destination as u64) << RTE_DESTINATION_SHIFT;
+        if self.polarity == Polarity::ActiveLow { raw |= RTE_ACTIVE_LOW; }
+        if self.trigger == TriggerMode::Level { raw |= RTE_LEVEL; }
+        if self.masked { raw |= RTE_MASKED; }
+        raw
+    }
+
+    /// Other delivery and destination modes are read as the fixed, physical mode.
+    pub fn from_raw(raw: u64) -> Self {
+        Self {
+            vector: (raw & RTE_VECTOR) as u8,
+            polarity: if raw & RTE_ACTIVE_LOW != 0 { Polarity::ActiveLow } else { Polarity::ActiveHigh },
+            trigger: if raw & RTE_LEVEL != 0 { TriggerMode::Level } else { TriggerMode::Edge },
+            masked: raw & RTE_MASKED != 0,
+            destination: (raw >> RTE_DESTINATION_SHIFT) as u8,
+        }
+    }
+}
+
+/// Where an ISA IRQ is connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsaRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl IsaRoute {
+    /// Resolve an ISA IRQ with the MADT interrupt source overrides.
+    /// Without an override, the IRQ is identity-mapped to a GSI, and is active high and edge-triggered.
+    pub fn resolve(irq: u8, mut madt_entries: impl Iterator<Item = MadtEntry>) -> Self {
+        let default = Self { gsi: irq as u32, polarity: Polarity::ActiveHigh, trigger: TriggerMode::Edge };
+
+        let Some((gsi, flags)) = madt_entries.find_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride { bus: 0, source, gsi, flags } if source == irq => Some((gsi, flags)),
+            _ => None,
+        }) else { return default };
+
+        // MPS INTI flags. `0b00` conforms to the bus, and `0b10` is reserved.
+        Self {
+            gsi,
+            polarity: if flags & 0b11 == 0b11 { Polarity::ActiveLow } else { default.polarity },
+            trigger: if (flags >> 2) & 0b11 == 0b11 { TriggerMode::Level } else { default.trigger },
+        }
+    }
+}
+
+/// An I/O APIC, which delivers the GSIs from `gsi_base` to `gsi_base + entry_count`.
+#[derive(Debug)]
+pub struct IoApic {
+    pub id: u8,
+    base: usize,
+    pub gsi_base: u32,
+    pub entry_count: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    /// `base` should be the identity-mapped MMIO base of an I/O APIC, which is not used by any other code.
+    pub unsafe fn new(id: u8, base: usize, gsi_base: u32) -> Self {
+        let mut ioapic = Self { id, base, gsi_base, entry_count: 0 };
+        ioapic.entry_count = ((ioapic.read(IOAPICVER) >> 16) & 0xFF) + 1;
+        ioapic
+    }
+
+    fn read(&mut self, index: u32) -> u32 {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(index);
+            ((self.base + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&mut self, index: u32, value: u32) {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(index);
+            ((self.base + IOWIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    /// Returns true if the GSI is delivered by this I/O APIC.
+    pub fn handles(&self, gsi: u32) -> bool {
+        self.gsi_base <= gsi && gsi - self.gsi_base < self.entry_count
+    }
+
+    pub fn entry(&mut self, index: u32) -> RedirectionEntry {
+        let low = self.read(IOREDTBL + 2 * index) as u64;
+        let high = self.read(IOREDTBL + 2 * index + 1) as u64;
+        RedirectionEntry::from_raw(high << 32 | low)
+    }
+
+    pub fn set_entry(&mut self, index: u32, entry: RedirectionEntry) {
+        let raw = entry.to_raw();
+        // mask first, so that a half-written entry is never delivered.
+        self.write(IOREDTBL + 2 * index, RTE_MASKED as u32);
+        self.write(IOREDTBL + 2 * index + 1, (raw >> 32) as u32);
+        self.write(IOREDTBL + 2 * index, raw as u32);
+    }
+
+    pub fn mask_all(&mut self) {
+        for index in 0..self.entry_count {
+            self.set_entry(index, RedirectionEntry::MASKED);
+        }
+    }
+}
+
+// 8259 PIC ports.
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+/// The vectors the 8259 PICs are remapped to, so that a spurious IRQ doesn't look like an exception.
+pub const PIC_VECTOR_BASE: u8 = 0x20;
+
+/// The vectors of IRQ 7 and IRQ 15, which the PICs raise on spurious IRQs even when masked.
+pub const PIC_SPURIOUS_MASTER: u8 = PIC_VECTOR_BASE + 7;
+pub const PIC_SPURIOUS_SLAVE: u8 = PIC_VECTOR_BASE + 15;
+
+/// Acknowledge the cascade IRQ on the master, after a spurious IRQ of the slave.
+///
+/// # Safety
+/// This should be called only from the handler of `PIC_SPURIOUS_SLAVE`.
+pub unsafe fn pic_cascade_eoi() {
+    Port::<u8>::new(PIC1_COMMAND).write(0x20);
+}
+
+/// Remap and mask every IRQ of the legacy 8259 PICs.
+///
+/// # Safety
+/// This should be called once, before the I/O APIC is used.
+pub unsafe fn disable_pic() {
+    let mut pic1_command = Port::<u8>::new(PIC1_COMMAND);
+    let mut pic1_data = Port::<u8>::new(PIC1_DATA);
+    let mut pic2_command = Port::<u8>::new(PIC2_COMMAND);
+    let mut pic2_data = Port::<u8>::new(PIC2_DATA);
+
+    // ICW1: initialize with ICW4. ICW2: vector base. ICW3: cascade on IRQ2. ICW4: 8086 mode.
+    pic1_command.write(0x11);
+    pic2_command.write(0x11);
+    pic1_data.write(PIC_VECTOR_BASE);
+    pic2_data.write(PIC_VECTOR_BASE + 8);
+    pic1_data.write(0x04);
+    pic2_data.write(0x02);
+    pic1_data.write(0x01);
+    pic2_data.write(0x01);
+
+    pic1_data.write(0xFF);
+    pic2_data.write(0xFF);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_redirection_entries() {
+        let entry = RedirectionEntry {
+            vector: 0x42,
+            polarity: Polarity::ActiveLow,
+            trigger: TriggerMode::Level,
+            masked: false,
+            destination: 3,
+        };
+        assert_eq!(entry.to_raw(), 0x0300_0000_0000_A042);
+        assert_eq!(RedirectionEntry::from_raw(entry.to_raw()), entry);
+        assert_eq!(RedirectionEntry::MASKED.to_raw(), 0x1_0000);
+    }
+
+    #[test]
+    fn resolves_isa_overrides() {
+        let entries = [
+            MadtEntry::InterruptSourceOverride { bus: 0, source: 0, gsi: 2, flags: 0 },
+            MadtEntry::InterruptSourceOverride { bus: 0, source: 9, gsi: 9, flags: 0b1111 },
+        ];
+        let resolve = |irq| IsaRoute::resolve(irq, entries.iter().copied());
+
+        assert_eq!(resolve(0), IsaRoute { gsi: 2, polarity: Polarity::ActiveHigh, trigger: TriggerMode::Edge });
+        assert_eq!(resolve(9), IsaRoute { gsi: 9, polarity: Polarity::ActiveLow, trigger: TriggerMode::Level });
+        assert_eq!(resolve(4), IsaRoute { gsi: 4, polarity: Polarity::ActiveHigh, trigger: TriggerMode::Edge });
+    }
+}
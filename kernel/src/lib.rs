@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)] // host tests use std
 #![feature(
     abi_x86_interrupt,
     allocator_api,
@@ -11,19 +11,39 @@
 )]
 
 mod sysfont;
+pub mod sync;
 pub mod geometry;
 pub mod canvas;
 pub mod screen;
 pub mod console;
 pub mod cursor;
+pub mod logger;
 
 pub mod pgmgr;
+pub mod paging;
+pub mod stack;
 pub mod allocator;
+pub mod slab;
+pub mod dma;
 
+pub mod serial;
+pub mod acpi;
+pub mod ioapic;
+pub mod ps2;
+pub mod smp;
+pub mod power;
 pub mod pci;
+pub mod vectors;
+pub mod msi;
 pub mod xhci;
 pub mod message;
+pub mod exception;
+pub mod backtrace;
+pub mod tarfs;
+pub mod shell;
 
 pub mod window;
 
-pub mod globals;
\ No newline at end of file
+pub mod globals;
+
+pub mod testing;
\ No newline at end of file
@@ -0,0 +1,319 @@
+//! Logger building blocks: a lock-free record ring (dmesg), per-module level filters and sinks.
+//!
+//! The logger itself lives in `globals::logger`.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter};
+
+/// The maximum length of a formatted record. Longer records are truncated.
+pub const LINE_LEN: usize = 160;
+
+/// A fixed-size buffer, which truncates what doesn't fit.
+pub struct LineBuf {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl LineBuf {
+    pub const fn new() -> Self {
+        Self { buf: [0; LINE_LEN], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // truncation happens on char boundaries.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl fmt::Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut n = s.len().min(LINE_LEN - self.len);
+        while !s.is_char_boundary(n) { n -= 1; }
+
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// TSC ticks since boot, displayed in seconds if the TSC frequency is known.
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    pub ticks: u64,
+    /// TSC ticks per microsecond, or zero if unknown.
+    pub ticks_per_us: u64,
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ticks_per_us == 0 {
+            write!(f, "{:>14}", self.ticks)
+        } else {
+            let us = self.ticks / self.ticks_per_us;
+            write!(f, "{:>7}.{:06}", us / 1_000_000, us % 1_000_000)
+        }
+    }
+}
+
+/// The TSC clock for timestamps.
+pub struct TscClock {
+    base: AtomicU64,
+    ticks_per_us: AtomicU64,
+}
+
+impl TscClock {
+    pub const fn new() -> Self {
+        Self {
+            base: AtomicU64::new(0),
+            ticks_per_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Start counting from now, and find the TSC frequency from CPUID if available.
+    pub fn init(&self) {
+        use core::arch::x86_64::{__cpuid, _rdtsc};
+
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        let mut ticks_per_us = 0;
+        if max_leaf >= 0x15 {
+            // TSC/crystal ratio and crystal frequency in Hz.
+            let leaf = unsafe { __cpuid(0x15) };
+            if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+                ticks_per_us = leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64 / 1_000_000;
+            }
+        }
+        if ticks_per_us == 0 && max_leaf >= 0x16 {
+            // the processor base frequency in MHz, which approximates the TSC frequency.
+            ticks_per_us = (unsafe { __cpuid(0x16) }.eax & 0xffff) as u64;
+        }
+
+        self.ticks_per_us.store(ticks_per_us, Ordering::Relaxed);
+        self.base.store(unsafe { _rdtsc() }, Ordering::Relaxed);
+    }
+
+    /// Busy-wait with the TSC. Returns false without waiting if the TSC frequency is unknown.
+    pub fn delay_us(&self, us: u64) -> bool {
+        use core::arch::x86_64::_rdtsc;
+
+        let ticks_per_us = self.ticks_per_us.load(Ordering::Relaxed);
+        if ticks_per_us == 0 { return false; }
+
+        let start = unsafe { _rdtsc() };
+        while unsafe { _rdtsc() }.wrapping_sub(start) < us * ticks_per_us {
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    pub fn now(&self) -> Timestamp {
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        Timestamp {
+            ticks: tsc.wrapping_sub(self.base.load(Ordering::Relaxed)),
+            ticks_per_us: self.ticks_per_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A formatted log record.
+#[derive(Clone, Copy, Debug)]
+pub struct LogLine<'a> {
+    /// The sequence number, counted from boot.
+    pub seq: usize,
+    pub timestamp: Timestamp,
+    pub level: Level,
+    pub target: &'a str,
+    pub text: &'a str,
+}
+
+impl fmt::Display for LogLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] [{}] {}", self.timestamp, self.level, self.text)
+    }
+}
+
+/// A destination of log records.
+///
+/// Records can be written from interrupt context, so a sink should never wait for a lock.
+/// Returns false if the record was dropped; it is still kept in the ring.
+pub trait LogSink: Sync {
+    fn name(&self) -> &'static str;
+    fn write(&self, line: &LogLine) -> bool;
+}
+
+struct Slot {
+    /// `seq + 1` of the record in this slot, or zero while it is being written.
+    stamp: AtomicUsize,
+    ticks: UnsafeCell<u64>,
+    level: UnsafeCell<Level>,
+    len: UnsafeCell<usize>,
+    text: UnsafeCell<[u8; LINE_LEN]>,
+}
+
+impl Slot {
+    const EMPTY: Self = Self {
+        stamp: AtomicUsize::new(0),
+        ticks: UnsafeCell::new(0),
+        level: UnsafeCell::new(Level::Info),
+        len: UnsafeCell::new(0),
+        text: UnsafeCell::new([0; LINE_LEN]),
+    };
+}
+
+/// A lock-free ring of the last `N` records.
+///
+/// Writers claim a slot by a single atomic increment, so they never wait for each other.
+/// Readers detect slots being overwritten by their stamps, and skip them.
+pub struct LogRing<const N: usize> {
+    next: AtomicUsize,
+    slots: [Slot; N],
+}
+
+// slot contents are guarded by their stamps.
+unsafe impl<const N: usize> Sync for LogRing<N> {}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            slots: [Slot::EMPTY; N],
+        }
+    }
+
+    /// Append a record, overwriting the oldest one. Returns the sequence number.
+    pub fn push(&self, timestamp: Timestamp, level: Level, text: &str) -> usize {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq % N];
+
+        let len = text.len().min(LINE_LEN);
+
+        slot.stamp.store(0, Ordering::Release);
+        core::sync::atomic::fence(Ordering::Release);
+        unsafe {
+            *slot.ticks.get() = timestamp.ticks;
+            *slot.level.get() = level;
+            *slot.len.get() = len;
+            (*slot.text.get())[..len].copy_from_slice(&text.as_bytes()[..len]);
+        }
+        slot.stamp.store(seq + 1, Ordering::Release);
+
+        seq
+    }
+
+    /// The number of records ever pushed.
+    pub fn total(&self) -> usize {
+        self.next.load(Ordering::Relaxed)
+    }
+
+    /// Visit the records still in the ring, from the oldest.
+    /// `ticks_per_us` is used for the timestamps.
+    pub fn for_each(&self, ticks_per_us: u64, mut f: impl FnMut(&LogLine)) {
+        let end = self.total();
+        for seq in end.saturating_sub(N)..end {
+            let slot = &self.slots[seq % N];
+            if slot.stamp.load(Ordering::Acquire) != seq + 1 { continue; }
+
+            let (ticks, level, len, text) = unsafe {
+                (
+                    core::ptr::read_volatile(slot.ticks.get()),
+                    core::ptr::read_volatile(slot.level.get()),
+                    core::ptr::read_volatile(slot.len.get()),
+                    core::ptr::read_volatile(slot.text.get()),
+                )
+            };
+            core::sync::atomic::fence(Ordering::Acquire);
+            if slot.stamp.load(Ordering::Relaxed) != seq + 1 { continue; } // overwritten while reading
+
+            let len = len.min(LINE_LEN);
+            let Ok(text) = core::str::from_utf8(&text[..len]) else { continue };
+            f(&LogLine {
+                seq,
+                timestamp: Timestamp { ticks, ticks_per_us },
+                level,
+                target: "",
+                text,
+            });
+        }
+    }
+}
+
+/// The level filter for a log level from the bootloader.
+pub fn level_filter(level: shared::LogLevel) -> LevelFilter {
+    use shared::LogLevel;
+    match level {
+        LogLevel::Off => LevelFilter::Off,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    }
+}
+
+/// The maximum length of a module path in a filter.
+pub const MODULE_LEN: usize = 48;
+const MAX_MODULE_FILTERS: usize = 16;
+
+/// Per-module level filters.
+///
+/// A record is filtered by the longest module path which is a prefix of its target,
+/// or by the default level if there's none.
+pub struct LevelFilters {
+    default: LevelFilter,
+    modules: heapless::Vec<(heapless::String<MODULE_LEN>, LevelFilter), MAX_MODULE_FILTERS>,
+}
+
+impl LevelFilters {
+    pub const fn new(default: LevelFilter) -> Self {
+        Self { default, modules: heapless::Vec::new() }
+    }
+
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    pub fn set_default(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    /// Set the level of a module and its submodules. Returns None if the table is full or the path is too long.
+    pub fn set(&mut self, module: &str, level: LevelFilter) -> Option<()> {
+        if let Some(entry) = self.modules.iter_mut().find(|(m, _)| m.as_str() == module) {
+            entry.1 = level;
+            return Some(());
+        }
+
+        let mut path = heapless::String::new();
+        path.push_str(module).ok()?;
+        self.modules.push((path, level)).ok()
+    }
+
+    /// Remove the level of a module, so that it follows its parent.
+    pub fn unset(&mut self, module: &str) {
+        self.modules.retain(|(m, _)| m.as_str() != module);
+    }
+
+    /// The level filter for the target.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules.iter()
+            .filter(|(m, _)| {
+                target.strip_prefix(m.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(m, _)| m.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// The most verbose level among all filters, which is the global max level of `log`.
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules.iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, LevelFilter::max)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, LevelFilter)> {
+        self.modules.iter().map(|(m, level)| (m.as_str(), *level))
+    }
+}
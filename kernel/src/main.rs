@@ -8,20 +8,17 @@ use core::panic::PanicInfo;
 
 use kernel::{
     globals,
-    // console_print,
-    console_println
+    console_print,
+    console_println,
+    serial_println,
 };
 
-const KERNEL_MAIN_STACK_SIZE: usize = 0x100000; // 1MB
-static KERNEL_MAIN_STACK: [u8; KERNEL_MAIN_STACK_SIZE] = [0; KERNEL_MAIN_STACK_SIZE];
-
 /// Relocate kernel stack.
 /// This should preceed over any function calls, and the function itself SHOULD BE inline.
 #[inline(always)]
 pub fn relocate_stack(){
     unsafe {
-        let kernel_main_stack_top = (&KERNEL_MAIN_STACK as *const u8)
-            .add(KERNEL_MAIN_STACK_SIZE);
+        let kernel_main_stack_top = globals::stacks::KERNEL_MAIN_STACK.top().as_u64();
         core::arch::asm!(
             "mov rsp, {}",
             in(reg) kernel_main_stack_top
@@ -52,9 +49,17 @@ pub fn kernel_main (
     globals::init(mmap, args);
 
     log::info!("init completed");
+    log::info!("{}", globals::allocator::heap_stats());
+
+    // greet with the message of the day from the initrd, if any.
+    if let Some(motd) = globals::fs::read("etc/motd") {
+        console_print!("{}", core::str::from_utf8(motd).unwrap_or(""));
+    }
 
     // log::info!("Hello, GYUR OS!");
 
+    globals::shell::init();
+
     loop {
         // Dequeuing should be occured in critical section
         // and no interrupts should happen during it.
@@ -62,10 +67,21 @@ pub fn kernel_main (
 
         match globals::MSG_QUEUE.dequeue() {
             Some(kernel::message::Message::XHCIInterrupt) => {
-                globals::XHC.lock().get_mut().unwrap()
-                    .process_events();
+                if let Some(xhc) = globals::XHC.lock().get_mut() {
+                    xhc.process_events();
+                }
+            },
+            Some(kernel::message::Message::SerialInterrupt) => {
+                globals::serial::process_input();
+            },
+            Some(kernel::message::Message::Ps2Interrupt) => {
+                globals::ps2::process_input();
+            },
+            None => {
+                // the COM1 IRQ may not be routed, so poll it too.
+                globals::serial::process_input();
+                halt()
             },
-            None => halt(),
         }
     }
 }
@@ -77,7 +93,19 @@ fn panic_handler(info: &PanicInfo) -> ! {
         core::arch::asm!("mov r11, 0xDEAD");
     }
 
+    // the panicking code may hold the output locks, and it never continues.
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        globals::serial::force_unlock();
+        globals::console::force_unlock();
+    }
+
+    // mirror to COM1 first, in case the screen is broken.
+    let backtrace = globals::symbols::backtrace(None, kernel::backtrace::current_rbp());
+    serial_println!("{}", info);
+    serial_println!("{}", backtrace);
     console_println!("{}", info);
+    console_println!("{}", backtrace);
     loop { halt() }
 }
 
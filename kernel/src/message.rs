@@ -1,4 +1,6 @@
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Message {
     XHCIInterrupt,
+    SerialInterrupt,
+    Ps2Interrupt,
 }
\ No newline at end of file
@@ -0,0 +1,255 @@
+//! MSI-X programming, and the message format shared with MSI.
+//!
+//! The MSI-X capability is decoded here rather than through the `pci_types` fork.
+//! The fork is a submodule whose capability accessors this tree only has for MSI (see `xhci::cfg_msi_fixed_dst`),
+//! and MSI-X support there is a change to the fork first. Until then, the registers below follow
+//! PCI Local Bus 3.0, 6.8.2, over any `ConfigSpace`, which also lets the tests use a fake one.
+//!
+//! https://wiki.osdev.org/PCI#Message_Signaled_Interrupts
+//! Intel SDM Vol. 3, 11.11 Message Signalled Interrupts
+
+use crate::pci::{BarInfo, ConfigSpace, PciDevice};
+
+pub const CAP_ID_MSI: u8 = 0x05;
+pub const CAP_ID_MSIX: u8 = 0x11;
+
+// the message address: the LAPIC MMIO window, with the destination APIC ID.
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+const MSI_DESTINATION_SHIFT: u64 = 12;
+
+// MSI-X capability registers, from the capability offset.
+const MSIX_CONTROL: u16 = 0x00; // upper half of the header dword
+const MSIX_TABLE: u16 = 0x04;
+const MSIX_TABLE_SIZE_MASK: u32 = 0x7FF << 16;
+const MSIX_FUNCTION_MASK: u32 = 1 << (16 + 14);
+const MSIX_ENABLE: u32 = 1 << (16 + 15);
+
+const MSI_ENABLE: u32 = 1 << 16;
+
+const REG_COMMAND: u16 = 0x04;
+const COMMAND_MEMORY: u32 = 1 << 1;
+const COMMAND_INTX_DISABLE: u32 = 1 << 10;
+
+// MSI-X table entries.
+const ENTRY_SIZE: usize = 16;
+const ENTRY_ADDRESS_LOW: usize = 0x0;
+const ENTRY_ADDRESS_HIGH: usize = 0x4;
+const ENTRY_DATA: usize = 0x8;
+const ENTRY_VECTOR_CONTROL: usize = 0xC;
+const ENTRY_MASKED: u32 = 1;
+
+/// An interrupt message, written by the device to deliver the vector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl MsiMessage {
+    /// An edge-triggered message in the fixed delivery mode, to a physical destination.
+    pub fn fixed(apic_id: u8, vector: u8) -> Self {
+        Self {
+            address: MSI_ADDRESS_BASE | (apic_id as u64) << MSI_DESTINATION_SHIFT,
+            data: vector as u32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MsixError {
+    NoCapability,
+    /// The table BAR is not a memory BAR.
+    BadTableBar(u8),
+}
+
+/// The MSI-X capability and table of a function.
+#[derive(Debug)]
+pub struct Msix {
+    cap_offset: u16,
+    table: usize,
+    pub table_size: u16,
+}
+
+impl Msix {
+    /// Find the table from the capability.
+    pub fn new(access: &impl ConfigSpace, device: &PciDevice) -> Result<Self, MsixError> {
+        let cap = device.capability(CAP_ID_MSIX).ok_or(MsixError::NoCapability)?;
+        let control = unsafe { access.read_dword(device.address, cap.offset + MSIX_CONTROL) };
+        let table = unsafe { access.read_dword(device.address, cap.offset + MSIX_TABLE) };
+
+        // the low 3 bits of the table register select the BAR, and the rest is the offset in it.
+        let bir = (table & 0b111) as u8;
+        let Some(Some(BarInfo::Memory { address, .. })) = device.bars.get(bir as usize) else {
+            return Err(MsixError::BadTableBar(bir));
+        };
+        Ok(Self {
+            cap_offset: cap.offset,
+            table: (address + (table & !0b111) as u64) as usize,
+            table_size: (((control & MSIX_TABLE_SIZE_MASK) >> 16) + 1) as u16,
+        })
+    }
+
+    fn entry(&self, index: u16) -> *mut u32 {
+        assert!(index < self.table_size);
+        (self.table + index as usize * ENTRY_SIZE) as *mut u32
+    }
+
+    /// Program an entry, which is masked while written.
+    ///
+    /// # Safety
+    /// The table should be identity-mapped, and the vector should have a handler.
+    pub unsafe fn set_entry(&mut self, index: u16, message: MsiMessage, masked: bool) {
+        let entry = self.entry(index) as usize;
+        let reg = |offset: usize| (entry + offset) as *mut u32;
+        reg(ENTRY_VECTOR_CONTROL).write_volatile(ENTRY_MASKED);
+        reg(ENTRY_ADDRESS_LOW).write_volatile(message.address as u32);
+        reg(ENTRY_ADDRESS_HIGH).write_volatile((message.address >> 32) as u32);
+        reg(ENTRY_DATA).write_volatile(message.data);
+        if !masked { reg(ENTRY_VECTOR_CONTROL).write_volatile(0); }
+    }
+
+    /// # Safety
+    /// The table should be identity-mapped.
+    pub unsafe fn set_masked(&mut self, index: u16, masked: bool) {
+        let control = (self.entry(index) as usize + ENTRY_VECTOR_CONTROL) as *mut u32;
+        control.write_volatile(if masked { ENTRY_MASKED } else { 0 });
+    }
+
+    /// Mask every entry, and turn MSI-X on with MSI and INTx off.
+    /// Entries deliver once they are programmed and unmasked.
+    ///
+    /// # Safety
+    /// The function should not be interrupting yet, and the table should be identity-mapped.
+    pub unsafe fn enable(&mut self, access: &impl ConfigSpace, device: &PciDevice) {
+        let addr = device.address;
+        if let Some(msi) = device.capability(CAP_ID_MSI) {
+            let header = access.read_dword(addr, msi.offset);
+            access.write_dword(addr, msi.offset, header & !MSI_ENABLE);
+        }
+        let command = access.read_dword(addr, REG_COMMAND) & 0xFFFF; // don't clear the status bits.
+        access.write_dword(addr, REG_COMMAND, command | COMMAND_MEMORY | COMMAND_INTX_DISABLE);
+
+        // the function mask holds the entries back, while they are masked one by one.
+        let header = access.read_dword(addr, self.cap_offset);
+        access.write_dword(addr, self.cap_offset, header | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+        for index in 0..self.table_size {
+            self.set_masked(index, true);
+        }
+        access.write_dword(addr, self.cap_offset, (header | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+    }
+}
+
+/// Turn MSI and MSI-X off.
+///
+/// # Safety
+/// The driver should be done with the interrupts of the function.
+pub unsafe fn disable(access: &impl ConfigSpace, device: &PciDevice) {
+    for (id, enable) in [(CAP_ID_MSI, MSI_ENABLE), (CAP_ID_MSIX, MSIX_ENABLE)] {
+        if let Some(cap) = device.capability(id) {
+            let header = access.read_dword(device.address, cap.offset);
+            access.write_dword(device.address, cap.offset, header & !enable);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pci::fake::FakeConfig;
+    use crate::pci::Capability;
+    use pci_types::PciAddress;
+
+    const MSI_CAP: u16 = 0x80;
+    const MSIX_CAP: u16 = 0x90;
+
+    /// The function of `device`.
+    const FUNCTION: (u8, u8, u8) = (0, 3, 0);
+
+    fn fake_config(dwords: &[(u16, u32)]) -> FakeConfig {
+        let mut config = FakeConfig::default();
+        config.add(FUNCTION, dwords);
+        config
+    }
+
+    /// A function with MSI, MSI-X, an I/O BAR 0 and a memory BAR 2.
+    fn device(bar2: u64) -> PciDevice {
+        let mut bars = [None; 6];
+        bars[0] = Some(BarInfo::Io { port: 0xc000, size: 0x20 });
+        bars[2] = Some(BarInfo::Memory { address: bar2, size: 0x4000, prefetchable: false, is_64bit: true });
+        PciDevice {
+            address: PciAddress::new(0, 0, 3, 0),
+            vendor_id: 0x1b36,
+            device_id: 0x000d,
+            class: 0x0c,
+            subclass: 0x03,
+            interface: 0x30,
+            revision: 1,
+            header_type: 0,
+            bars,
+            capabilities: [
+                Capability { id: CAP_ID_MSI, offset: MSI_CAP },
+                Capability { id: CAP_ID_MSIX, offset: MSIX_CAP },
+            ].into_iter().collect(),
+            extended_capabilities: heapless::Vec::new(),
+            interrupt_line: 0x0b,
+            interrupt_pin: 1,
+        }
+    }
+
+    #[test]
+    fn encodes_messages() {
+        let message = MsiMessage::fixed(3, 0x51);
+        assert_eq!(message, MsiMessage { address: 0xFEE0_3000, data: 0x51 });
+    }
+
+    #[test]
+    fn decodes_the_table() {
+        // 8 entries at 0x3000 in BAR 2, with the enable and function mask bits set around the size.
+        let config = fake_config(&[
+            (MSIX_CAP, MSIX_ENABLE | MSIX_FUNCTION_MASK | (7 << 16) | CAP_ID_MSIX as u32),
+            (MSIX_CAP + MSIX_TABLE, 0x3000 | 2),
+        ]);
+        let msix = Msix::new(&config, &device(0xfebf_0000)).unwrap();
+        assert_eq!((msix.table, msix.table_size), (0xfebf_3000, 8));
+
+        // the largest table.
+        let config = fake_config(&[(MSIX_CAP, MSIX_TABLE_SIZE_MASK | MSIX_ENABLE), (MSIX_CAP + MSIX_TABLE, 2)]);
+        assert_eq!(Msix::new(&config, &device(0)).unwrap().table_size, 2048);
+
+        // the table in the I/O BAR, in a missing BAR, and no capability.
+        let config = fake_config(&[(MSIX_CAP + MSIX_TABLE, 0x1000)]);
+        assert_eq!(Msix::new(&config, &device(0)).unwrap_err(), MsixError::BadTableBar(0));
+        let config = fake_config(&[(MSIX_CAP + MSIX_TABLE, 5)]);
+        assert_eq!(Msix::new(&config, &device(0)).unwrap_err(), MsixError::BadTableBar(5));
+        let mut no_msix = device(0);
+        no_msix.capabilities.pop();
+        assert_eq!(Msix::new(&config, &no_msix).unwrap_err(), MsixError::NoCapability);
+    }
+
+    #[test]
+    fn programs_the_table() {
+        let table = Box::leak(Box::new([0xFFFF_FFFFu32; 4 * ENTRY_SIZE / 4]));
+        let device = device(table.as_ptr() as u64);
+        let config = fake_config(&[
+            (REG_COMMAND, 0x0010_0000), // a status bit, which is write-1-to-clear.
+            (MSI_CAP, MSI_ENABLE | CAP_ID_MSI as u32),
+            (MSIX_CAP, (3 << 16) | CAP_ID_MSIX as u32),
+            (MSIX_CAP + MSIX_TABLE, 2),
+        ]);
+
+        let mut msix = Msix::new(&config, &device).unwrap();
+        unsafe {
+            msix.enable(&config, &device);
+            msix.set_entry(1, MsiMessage::fixed(2, 0x60), false);
+        }
+        assert_eq!(config.get(FUNCTION, REG_COMMAND), COMMAND_MEMORY | COMMAND_INTX_DISABLE); // not written back.
+        assert_eq!(config.get(FUNCTION, MSI_CAP) & MSI_ENABLE, 0);
+        assert_eq!(config.get(FUNCTION, MSIX_CAP) & (MSIX_ENABLE | MSIX_FUNCTION_MASK), MSIX_ENABLE);
+        assert_eq!(table[..4], [0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFF, ENTRY_MASKED]);
+        assert_eq!(table[4..8], [0xFEE0_2000, 0, 0x60, 0]);
+        assert_eq!(table[12..], [0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFF, ENTRY_MASKED]);
+
+        unsafe { disable(&config, &device) };
+        assert_eq!(config.get(FUNCTION, MSIX_CAP) & MSIX_ENABLE, 0);
+    }
+}
@@ -0,0 +1,300 @@
+//! Virtual memory regions and demand paging.
+//!
+//! The identity mapping (see `globals::paging`) occupies the first PML4 entry.
+//! The entries after it are reserved for lazily backed areas,
+//! whose pages get a zeroed frame on the first touch.
+
+use core::fmt;
+use core::ops::Range;
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    Mapper,
+    OffsetPageTable,
+    Page,
+    PageTableFlags,
+    Size4KiB,
+    mapper::MapToError,
+};
+use x86_64::structures::idt::{
+    InterruptStackFrameValue,
+    PageFaultErrorCode,
+};
+
+use crate::pgmgr::{
+    PageManager,
+    KERNEL_PAGE_SIZE,
+};
+
+/// The virtual size covered by a single PML4 entry. (512GB)
+const PML4_ENTRY_SPAN: u64 = 1 << 39;
+
+/// Lazily backed virtual areas.
+/// Each area occupies a whole PML4 entry, right after the identity mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Area {
+    /// Task stacks.
+    TaskStacks = 0,
+    /// Zero-fill-on-demand mappings.
+    ZeroFill,
+}
+
+impl Area {
+    const COUNT: usize = 2;
+
+    /// The virtual address range of this area.
+    pub const fn range(&self) -> Range<u64> {
+        let start = PML4_ENTRY_SPAN * (*self as u64 + 1);
+        start..(start + PML4_ENTRY_SPAN)
+    }
+}
+
+/// The kind of a region, which decides how a fault on it is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// A frame is allocated and mapped on the first access.
+    Lazy,
+    /// An unmapped page below or above a stack. Any access to it is a stack overflow.
+    Guard,
+}
+
+/// A named virtual memory region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub kind: RegionKind,
+    pub start: VirtAddr,
+    pub end: VirtAddr, // represents [start, end) range
+    pub flags: PageTableFlags,
+}
+
+impl Region {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+const MAX_REGIONS: usize = 64;
+
+/// The table of regions, which also manages the unused part of each area.
+pub struct RegionTable {
+    regions: heapless::Vec<Region, MAX_REGIONS>,
+    cursors: [u64; Area::COUNT],
+}
+
+impl RegionTable {
+    pub const fn new() -> Self {
+        Self {
+            regions: heapless::Vec::new(),
+            cursors: [
+                Area::TaskStacks.range().start,
+                Area::ZeroFill.range().start,
+            ],
+        }
+    }
+
+    /// Reserve `page_cnt` pages in the given area, and register it as a region.
+    /// No frames are mapped here.
+    pub fn reserve(
+        &mut self,
+        area: Area,
+        name: &'static str,
+        kind: RegionKind,
+        page_cnt: usize,
+        flags: PageTableFlags,
+    ) -> Option<Region> {
+        let cursor = &mut self.cursors[area as usize];
+        let end = cursor.checked_add((page_cnt * KERNEL_PAGE_SIZE) as u64)?;
+        if end > area.range().end { return None; }
+
+        let region = Region {
+            name,
+            kind,
+            start: VirtAddr::new(*cursor),
+            end: VirtAddr::new(end),
+            flags,
+        };
+        self.regions.push(region).ok()?;
+        *cursor = end;
+
+        Some(region)
+    }
+
+    /// Reserve a region of `page_cnt` pages with a guard page on each side.
+    /// Returns the region between the guard pages.
+    pub fn reserve_guarded(
+        &mut self,
+        area: Area,
+        name: &'static str,
+        kind: RegionKind,
+        page_cnt: usize,
+        flags: PageTableFlags,
+    ) -> Option<Region> {
+        if self.regions.capacity() - self.regions.len() < 3 { return None; }
+
+        self.reserve(area, name, RegionKind::Guard, 1, PageTableFlags::empty())?;
+        let region = self.reserve(area, name, kind, page_cnt, flags)?;
+        self.reserve(area, name, RegionKind::Guard, 1, PageTableFlags::empty())?;
+
+        Some(region)
+    }
+
+    /// Register a region outside of the areas, such as a guard page of a static stack.
+    pub fn register(&mut self, region: Region) -> Option<()> {
+        self.regions.push(region).ok()
+    }
+
+    /// Find the region containing the given address.
+    pub fn find(&self, addr: VirtAddr) -> Option<Region> {
+        self.regions.iter().find(|region| region.contains(addr)).copied()
+    }
+}
+
+/// The reason why a page fault could not be resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultCause {
+    /// The address doesn't belong to any region.
+    NoRegion,
+    /// The address lies in a guard page.
+    StackOverflow,
+    /// The page is present, but the access is not permitted.
+    ProtectionViolation,
+    /// No frame is left for the page or its page tables.
+    OutOfMemory,
+    /// The page is already mapped, or lies on a huge page.
+    MapFailed,
+    /// The fault hit while this CPU was modifying the page tables or the page manager.
+    LockContention,
+}
+
+impl fmt::Display for FaultCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FaultCause::NoRegion => "address outside of any region",
+            FaultCause::StackOverflow => "stack overflow",
+            FaultCause::ProtectionViolation => "access not permitted",
+            FaultCause::OutOfMemory => "out of page frames",
+            FaultCause::MapFailed => "page mapping failed",
+            FaultCause::LockContention => "page tables or page manager locked",
+        })
+    }
+}
+
+/// Resolve a fault on a lazy region, by mapping a zeroed frame into the faulting page.
+pub fn resolve_fault(
+    mapper: &mut OffsetPageTable<'static>,
+    mgr: &mut PageManager,
+    region: Region,
+    addr: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> Result<(), FaultCause> {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return Err(FaultCause::ProtectionViolation);
+    }
+
+    match region.kind {
+        RegionKind::Lazy => {
+            let page = Page::<Size4KiB>::containing_address(addr);
+            let frame_id = mgr.allocate(1)
+                .map_err(|_| FaultCause::OutOfMemory)?;
+
+            // frames are identity-mapped, so we can zero it directly.
+            unsafe {
+                core::ptr::write_bytes(frame_id.addr() as *mut u8, 0, KERNEL_PAGE_SIZE);
+            }
+
+            match unsafe { mapper.map_to(page, frame_id.into(), region.flags, mgr) } {
+                Ok(flush) => {
+                    flush.flush();
+                    Ok(())
+                },
+                Err(err) => {
+                    let _ = mgr.free(frame_id, 1);
+                    Err(match err {
+                        MapToError::FrameAllocationFailed => FaultCause::OutOfMemory,
+                        _ => FaultCause::MapFailed,
+                    })
+                },
+            }
+        },
+        RegionKind::Guard => Err(FaultCause::StackOverflow),
+    }
+}
+
+/// Page fault error code bits, in words.
+pub struct ErrorCodeWords(pub PageFaultErrorCode);
+
+impl fmt::Display for ErrorCodeWords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+
+        f.write_str(if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "protection violation"
+        } else {
+            "page not present"
+        })?;
+        f.write_str(if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            ", instruction fetch"
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            ", write"
+        } else {
+            ", read"
+        })?;
+        f.write_str(if code.contains(PageFaultErrorCode::USER_MODE) {
+            ", user mode"
+        } else {
+            ", kernel mode"
+        })?;
+        if code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            f.write_str(", reserved bit set in page table")?;
+        }
+        if code.contains(PageFaultErrorCode::PROTECTION_KEY) {
+            f.write_str(", protection key")?;
+        }
+        if code.contains(PageFaultErrorCode::SHADOW_STACK) {
+            f.write_str(", shadow stack")?;
+        }
+        Ok(())
+    }
+}
+
+/// The maximum length of an x86 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// A decoded report of a page fault which could not be resolved.
+pub struct PageFaultReport<'a> {
+    pub addr: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+    pub stack_frame: &'a InterruptStackFrameValue,
+    pub region: Option<Region>,
+    pub cause: FaultCause,
+    /// Bytes at the faulting instruction, if they are readable.
+    pub instruction: Option<[u8; MAX_INSTRUCTION_LEN]>,
+}
+
+impl fmt::Display for PageFaultReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Page Fault at {:#018x} ({})", self.addr.as_u64(), self.cause)?;
+        writeln!(f, "  error code {:#x}: {}", self.error_code.bits(), ErrorCodeWords(self.error_code))?;
+        write!(f, "  instruction {:#018x}:", self.stack_frame.instruction_pointer.as_u64())?;
+        match self.instruction {
+            Some(bytes) => {
+                for b in bytes.iter() {
+                    write!(f, " {:02x}", b)?;
+                }
+                writeln!(f)?;
+            },
+            None => writeln!(f, " (unreadable)")?,
+        }
+        writeln!(f, "  stack pointer {:#018x}", self.stack_frame.stack_pointer.as_u64())?;
+        match self.region {
+            Some(region) => write!(
+                f, "  region \"{}\" ({:?}) [{:#x}, {:#x})",
+                region.name, region.kind, region.start.as_u64(), region.end.as_u64()
+            ),
+            None => write!(f, "  region (none)"),
+        }
+    }
+}
+
@@ -1,3 +1,5 @@
+extern crate alloc;
+
 use bit_field::BitField;
 use x86_64::instructions::port::Port;
 
@@ -17,6 +19,16 @@ pub use pci_types::map_field as acc_map_field;
 
 use pci_types::dwords::HeaderTypeDword;
 
+use crate::acpi::McfgEntry;
+
+/// A configuration access method, and the part of the configuration space it reaches.
+pub trait ConfigSpace: DwordAccessMethod {
+    /// The segment groups, with the first bus of each.
+    fn root_buses(&self) -> impl Iterator<Item = (u16, u8)>;
+    /// Whether the extended configuration space (0x100 to 0xFFF) is accessible.
+    fn is_extended(&self) -> bool;
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct LegacyPortAccessMethod;
 impl LegacyPortAccessMethod {
@@ -70,35 +82,591 @@ impl DwordAccessMethod for LegacyPortAccessMethod {
     }
 }
 
-/// Scan all by Enumerating all 65536 (bus, dev, fun) triples.
-/// Alternatively we can use DFS though.
-pub fn scan_all_brute() -> impl Iterator<Item = PciAddress> {
-    use itertools::Itertools;
+impl ConfigSpace for LegacyPortAccessMethod {
+    fn root_buses(&self) -> impl Iterator<Item = (u16, u8)> {
+        core::iter::once((0, 0))
+    }
 
-    Itertools::cartesian_product(
-        0..=0xffu8, // bus
-        0..=31u8, // dev
-    ).flat_map(|(bus, dev)| {
-        // segment is unused, set to 0.
+    fn is_extended(&self) -> bool {
+        false
+    }
+}
 
-        let addr0 = PciAddress::new(0, bus, dev, 0x00);
+/// PCI Express enhanced configuration access (ECAM), through the MMIO regions listed in the MCFG.
+/// Each function has 4KB of configuration space.
+#[derive(Clone, Copy, Debug)]
+pub struct EcamAccessMethod {
+    regions: &'static [McfgEntry],
+}
 
-        let opt0 = if LegacyPortAccessMethod.function_exists(addr0) {
-            Some(addr0)
-        } else { None };
+impl EcamAccessMethod {
+    /// # Safety
+    /// The regions should be from the MCFG, and identity-mapped.
+    pub unsafe fn new(regions: &'static [McfgEntry]) -> Self {
+        Self { regions }
+    }
 
-        [opt0].into_iter().chain(
-            if LegacyPortAccessMethod.has_multiple_functions(addr0) {
-                core::array::from_fn::<u8, 7, _>(|i| (i as u8 + 1)).map(|fun| {
-                    let addr = PciAddress::new(0, bus, dev, fun);
-                    if LegacyPortAccessMethod.function_exists(addr) {
-                        Some(addr)
-                    } else { None }
-                }).into_iter()
-            } else {
-                [None; 7].into_iter()
-            }
+    /// The physical address of a configuration register, if a region covers the function.
+    pub fn config_address(&self, addr: PciAddress, offset: u16) -> Option<u64> {
+        assert!(offset.is_multiple_of(4) && offset < 0x1000);
+        let region = self.regions.iter().find(|region| {
+            region.segment == addr.segment() && (region.start_bus..=region.end_bus).contains(&addr.bus())
+        })?;
+        let bus = (addr.bus() - region.start_bus) as u64;
+        Some(region.base + (bus << 20 | (addr.slot() as u64) << 15 | (addr.function() as u64) << 12 | offset as u64))
+    }
+}
+
+impl DwordAccessMethod for EcamAccessMethod {
+    unsafe fn read_dword(&self, address: PciAddress, offset: u16) -> u32 {
+        match self.config_address(address, offset) {
+            Some(config) => (config as *const u32).read_volatile(),
+            None => 0xffffffff, // as if no device responded.
+        }
+    }
+
+    unsafe fn write_dword(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(config) = self.config_address(address, offset) {
+            (config as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn function_exists(&self, address: PciAddress) -> bool {
+        unsafe {
+            self.read_dword(address, 0x00) != 0xffffffff
+        }
+    }
+
+    fn has_multiple_functions(&self, address: PciAddress) -> bool {
+        unsafe {
+            core::mem::transmute::<u32, HeaderTypeDword>(
+                self.read_dword(address, 0x0C)
+            ).has_multiple_functions()
+        }
+    }
+}
+
+impl ConfigSpace for EcamAccessMethod {
+    fn root_buses(&self) -> impl Iterator<Item = (u16, u8)> {
+        self.regions.iter().map(|region| (region.segment, region.start_bus))
+    }
+
+    fn is_extended(&self) -> bool {
+        true
+    }
+}
+
+/// The configuration access of the system: ECAM if the firmware has an MCFG, or the legacy ports.
+#[derive(Clone, Copy, Debug)]
+pub enum PciConfigAccess {
+    Legacy(LegacyPortAccessMethod),
+    Ecam(EcamAccessMethod),
+}
+
+impl DwordAccessMethod for PciConfigAccess {
+    unsafe fn read_dword(&self, address: PciAddress, offset: u16) -> u32 {
+        match self {
+            Self::Legacy(access) => access.read_dword(address, offset),
+            Self::Ecam(access) => access.read_dword(address, offset),
+        }
+    }
+
+    unsafe fn write_dword(&self, address: PciAddress, offset: u16, value: u32) {
+        match self {
+            Self::Legacy(access) => access.write_dword(address, offset, value),
+            Self::Ecam(access) => access.write_dword(address, offset, value),
+        }
+    }
+
+    fn function_exists(&self, address: PciAddress) -> bool {
+        match self {
+            Self::Legacy(access) => access.function_exists(address),
+            Self::Ecam(access) => access.function_exists(address),
+        }
+    }
+
+    fn has_multiple_functions(&self, address: PciAddress) -> bool {
+        match self {
+            Self::Legacy(access) => access.has_multiple_functions(address),
+            Self::Ecam(access) => access.has_multiple_functions(address),
+        }
+    }
+}
+
+impl ConfigSpace for PciConfigAccess {
+    fn root_buses(&self) -> impl Iterator<Item = (u16, u8)> {
+        // the iterators differ in type, so take them in turn.
+        let (legacy, ecam) = match self {
+            Self::Legacy(access) => (Some(access.root_buses()), None),
+            Self::Ecam(access) => (None, Some(access.root_buses())),
+        };
+        legacy.into_iter().flatten().chain(ecam.into_iter().flatten())
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self, Self::Ecam(_))
+    }
+}
+
+
+// configuration space offsets, common to all header types.
+const REG_ID: u16 = 0x00;
+const REG_COMMAND_STATUS: u16 = 0x04;
+const REG_CLASS: u16 = 0x08;
+const REG_HEADER_TYPE: u16 = 0x0C;
+const REG_BAR0: u16 = 0x10;
+const REG_BUS_NUMBERS: u16 = 0x18; // bridges only
+const REG_CAPABILITIES: u16 = 0x34;
+const REG_INTERRUPT: u16 = 0x3C;
+
+const COMMAND_IO: u32 = 1 << 0;
+const COMMAND_MEMORY: u32 = 1 << 1;
+const STATUS_CAPABILITIES: u32 = 1 << (16 + 4);
+
+const HEADER_TYPE_ENDPOINT: u8 = 0x00;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+/// The capability list is at most 48 entries long, as the configuration space is 256 bytes.
+const MAX_CAPABILITIES: usize = 48;
+
+/// The extended capability list starts after the PCI-compatible space.
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+const MAX_EXTENDED_CAPABILITIES: usize = 32;
+
+/// A base address register, with the size of its region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BarInfo {
+    Memory { address: u64, size: u64, prefetchable: bool, is_64bit: bool },
+    Io { port: u32, size: u32 },
+}
+
+/// A capability in the configuration space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    pub offset: u16,
+}
+
+/// A PCI Express extended capability, such as AER (0x01) or SR-IOV (0x10).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+/// A function found by the enumeration.
+#[derive(Clone, Debug)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub interface: u8,
+    pub revision: u8,
+    /// Without the multifunction bit.
+    pub header_type: u8,
+    /// Endpoints have 6 BARs, and bridges 2. The upper half of a 64-bit BAR is None.
+    pub bars: [Option<BarInfo>; 6],
+    pub capabilities: heapless::Vec<Capability, MAX_CAPABILITIES>,
+    /// Empty without the extended configuration space.
+    pub extended_capabilities: heapless::Vec<ExtendedCapability, MAX_EXTENDED_CAPABILITIES>,
+    pub interrupt_line: u8,
+    /// 1 to 4 for INTA# to INTD#, or 0 for none.
+    pub interrupt_pin: u8,
+}
+
+impl PciDevice {
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == HEADER_TYPE_BRIDGE
+            && (self.class, self.subclass) == (CLASS_BRIDGE, SUBCLASS_PCI_BRIDGE)
+    }
+
+    pub fn capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities.iter().copied().find(|cap| cap.id == id)
+    }
+
+    pub fn extended_capability(&self, id: u16) -> Option<ExtendedCapability> {
+        self.extended_capabilities.iter().copied().find(|cap| cap.id == id)
+    }
+}
+
+impl core::fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f, "{:04x}:{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}{:02x}{:02x}",
+            self.address.segment(), self.address.bus(), self.address.slot(), self.address.function(),
+            self.vendor_id, self.device_id, self.class, self.subclass, self.interface,
         )
-    }).flatten()
+    }
+}
+
+/// Size a BAR by writing all ones, with the decoding disabled meanwhile.
+/// Returns the BAR and the number of registers it takes.
+unsafe fn read_bar(access: &impl DwordAccessMethod, addr: PciAddress, index: u16, bar_count: u16) -> (Option<BarInfo>, u16) {
+    let offset = REG_BAR0 + 4 * index;
+    let probe = |offset: u16| {
+        let original = access.read_dword(addr, offset);
+        access.write_dword(addr, offset, u32::MAX);
+        let mask = access.read_dword(addr, offset);
+        access.write_dword(addr, offset, original);
+        (original, mask)
+    };
+
+    let (low, low_mask) = probe(offset);
+    if low & 1 == 1 {
+        // the upper half may read back zeros, for 16-bit I/O decoders.
+        let mask = match low_mask & !0b11 {
+            mask if mask >> 16 == 0 => mask as u16 as i16 as i32 as u32,
+            mask => mask,
+        };
+        let bar = (mask != 0).then_some(BarInfo::Io { port: low & !0b11, size: !mask + 1 });
+        return (bar, 1);
+    }
+
+    let prefetchable = low & 0b1000 != 0;
+    let is_64bit = (low >> 1) & 0b11 == 0b10;
+    // the upper half would be past the BARs, e.g. the CardBus CIS pointer or the bus numbers of a bridge.
+    if is_64bit && index + 1 >= bar_count {
+        return (None, 1);
+    }
+    let (address, mask) = if is_64bit {
+        let (high, high_mask) = probe(offset + 4);
+        ((high as u64) << 32 | (low & !0xF) as u64, (high_mask as u64) << 32 | (low_mask & !0xF) as u64)
+    } else {
+        ((low & !0xF) as u64, (low_mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000)
+    };
+    // unimplemented BARs read back zeros.
+    let bar = (mask != 0 && (is_64bit || mask & 0xFFFF_FFFF != 0))
+        .then_some(BarInfo::Memory { address, size: !mask + 1, prefetchable, is_64bit });
+    (bar, if is_64bit { 2 } else { 1 })
+}
+
+/// Read a function, sizing its BARs and walking its capability list.
+///
+/// # Safety
+/// The BARs are briefly rewritten, so the function should not be in use.
+pub unsafe fn read_device(access: &impl ConfigSpace, addr: PciAddress) -> PciDevice {
+    let id = access.read_dword(addr, REG_ID);
+    let class = access.read_dword(addr, REG_CLASS);
+    let header_type = (access.read_dword(addr, REG_HEADER_TYPE) >> 16) as u8 & 0x7F;
+    let interrupt = access.read_dword(addr, REG_INTERRUPT);
+    let command_status = access.read_dword(addr, REG_COMMAND_STATUS);
+
+    let mut bars = [None; 6];
+    let bar_count = match header_type {
+        HEADER_TYPE_ENDPOINT => 6,
+        HEADER_TYPE_BRIDGE => 2,
+        _ => 0, // CardBus
+    };
+    if bar_count > 0 {
+        access.write_dword(addr, REG_COMMAND_STATUS, command_status & !(COMMAND_IO | COMMAND_MEMORY) & 0xFFFF);
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, len) = read_bar(access, addr, index, bar_count);
+            bars[index as usize] = bar;
+            index += len;
+        }
+        access.write_dword(addr, REG_COMMAND_STATUS, command_status & 0xFFFF);
+    }
+
+    let mut capabilities = heapless::Vec::new();
+    if command_status & STATUS_CAPABILITIES != 0 {
+        let mut offset = (access.read_dword(addr, REG_CAPABILITIES) & 0xFC) as u16;
+        while offset != 0 && !capabilities.is_full() {
+            let header = access.read_dword(addr, offset);
+            let _ = capabilities.push(Capability { id: header as u8, offset });
+            offset = ((header >> 8) & 0xFC) as u16;
+        }
+    }
+
+    let mut extended_capabilities = heapless::Vec::new();
+    if access.is_extended() {
+        // a function without extended capabilities has zeros, or all ones on a conventional PCI device.
+        let mut offset = EXTENDED_CAPABILITIES;
+        while offset >= EXTENDED_CAPABILITIES && !extended_capabilities.is_full() {
+            let header = access.read_dword(addr, offset);
+            if header == 0 || header == u32::MAX { break; }
+            let _ = extended_capabilities.push(ExtendedCapability {
+                id: header as u16,
+                version: ((header >> 16) & 0xF) as u8,
+                offset,
+            });
+            offset = ((header >> 20) & 0xFFC) as u16;
+        }
+    }
+
+    PciDevice {
+        address: addr,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        interface: (class >> 8) as u8,
+        revision: class as u8,
+        header_type,
+        bars,
+        capabilities,
+        extended_capabilities,
+        interrupt_line: interrupt as u8,
+        interrupt_pin: (interrupt >> 8) as u8,
+    }
+}
+
+/// Enumerate the functions reachable from the host bridges of each segment group, following PCI-to-PCI bridges depth-first.
+///
+/// # Safety
+/// This should be called before any driver uses the devices. (see [`read_device`])
+pub unsafe fn enumerate(access: &impl ConfigSpace) -> alloc::vec::Vec<PciDevice> {
+    let mut devices = alloc::vec::Vec::new();
+
+    for (segment, root_bus) in access.root_buses() {
+        let mut visited = [false; 256];
+
+        // with a multifunction host bridge, each function is the host bridge of a bus.
+        let host = PciAddress::new(segment, root_bus, 0, 0);
+        if access.function_exists(host) && access.has_multiple_functions(host) {
+            for function in 0..8 {
+                let addr = PciAddress::new(segment, root_bus, 0, function);
+                let Some(bus) = root_bus.checked_add(function) else { break };
+                if access.function_exists(addr) {
+                    scan_bus(access, segment, bus, &mut visited, &mut devices);
+                }
+            }
+        } else {
+            scan_bus(access, segment, root_bus, &mut visited, &mut devices);
+        }
+    }
+    devices
+}
+
+unsafe fn scan_bus(
+    access: &impl ConfigSpace,
+    segment: u16,
+    bus: u8,
+    visited: &mut [bool; 256],
+    devices: &mut alloc::vec::Vec<PciDevice>,
+) {
+    // a misconfigured bridge may point back to a scanned bus.
+    if core::mem::replace(&mut visited[bus as usize], true) { return; }
+
+    for slot in 0..32 {
+        let addr0 = PciAddress::new(segment, bus, slot, 0);
+        if !access.function_exists(addr0) { continue; }
+        let functions = if access.has_multiple_functions(addr0) { 0..8 } else { 0..1 };
+
+        for function in functions {
+            let addr = PciAddress::new(segment, bus, slot, function);
+            if !access.function_exists(addr) { continue; }
+
+            let device = read_device(access, addr);
+            let secondary_bus = device.is_bridge()
+                .then(|| (access.read_dword(addr, REG_BUS_NUMBERS) >> 8) as u8);
+            devices.push(device);
+
+            if let Some(secondary_bus) = secondary_bus.filter(|&bus| bus != 0) {
+                scan_bus(access, segment, secondary_bus, visited, devices);
+            }
+        }
+    }
+}
+
+/// What a driver binds to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PciMatch {
+    /// The class code. None matches any interface.
+    Class { class: u8, subclass: u8, interface: Option<u8> },
+    /// The vendor ID. None matches any device.
+    Id { vendor_id: u16, device_id: Option<u16> },
+}
+
+impl PciMatch {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        match *self {
+            Self::Class { class, subclass, interface } => {
+                (device.class, device.subclass) == (class, subclass)
+                    && interface.is_none_or(|interface| interface == device.interface)
+            },
+            Self::Id { vendor_id, device_id } => {
+                device.vendor_id == vendor_id
+                    && device_id.is_none_or(|device_id| device_id == device.device_id)
+            },
+        }
+    }
+}
+
+/// A driver of PCI functions.
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Take a matching device. Returns false if the device is not usable, leaving it to later drivers.
+    pub probe: fn(&PciDevice) -> bool,
 }
 
+impl PciDriver {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.matches.iter().any(|m| m.matches(device))
+    }
+}
+
+/// A fake configuration space, for the tests of the modules on PCI.
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// Configuration spaces in memory. BARs read back their size masks after all ones are written,
+    /// and BARs without masks are unimplemented.
+    #[derive(Default)]
+    pub(crate) struct FakeConfig {
+        pub(crate) spaces: RefCell<HashMap<(u8, u8, u8), [u32; 1024]>>,
+        pub(crate) bar_masks: HashMap<(u8, u8, u8, u16), u32>,
+    }
+
+    impl FakeConfig {
+        pub(crate) fn add(&mut self, (bus, slot, function): (u8, u8, u8), dwords: &[(u16, u32)]) {
+            let mut space = [0; 1024];
+            for &(offset, value) in dwords {
+                space[offset as usize / 4] = value;
+            }
+            self.spaces.get_mut().insert((bus, slot, function), space);
+        }
+
+        pub(crate) fn key(addr: PciAddress) -> (u8, u8, u8) {
+            (addr.bus(), addr.slot(), addr.function())
+        }
+
+        pub(crate) fn get(&self, function: (u8, u8, u8), offset: u16) -> u32 {
+            self.spaces.borrow()[&function][offset as usize / 4]
+        }
+    }
+
+    impl DwordAccessMethod for FakeConfig {
+        unsafe fn read_dword(&self, address: PciAddress, offset: u16) -> u32 {
+            self.spaces.borrow().get(&Self::key(address)).map_or(u32::MAX, |space| space[offset as usize / 4])
+        }
+
+        unsafe fn write_dword(&self, address: PciAddress, offset: u16, value: u32) {
+            let (bus, slot, function) = Self::key(address);
+            let mask = self.bar_masks.get(&(bus, slot, function, offset));
+            if let Some(space) = self.spaces.borrow_mut().get_mut(&(bus, slot, function)) {
+                let old = space[offset as usize / 4];
+                space[offset as usize / 4] = match mask {
+                    Some(&mask) if value == u32::MAX => mask | (old & 0xF),
+                    None if (REG_BAR0..REG_BAR0 + 24).contains(&offset) => old,
+                    _ => value,
+                };
+            }
+        }
+
+        fn function_exists(&self, address: PciAddress) -> bool {
+            self.spaces.borrow().contains_key(&Self::key(address))
+        }
+
+        fn has_multiple_functions(&self, address: PciAddress) -> bool {
+            unsafe { self.read_dword(address, REG_HEADER_TYPE) & (0x80 << 16) != 0 }
+        }
+    }
+
+    impl ConfigSpace for FakeConfig {
+        fn root_buses(&self) -> impl Iterator<Item = (u16, u8)> {
+            core::iter::once((0, 0))
+        }
+
+        fn is_extended(&self) -> bool {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::fake::FakeConfig;
+
+    #[test]
+    fn enumerates_behind_bridges() {
+        let mut config = FakeConfig::default();
+        config.add((0, 0, 0), &[(REG_ID, 0x1237_8086), (REG_CLASS, 0x0600_0000)]);
+        // a bridge to bus 2, and a misconfigured one claiming the same bus.
+        config.add((0, 1, 0), &[(REG_ID, 0x0001_1b36), (REG_CLASS, 0x0604_0000), (REG_HEADER_TYPE, 0x01 << 16), (REG_BUS_NUMBERS, 0x0002_0200)]);
+        config.add((0, 2, 0), &[(REG_ID, 0x0001_1b36), (REG_CLASS, 0x0604_0000), (REG_HEADER_TYPE, 0x01 << 16), (REG_BUS_NUMBERS, 0x0002_0200)]);
+        // an xHCI with a 64-bit BAR, MSI and MSI-X.
+        config.add((2, 0, 0), &[
+            (REG_ID, 0x000d_1b36),
+            (REG_COMMAND_STATUS, STATUS_CAPABILITIES | COMMAND_MEMORY),
+            (REG_CLASS, 0x0c03_3001),
+            (REG_BAR0, 0xfebf_0004),
+            (REG_CAPABILITIES, 0x90),
+            (REG_INTERRUPT, 0x0000_010b),
+            (0x90, 0x0000_a011),
+            (0xa0, 0x0000_0005),
+            (0x100, 0x1401_0001), // AER, then SR-IOV at 0x140
+            (0x140, 0x0001_0010),
+        ]);
+        config.bar_masks.insert((2, 0, 0, REG_BAR0), 0xffff_c000);
+        config.bar_masks.insert((2, 0, 0, REG_BAR0 + 4), 0xffff_ffff);
+
+        let devices = unsafe { enumerate(&config) };
+        let addrs: Vec<_> = devices.iter().map(|device| FakeConfig::key(device.address)).collect();
+        assert_eq!(addrs, [(0, 0, 0), (0, 1, 0), (2, 0, 0), (0, 2, 0)]);
+
+        let xhci = &devices[2];
+        assert!(PciMatch::Class { class: 0x0c, subclass: 0x03, interface: Some(0x30) }.matches(xhci));
+        assert!(!PciMatch::Id { vendor_id: 0x1b36, device_id: Some(0x0001) }.matches(xhci));
+        assert_eq!(xhci.bars[0], Some(BarInfo::Memory { address: 0xfebf_0000, size: 0x4000, prefetchable: false, is_64bit: true }));
+        assert_eq!(xhci.bars[1..], [None; 5]);
+        assert_eq!(xhci.capabilities, [Capability { id: 0x11, offset: 0x90 }, Capability { id: 0x05, offset: 0xa0 }]);
+        assert_eq!(xhci.extended_capabilities, [
+            ExtendedCapability { id: 0x01, version: 1, offset: 0x100 },
+            ExtendedCapability { id: 0x10, version: 1, offset: 0x140 },
+        ]);
+        assert_eq!((xhci.interrupt_line, xhci.interrupt_pin), (0x0b, 1));
+
+        // sizing restores the BARs and the command register.
+        unsafe {
+            assert_eq!(config.read_dword(xhci.address, REG_BAR0), 0xfebf_0004);
+            assert_eq!(config.read_dword(xhci.address, REG_COMMAND_STATUS) & 0xFFFF, COMMAND_MEMORY);
+        }
+    }
+
+    #[test]
+    fn sizes_io_and_last_bars() {
+        let mut config = FakeConfig::default();
+        // a 32-byte I/O BAR with a 16-bit decoder, and a 64-bit BAR in the last slot.
+        config.add((0, 0, 0), &[
+            (REG_ID, 0x1000_8086),
+            (REG_CLASS, 0x0200_0000),
+            (REG_BAR0 + 8, 0x0000_c001),
+            (REG_BAR0 + 20, 0xfe00_0004),
+            (REG_BAR0 + 24, 0x1234), // CardBus CIS pointer
+        ]);
+        config.bar_masks.insert((0, 0, 0, REG_BAR0 + 8), 0x0000_ffe0);
+        config.bar_masks.insert((0, 0, 0, REG_BAR0 + 20), 0xffff_c000);
+
+        let device = unsafe { read_device(&config, PciAddress::new(0, 0, 0, 0)) };
+        assert_eq!(device.bars[2], Some(BarInfo::Io { port: 0xc000, size: 0x20 }));
+        assert_eq!(device.bars[5], None);
+        assert_eq!(unsafe { config.read_dword(device.address, REG_BAR0 + 24) }, 0x1234);
+    }
+
+    #[test]
+    fn maps_ecam_addresses() {
+        static REGIONS: [McfgEntry; 2] = [
+            McfgEntry { base: 0xb000_0000, segment: 0, start_bus: 0, end_bus: 0xff },
+            McfgEntry { base: 0xe000_0000, segment: 1, start_bus: 0x80, end_bus: 0x8f },
+        ];
+        let ecam = unsafe { EcamAccessMethod::new(&REGIONS) };
+
+        assert_eq!(ecam.config_address(PciAddress::new(0, 1, 2, 3), 0x104), Some(0xb011_3104));
+        assert_eq!(ecam.config_address(PciAddress::new(1, 0x81, 0, 0), 0xffc), Some(0xe010_0ffc));
+        assert_eq!(ecam.config_address(PciAddress::new(1, 0x90, 0, 0), 0), None);
+        assert_eq!(ecam.config_address(PciAddress::new(2, 0, 0, 0), 0), None);
+        assert_eq!(ecam.root_buses().collect::<Vec<_>>(), [(0, 0), (1, 0x80)]);
+    }
+}
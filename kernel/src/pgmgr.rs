@@ -1,4 +1,10 @@
 use bit_field::BitField;
+use x86_64::PhysAddr;
+use x86_64::structures::paging::{
+    FrameAllocator,
+    PhysFrame,
+    Size4KiB,
+};
 // use shared::uefi_memory::PAGE_SIZE as UEFI_PAGE_SIZE;
 
 pub const KB: usize = 0x400;
@@ -26,6 +32,12 @@ impl FrameID {
     }
 }
 
+impl From<FrameID> for PhysFrame<Size4KiB> {
+    fn from(id: FrameID) -> Self {
+        PhysFrame::containing_address(PhysAddr::new(id.addr() as u64))
+    }
+}
+
 /// Page Status of either vacant or using.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PageStat {
@@ -136,6 +148,42 @@ impl PageManager { // allocation and freeing
         Err(PageAllocationError::NotEnoughMemory)
     }
 
+    /// Allocate pages of the given page count, whose start frame id is a multiple of `align`,
+    /// and which lie below `limit`.
+    pub fn allocate_constrained(&mut self, page_cnt: usize, align: usize, limit: FrameID) -> Result<FrameID> {
+        assert!(align.is_power_of_two());
+
+        let end = self.end.min(limit);
+        let mut start = self.begin.0.next_multiple_of(align);
+        while start + page_cnt <= end.0 {
+            // skip to the next aligned candidate after the first non-vacant frame.
+            match ((start)..(start + page_cnt)).find(|&i| self.get_stat(FrameID(i)) != PageStat::Vacant) {
+                Some(used) => { start = (used + 1).next_multiple_of(align); },
+                None => {
+                    self.set_range_stat(FrameID(start), page_cnt, PageStat::Using);
+                    return Ok(FrameID(start));
+                },
+            }
+        }
+        Err(PageAllocationError::NotEnoughMemory)
+    }
+
+    /// Allocate pages of the given page count, starting from the given frame id.
+    /// Fails if any of them is not vacant.
+    pub fn allocate_at(&mut self, begin: FrameID, page_cnt: usize) -> Result<FrameID> {
+        if begin < self.begin || begin.0 + page_cnt > self.end.0 {
+            return Err(PageAllocationError::NotEnoughMemory);
+        }
+        for i in (begin.0)..(begin.0 + page_cnt) {
+            if self.get_stat(FrameID(i)) != PageStat::Vacant {
+                return Err(PageAllocationError::NotEnoughMemory);
+            }
+        }
+
+        self.set_range_stat(begin, page_cnt, PageStat::Using);
+        Ok(begin)
+    }
+
     /// Free pages of the given range.
     /// In this simple page manager, we need both start frame id and page count.
     /// Memoizing the list might be the future improvement.
@@ -147,6 +195,14 @@ impl PageManager { // allocation and freeing
     }
 }
 
+/// Page table frames for the mapper.
+/// Since frames are identity-mapped, the mapper can zero them by itself.
+unsafe impl FrameAllocator<Size4KiB> for PageManager {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate(1).ok().map(PhysFrame::from)
+    }
+}
+
 impl PageManager { // statistics - part
     /// Returns total number of frames of which this manager is responsible.
     /// 
@@ -0,0 +1,123 @@
+//! System reset and ACPI soft-off.
+//!
+//! A reset is tried through the FADT reset register, the keyboard controller, and finally a triple fault.
+//! https://wiki.osdev.org/Reboot
+//! https://uefi.org/specs/ACPI/6.5/04_ACPI_Hardware_Specification.html#pm1-control-registers
+
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::acpi::{GenericAddress, ResetRegister, SleepType};
+
+// PM1 control register bits.
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+
+// the legacy PCI configuration mechanism.
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+// the i8042 keyboard controller.
+const KBC_STATUS: u16 = 0x64;
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerError {
+    /// No ACPI tables, or no FADT.
+    NoAcpi,
+    /// The FADT has no PM1a control register.
+    NoPm1Control,
+    /// No `_Sx_` object found in the AML.
+    NoSleepType(u8),
+    /// The system is still running after entering the sleep state.
+    Ignored,
+    /// The register is in an address space other than memory, I/O, or PCI configuration.
+    UnsupportedAddressSpace(u8),
+    /// The firmware didn't hand over to ACPI mode.
+    AcpiNotEnabled,
+}
+
+/// Write the reset value to the FADT reset register.
+///
+/// # Safety
+/// This resets the system. A memory register should be identity-mapped.
+pub unsafe fn write_reset_register(reset: ResetRegister) -> Result<(), PowerError> {
+    let address = reset.register.address;
+    match reset.register.address_space {
+        GenericAddress::SYSTEM_MEMORY => (address as *mut u8).write_volatile(reset.value),
+        GenericAddress::SYSTEM_IO => Port::<u8>::new(address as u16).write(reset.value),
+        GenericAddress::PCI_CONFIG => {
+            // bus 0. the address is device (47:32), function (31:16) and offset (15:0).
+            let device = ((address >> 32) & 0x1F) as u32;
+            let function = ((address >> 16) & 0x7) as u32;
+            let offset = (address & 0xFF) as u32;
+            Port::<u32>::new(PCI_CONFIG_ADDRESS).write(1 << 31 | device << 11 | function << 8 | offset & !0b11);
+            Port::<u8>::new(PCI_CONFIG_DATA + (offset & 0b11) as u16).write(reset.value);
+        },
+        space => return Err(PowerError::UnsupportedAddressSpace(space)),
+    }
+    Ok(())
+}
+
+/// Pulse the CPU reset line through the keyboard controller.
+///
+/// # Safety
+/// This resets the system, if there's an i8042.
+pub unsafe fn pulse_keyboard_controller_reset() {
+    let mut status = Port::<u8>::new(KBC_STATUS);
+    // a missing controller reads 0xFF, so don't wait forever.
+    for _ in 0..0x10000 {
+        if status.read() & KBC_STATUS_INPUT_FULL == 0 { break; }
+        core::hint::spin_loop();
+    }
+    status.write(KBC_PULSE_RESET);
+}
+
+/// Reset the CPU with a triple fault: any exception without an IDT.
+///
+/// # Safety
+/// This never returns, and nothing is cleaned up.
+pub unsafe fn triple_fault() -> ! {
+    let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    x86_64::instructions::tables::lidt(&idt);
+    core::arch::asm!("int3", options(noreturn));
+}
+
+/// Switch the firmware to ACPI mode, which is required for the sleep registers.
+///
+/// # Safety
+/// `pm1a_control` and `smi_command` should be from the FADT.
+pub unsafe fn enable_acpi(pm1a_control: u16, smi_command: u32, acpi_enable: u8, mut delay_ms: impl FnMut(u64)) -> Result<(), PowerError> {
+    let mut control = Port::<u16>::new(pm1a_control);
+    if control.read() & SCI_EN != 0 { return Ok(()); }
+    // hardware-reduced systems have no SMI command port, and are always in ACPI mode.
+    if smi_command == 0 || acpi_enable == 0 { return Ok(()); }
+
+    Port::<u8>::new(smi_command as u16).write(acpi_enable);
+    for _ in 0..300 {
+        if control.read() & SCI_EN != 0 { return Ok(()); }
+        delay_ms(10);
+    }
+    Err(PowerError::AcpiNotEnabled)
+}
+
+/// Enter a sleep state by writing `SLP_TYP` and `SLP_EN` to the PM1 control registers.
+/// For S5 this powers off, and returns only if the firmware ignored it.
+///
+/// # Safety
+/// The ports should be from the FADT, and the sleep type from the AML.
+pub unsafe fn enter_sleep_state(pm1a_control: u16, pm1b_control: Option<u16>, sleep_type: SleepType) {
+    let write = |port: u16, slp_typ: u8| {
+        let mut control = Port::<u16>::new(port);
+        let value = control.read() & !(SLP_TYP_MASK | SLP_EN);
+        control.write(value | ((slp_typ as u16) << SLP_TYP_SHIFT & SLP_TYP_MASK) | SLP_EN);
+    };
+    if let Some(pm1b_control) = pm1b_control {
+        write(pm1b_control, sleep_type.b);
+    }
+    write(pm1a_control, sleep_type.a);
+}
@@ -0,0 +1,431 @@
+//! i8042 PS/2 controller driver, with scancode set 2 and mouse packet decoding.
+//!
+//! Keys and mouse movements are reported as USB HID boot reports, the same as the xHCI path.
+//! https://wiki.osdev.org/I8042_PS/2_Controller
+//! https://wiki.osdev.org/PS/2_Keyboard
+
+use x86_64::instructions::port::Port;
+
+use crate::xhci::class::{KeyboardBitSet, KeyboardReport, MouseReport};
+
+/// The legacy IRQ numbers of the PS/2 ports.
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const MOUSE_IRQ: u8 = 12;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64; // or the command port, on write.
+
+// status bits.
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+const STATUS_AUX: u8 = 0x20; // the output is from the second (mouse) port.
+
+// controller commands.
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xA7;
+const CMD_ENABLE_AUX: u8 = 0xA8;
+const CMD_TEST_AUX: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_KEYBOARD: u8 = 0xAB;
+const CMD_DISABLE_KEYBOARD: u8 = 0xAD;
+const CMD_ENABLE_KEYBOARD: u8 = 0xAE;
+const CMD_WRITE_AUX: u8 = 0xD4;
+
+// configuration bits.
+const CONFIG_KEYBOARD_IRQ: u8 = 0x01;
+const CONFIG_AUX_IRQ: u8 = 0x02;
+const CONFIG_AUX_DISABLED: u8 = 0x20;
+const CONFIG_TRANSLATION: u8 = 0x40; // translates to scancode set 1, which we don't want.
+
+// device commands and responses.
+const DEV_RESET: u8 = 0xFF;
+const DEV_SET_DEFAULTS: u8 = 0xF6;
+const DEV_ENABLE_REPORTING: u8 = 0xF4;
+const DEV_SCANCODE_SET: u8 = 0xF0;
+const DEV_ACK: u8 = 0xFA;
+const DEV_SELF_TEST_PASSED: u8 = 0xAA;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+
+/// Polling iterations before a timeout. There's no timer yet, so this is a rough bound.
+const POLL_LIMIT: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller or a device didn't respond, e.g. there's no controller.
+    Timeout,
+    /// The controller self test failed.
+    SelfTestFailed(u8),
+    /// A device didn't acknowledge the command.
+    NoAck { command: u8, response: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Device {
+    Keyboard,
+    Mouse,
+}
+
+/// The devices which are initialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Devices {
+    pub keyboard: bool,
+    pub mouse: bool,
+}
+
+/// The i8042 controller.
+pub struct Controller {
+    data: Port<u8>,
+    status: Port<u8>,
+}
+
+impl Controller {
+    /// # Safety
+    /// The PS/2 ports should not be used by any other code.
+    pub const unsafe fn new() -> Self {
+        Self { data: Port::new(DATA_PORT), status: Port::new(STATUS_PORT) }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.status.read() }
+    }
+
+    fn wait(&mut self, ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+        for _ in 0..POLL_LIMIT {
+            if ready(self.status()) { return Ok(()); }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait(|status| status & STATUS_INPUT_FULL == 0)?;
+        unsafe { self.status.write(command) };
+        Ok(())
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait(|status| status & STATUS_INPUT_FULL == 0)?;
+        unsafe { self.data.write(byte) };
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<u8, Ps2Error> {
+        self.wait(|status| status & STATUS_OUTPUT_FULL != 0)?;
+        Ok(unsafe { self.data.read() })
+    }
+
+    fn flush(&mut self) {
+        // a missing controller reads as 0xFF, which never empties.
+        for _ in 0..POLL_LIMIT {
+            if self.status() & STATUS_OUTPUT_FULL == 0 { return; }
+            unsafe { self.data.read() };
+        }
+    }
+
+    fn config(&mut self) -> Result<u8, Ps2Error> {
+        self.command(CMD_READ_CONFIG)?;
+        self.read()
+    }
+
+    fn set_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.command(CMD_WRITE_CONFIG)?;
+        self.write(config)
+    }
+
+    /// Send a command to a device, and wait for the acknowledgement.
+    fn send(&mut self, device: Device, command: u8) -> Result<(), Ps2Error> {
+        if device == Device::Mouse { self.command(CMD_WRITE_AUX)?; }
+        self.write(command)?;
+        match self.read()? {
+            DEV_ACK => Ok(()),
+            response => Err(Ps2Error::NoAck { command, response }),
+        }
+    }
+
+    /// Reset a device, and wait for its self test.
+    fn reset(&mut self, device: Device) -> Result<(), Ps2Error> {
+        self.send(device, DEV_RESET)?;
+        match self.read()? {
+            DEV_SELF_TEST_PASSED => {},
+            response => return Err(Ps2Error::NoAck { command: DEV_RESET, response }),
+        }
+        if device == Device::Mouse { self.read()?; } // the device ID
+        Ok(())
+    }
+
+    /// Initialize the controller and the devices, with IRQs enabled on the working ports.
+    /// The keyboard is set to the scancode set 2, and the mouse sends the standard 3-byte packets.
+    pub fn init(&mut self) -> Result<Devices, Ps2Error> {
+        self.command(CMD_DISABLE_KEYBOARD)?;
+        self.command(CMD_DISABLE_AUX)?;
+        self.flush();
+
+        let config = self.config()? & !(CONFIG_KEYBOARD_IRQ | CONFIG_AUX_IRQ | CONFIG_TRANSLATION);
+        self.set_config(config)?;
+
+        self.command(CMD_SELF_TEST)?;
+        match self.read()? {
+            SELF_TEST_PASSED => {},
+            response => return Err(Ps2Error::SelfTestFailed(response)),
+        }
+        self.set_config(config)?; // the self test may reset the controller.
+
+        // the second port exists if enabling it clears the disabled bit.
+        self.command(CMD_ENABLE_AUX)?;
+        let dual = self.config()? & CONFIG_AUX_DISABLED == 0;
+        self.command(CMD_DISABLE_AUX)?;
+
+        self.command(CMD_TEST_KEYBOARD)?;
+        let mut keyboard = self.read()? == 0;
+        let mut mouse = dual && {
+            self.command(CMD_TEST_AUX)?;
+            self.read()? == 0
+        };
+
+        if keyboard {
+            self.command(CMD_ENABLE_KEYBOARD)?;
+            keyboard = self.reset(Device::Keyboard)
+                .and_then(|_| self.send(Device::Keyboard, DEV_SCANCODE_SET))
+                .and_then(|_| self.send(Device::Keyboard, 2))
+                .is_ok();
+        }
+        if mouse {
+            self.command(CMD_ENABLE_AUX)?;
+            mouse = self.reset(Device::Mouse)
+                .and_then(|_| self.send(Device::Mouse, DEV_SET_DEFAULTS))
+                .and_then(|_| self.send(Device::Mouse, DEV_ENABLE_REPORTING))
+                .is_ok();
+        }
+        self.flush();
+
+        let mut config = self.config()?;
+        if keyboard { config |= CONFIG_KEYBOARD_IRQ; }
+        if mouse { config |= CONFIG_AUX_IRQ; }
+        self.set_config(config)?;
+
+        Ok(Devices { keyboard, mouse })
+    }
+
+    /// Read a byte from a device, if any.
+    pub fn try_read(&mut self) -> Option<(Device, u8)> {
+        let status = self.status();
+        if status & STATUS_OUTPUT_FULL == 0 { return None; }
+
+        let device = if status & STATUS_AUX != 0 { Device::Mouse } else { Device::Keyboard };
+        Some((device, unsafe { self.data.read() }))
+    }
+}
+
+/// A key in HID terms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    /// A bit of the modifier byte.
+    Modifier(u8),
+    /// A keyboard/keypad page usage ID.
+    Usage(u8),
+}
+
+/// Scancode set 2 make codes to HID keys.
+fn key(extended: bool, code: u8) -> Option<Key> {
+    use Key::*;
+    Some(match (extended, code) {
+        (false, 0x14) => Modifier(0), // left ctrl
+        (false, 0x12) => Modifier(1), // left shift
+        (false, 0x11) => Modifier(2), // left alt
+        (true, 0x1F) => Modifier(3), // left gui
+        (true, 0x14) => Modifier(4), // right ctrl
+        (false, 0x59) => Modifier(5), // right shift
+        (true, 0x11) => Modifier(6), // right alt
+        (true, 0x27) => Modifier(7), // right gui
+
+        // letters
+        (false, 0x1C) => Usage(0x04), (false, 0x32) => Usage(0x05), (false, 0x21) => Usage(0x06),
+        (false, 0x23) => Usage(0x07), (false, 0x24) => Usage(0x08), (false, 0x2B) => Usage(0x09),
+        (false, 0x34) => Usage(0x0A), (false, 0x33) => Usage(0x0B), (false, 0x43) => Usage(0x0C),
+        (false, 0x3B) => Usage(0x0D), (false, 0x42) => Usage(0x0E), (false, 0x4B) => Usage(0x0F),
+        (false, 0x3A) => Usage(0x10), (false, 0x31) => Usage(0x11), (false, 0x44) => Usage(0x12),
+        (false, 0x4D) => Usage(0x13), (false, 0x15) => Usage(0x14), (false, 0x2D) => Usage(0x15),
+        (false, 0x1B) => Usage(0x16), (false, 0x2C) => Usage(0x17), (false, 0x3C) => Usage(0x18),
+        (false, 0x2A) => Usage(0x19), (false, 0x1D) => Usage(0x1A), (false, 0x22) => Usage(0x1B),
+        (false, 0x35) => Usage(0x1C), (false, 0x1A) => Usage(0x1D),
+
+        // digits
+        (false, 0x16) => Usage(0x1E), (false, 0x1E) => Usage(0x1F), (false, 0x26) => Usage(0x20),
+        (false, 0x25) => Usage(0x21), (false, 0x2E) => Usage(0x22), (false, 0x36) => Usage(0x23),
+        (false, 0x3D) => Usage(0x24), (false, 0x3E) => Usage(0x25), (false, 0x46) => Usage(0x26),
+        (false, 0x45) => Usage(0x27),
+
+        (false, 0x5A) => Usage(0x28), // enter
+        (false, 0x76) => Usage(0x29), // escape
+        (false, 0x66) => Usage(0x2A), // backspace
+        (false, 0x0D) => Usage(0x2B), // tab
+        (false, 0x29) => Usage(0x2C), // space
+        (false, 0x4E) => Usage(0x2D), // -
+        (false, 0x55) => Usage(0x2E), // =
+        (false, 0x54) => Usage(0x2F), // [
+        (false, 0x5B) => Usage(0x30), // ]
+        (false, 0x5D) => Usage(0x31), // \
+        (false, 0x4C) => Usage(0x33), // ;
+        (false, 0x52) => Usage(0x34), // '
+        (false, 0x0E) => Usage(0x35), // `
+        (false, 0x41) => Usage(0x36), // ,
+        (false, 0x49) => Usage(0x37), // .
+        (false, 0x4A) => Usage(0x38), // /
+        (false, 0x58) => Usage(0x39), // caps lock
+
+        // F1-F12
+        (false, 0x05) => Usage(0x3A), (false, 0x06) => Usage(0x3B), (false, 0x04) => Usage(0x3C),
+        (false, 0x0C) => Usage(0x3D), (false, 0x03) => Usage(0x3E), (false, 0x0B) => Usage(0x3F),
+        (false, 0x83) => Usage(0x40), (false, 0x0A) => Usage(0x41), (false, 0x01) => Usage(0x42),
+        (false, 0x09) => Usage(0x43), (false, 0x78) => Usage(0x44), (false, 0x07) => Usage(0x45),
+
+        (true, 0x7C) => Usage(0x46), // print screen, after the fake shift.
+        (false, 0x7E) => Usage(0x47), // scroll lock
+        (true, 0x70) => Usage(0x49), // insert
+        (true, 0x6C) => Usage(0x4A), // home
+        (true, 0x7D) => Usage(0x4B), // page up
+        (true, 0x71) => Usage(0x4C), // delete
+        (true, 0x69) => Usage(0x4D), // end
+        (true, 0x7A) => Usage(0x4E), // page down
+        (true, 0x74) => Usage(0x4F), // right
+        (true, 0x6B) => Usage(0x50), // left
+        (true, 0x72) => Usage(0x51), // down
+        (true, 0x75) => Usage(0x52), // up
+
+        // keypad
+        (false, 0x77) => Usage(0x53), // num lock
+        (true, 0x4A) => Usage(0x54), // /
+        (false, 0x7C) => Usage(0x55), // *
+        (false, 0x7B) => Usage(0x56), // -
+        (false, 0x79) => Usage(0x57), // +
+        (true, 0x5A) => Usage(0x58), // enter
+        (false, 0x69) => Usage(0x59), (false, 0x72) => Usage(0x5A), (false, 0x7A) => Usage(0x5B),
+        (false, 0x6B) => Usage(0x5C), (false, 0x73) => Usage(0x5D), (false, 0x74) => Usage(0x5E),
+        (false, 0x6C) => Usage(0x5F), (false, 0x75) => Usage(0x60), (false, 0x7D) => Usage(0x61),
+        (false, 0x70) => Usage(0x62), // 0
+        (false, 0x71) => Usage(0x63), // .
+
+        (false, 0x61) => Usage(0x64), // non-US \
+        (true, 0x2F) => Usage(0x65), // application
+
+        _ => return None, // including the fake shifts around print screen.
+    })
+}
+
+/// Scancode set 2 decoder, which keeps the key state to make reports.
+#[derive(Default)]
+pub struct KeyboardDecoder {
+    extended: bool,
+    release: bool,
+    /// The remaining bytes of the pause sequence, which has no break code.
+    skip: u8,
+    modifier: u8,
+    keys: KeyboardBitSet,
+}
+
+impl KeyboardDecoder {
+    /// Feed a byte. Returns a report when the key state changes; typematic repeats are ignored.
+    pub fn feed(&mut self, byte: u8) -> Option<KeyboardReport> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+        match byte {
+            0xE0 => { self.extended = true; return None; },
+            0xF0 => { self.release = true; return None; },
+            0xE1 => { self.skip = 7; return None; },
+            _ => {},
+        }
+
+        let (extended, release) = (self.extended, self.release);
+        self.extended = false;
+        self.release = false;
+
+        let prev_keys = self.keys;
+        let prev_modifier = self.modifier;
+        match key(extended, byte)? {
+            Key::Modifier(bit) if release => self.modifier &= !(1 << bit),
+            Key::Modifier(bit) => self.modifier |= 1 << bit,
+            Key::Usage(usage) => self.keys.set(usage as usize, !release),
+        }
+
+        (self.keys != prev_keys || self.modifier != prev_modifier).then_some(KeyboardReport {
+            modifier: self.modifier,
+            cur_keys: self.keys,
+            prev_keys,
+        })
+    }
+}
+
+// mouse packet bits.
+const PACKET_ALWAYS_ONE: u8 = 0x08;
+const PACKET_X_SIGN: u8 = 0x10;
+const PACKET_Y_SIGN: u8 = 0x20;
+const PACKET_OVERFLOW: u8 = 0xC0;
+
+/// Standard 3-byte mouse packet decoder.
+#[derive(Default)]
+pub struct MouseDecoder {
+    packet: [u8; 3],
+    len: usize,
+}
+
+impl MouseDecoder {
+    /// Feed a byte. Returns a report when a packet is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<MouseReport> {
+        // resynchronize on a byte which can't start a packet.
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 { return None; }
+
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet.len() { return None; }
+        self.len = 0;
+
+        let [flags, x, y] = self.packet;
+        if flags & PACKET_OVERFLOW != 0 { return None; }
+
+        // 9-bit two's complement, and PS/2 Y grows upward while HID Y grows downward.
+        let x = x as isize - if flags & PACKET_X_SIGN != 0 { 0x100 } else { 0 };
+        let y = y as isize - if flags & PACKET_Y_SIGN != 0 { 0x100 } else { 0 };
+        Some(MouseReport { buttons: flags & 0x07, disp: (x, -y) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_scancode_set_2() {
+        let mut decoder = KeyboardDecoder::default();
+        let mut feed = |bytes: &[u8]| bytes.iter().filter_map(|&b| decoder.feed(b)).last();
+
+        // shift + a
+        assert_eq!(feed(&[0x12]).unwrap().modifier, 0b10);
+        let report = feed(&[0x1C]).unwrap();
+        assert!(report.cur_keys[0x04] && !report.prev_keys[0x04]);
+        assert!(feed(&[0x1C]).is_none()); // typematic repeat
+        let report = feed(&[0xF0, 0x1C]).unwrap();
+        assert!(!report.cur_keys[0x04] && report.prev_keys[0x04]);
+        assert_eq!(feed(&[0xF0, 0x12]).unwrap().modifier, 0);
+
+        // extended keys, and the pause sequence
+        assert!(feed(&[0xE0, 0x75]).unwrap().cur_keys[0x52]); // up
+        assert_eq!(feed(&[0xE0, 0x14]).unwrap().modifier, 0b1_0000); // right ctrl
+        assert!(feed(&[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77]).is_none());
+        assert!(!feed(&[0xE0, 0xF0, 0x75]).unwrap().cur_keys[0x52]);
+    }
+
+    #[test]
+    fn decodes_mouse_packets() {
+        let mut decoder = MouseDecoder::default();
+        let mut feed = |bytes: &[u8]| bytes.iter().filter_map(|&b| decoder.feed(b)).last();
+
+        assert_eq!(feed(&[0x09, 5, 3]), Some(MouseReport { buttons: 1, disp: (5, -3) }));
+        assert_eq!(feed(&[0x38, 0xFE, 0xFD]), Some(MouseReport { buttons: 0, disp: (-2, 3) }));
+        assert_eq!(feed(&[0x00, 0x0A, 1, 2]), Some(MouseReport { buttons: 2, disp: (1, -2) })); // resync
+        assert_eq!(feed(&[0x48, 0xFF, 0]), None); // overflow
+    }
+}
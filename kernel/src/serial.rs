@@ -0,0 +1,130 @@
+//! 16550 UART driver.
+//!
+//! https://wiki.osdev.org/Serial_Ports
+
+use core::fmt;
+
+use x86_64::instructions::port::Port;
+
+/// The I/O port base of COM1.
+pub const COM1_BASE: u16 = 0x3F8;
+
+/// The legacy IRQ number of COM1.
+pub const COM1_IRQ: u8 = 4;
+
+/// The base clock divided by the divisor gives the baud rate.
+const BASE_BAUD: u32 = 115200;
+
+// register offsets.
+const DATA: u16 = 0; // receive/transmit buffer, or divisor low byte if DLAB is set.
+const INT_ENABLE: u16 = 1; // or divisor high byte if DLAB is set.
+const FIFO_CTRL: u16 = 2;
+const LINE_CTRL: u16 = 3;
+const MODEM_CTRL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// line control bits.
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+
+// line status bits.
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+// modem control bits.
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+const MCR_OUT2: u8 = 0x08; // gates the IRQ line.
+const MCR_LOOPBACK: u8 = 0x10;
+
+/// Interrupt enable bit for received data.
+const IER_RECEIVED: u8 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// The loopback test failed, which means there's no UART on the port.
+    NotPresent,
+}
+
+/// A 16550-compatible UART.
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    /// # Safety
+    /// `base` should be the I/O port base of a UART, which is not used by any other code.
+    pub const unsafe fn new(base: u16) -> Self {
+        Self { base }
+    }
+
+    fn port(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+
+    fn read(&self, offset: u16) -> u8 {
+        unsafe { self.port(offset).read() }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        unsafe { self.port(offset).write(value) }
+    }
+
+    /// Initialize the UART in 8N1 with the given baud rate, and check that it is present by a loopback test.
+    /// Interrupts are disabled; see `enable_receive_interrupt`.
+    pub fn init(&mut self, baud: u32) -> Result<(), SerialError> {
+        let divisor = (BASE_BAUD / baud).max(1) as u16;
+
+        self.write(INT_ENABLE, 0x00);
+        self.write(LINE_CTRL, LCR_DLAB);
+        self.write(DATA, divisor as u8);
+        self.write(INT_ENABLE, (divisor >> 8) as u8);
+        self.write(LINE_CTRL, LCR_8N1);
+        self.write(FIFO_CTRL, 0xC7); // enable and clear FIFOs, with 14-byte threshold.
+
+        // loopback test
+        self.write(MODEM_CTRL, MCR_LOOPBACK | MCR_RTS | MCR_OUT2);
+        self.write(DATA, 0xAE);
+        if self.read(DATA) != 0xAE {
+            return Err(SerialError::NotPresent);
+        }
+
+        self.write(MODEM_CTRL, MCR_DTR | MCR_RTS | MCR_OUT2);
+        Ok(())
+    }
+
+    /// Raise the IRQ when a byte is received.
+    pub fn enable_receive_interrupt(&mut self) {
+        self.write(INT_ENABLE, IER_RECEIVED);
+    }
+
+    pub fn disable_interrupts(&mut self) {
+        self.write(INT_ENABLE, 0x00);
+    }
+
+    /// Send a byte, waiting for the transmitter to be ready.
+    pub fn send(&mut self, byte: u8) {
+        while self.read(LINE_STATUS) & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write(DATA, byte);
+    }
+
+    /// Take a received byte, if any. This is the polled receive path,
+    /// which is also used by the interrupt handler to drain the FIFO.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.read(LINE_STATUS) & LSR_DATA_READY == 0 { return None; }
+        Some(self.read(DATA))
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect CRLF.
+            if byte == b'\n' { self.send(b'\r'); }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! A line-based command shell, fed by COM1 and the keyboards.
+
+/// The longest command line.
+pub const LINE_LEN: usize = 80;
+
+/// What the caller should echo after a byte is fed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Insert(u8),
+    Erase,
+    /// The line is complete, and can be taken with `LineEditor::line`.
+    Submit,
+}
+
+/// The line being typed.
+pub struct LineEditor {
+    buf: heapless::Vec<u8, LINE_LEN>,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self { buf: heapless::Vec::new() }
+    }
+
+    /// Edit the line with a byte. Other control characters, and bytes past the end of the line, are ignored.
+    pub fn feed(&mut self, byte: u8) -> Option<Edit> {
+        match byte {
+            b'\r' | b'\n' => Some(Edit::Submit),
+            0x08 | 0x7F => self.buf.pop().map(|_| Edit::Erase), // backspace, or DEL from terminals.
+            0x20..=0x7E => self.buf.push(byte).ok().map(|()| Edit::Insert(byte)),
+            _ => None,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.buf).unwrap() // printable ASCII only.
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Empty,
+    Help,
+    /// Print the log records kept in the ring.
+    Dmesg,
+    /// List the PCI device table.
+    Lspci,
+    Reboot,
+    Shutdown,
+    Unknown(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// The commands and their descriptions, for `help`.
+    pub const HELP: &'static [(&'static str, &'static str)] = &[
+        ("help", "show this message"),
+        ("dmesg", "print the kernel log"),
+        ("lspci", "list the PCI devices and their drivers"),
+        ("reboot", "reset the system"),
+        ("shutdown", "power off with ACPI S5 (or `poweroff`)"),
+    ];
+
+    pub fn parse(line: &'a str) -> Self {
+        match line.split_whitespace().next() {
+            None => Self::Empty,
+            Some("help") => Self::Help,
+            Some("dmesg") => Self::Dmesg,
+            Some("lspci") => Self::Lspci,
+            Some("reboot") => Self::Reboot,
+            Some("shutdown" | "poweroff") => Self::Shutdown,
+            Some(name) => Self::Unknown(name),
+        }
+    }
+}
+
+/// The ASCII character of a HID keyboard usage, on the US layout.
+pub fn hid_to_ascii(usage: u8, shift: bool) -> Option<u8> {
+    const LETTERS: core::ops::RangeInclusive<u8> = 0x04..=0x1D;
+    const DIGITS: &[u8; 10] = b"1234567890";
+    const SHIFTED_DIGITS: &[u8; 10] = b"!@#$%^&*()";
+    // 0x2D..=0x38. 0x32 is the non-US `#`, which is `\` on the US layout.
+    const SYMBOLS: &[u8; 12] = b"-=[]\\\\;'`,./";
+    const SHIFTED_SYMBOLS: &[u8; 12] = b"_+{}||:\"~<>?";
+
+    match usage {
+        usage if LETTERS.contains(&usage) => {
+            let c = b'a' + (usage - 0x04);
+            Some(if shift { c.to_ascii_uppercase() } else { c })
+        },
+        0x1E..=0x27 => Some(if shift { SHIFTED_DIGITS } else { DIGITS }[(usage - 0x1E) as usize]),
+        0x28 => Some(b'\n'),
+        0x2A => Some(0x08), // backspace
+        0x2B => Some(b'\t'),
+        0x2C => Some(b' '),
+        0x2D..=0x38 => Some(if shift { SHIFTED_SYMBOLS } else { SYMBOLS }[(usage - 0x2D) as usize]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_lines() {
+        let mut editor = LineEditor::new();
+        let edits: Vec<_> = b"rex\x7Fboot\x01\r".iter().filter_map(|&b| editor.feed(b)).collect();
+        assert_eq!(edits[..4], [Edit::Insert(b'r'), Edit::Insert(b'e'), Edit::Insert(b'x'), Edit::Erase]);
+        assert_eq!(edits.last(), Some(&Edit::Submit));
+        assert_eq!(editor.line(), "reboot");
+        assert_eq!(Command::parse(editor.line()), Command::Reboot);
+
+        editor.clear();
+        assert_eq!(editor.feed(0x08), None);
+        assert_eq!(Command::parse("  poweroff now"), Command::Shutdown);
+        assert_eq!(Command::parse(" "), Command::Empty);
+        assert_eq!(Command::parse("ls /"), Command::Unknown("ls"));
+    }
+
+    #[test]
+    fn maps_hid_usages() {
+        assert_eq!(hid_to_ascii(0x04, false), Some(b'a'));
+        assert_eq!(hid_to_ascii(0x1D, true), Some(b'Z'));
+        assert_eq!(hid_to_ascii(0x27, false), Some(b'0'));
+        assert_eq!(hid_to_ascii(0x1F, true), Some(b'@'));
+        assert_eq!(hid_to_ascii(0x31, false), Some(b'\\'));
+        assert_eq!(hid_to_ascii(0x38, true), Some(b'?'));
+        assert_eq!(hid_to_ascii(0x3A, false), None); // F1
+    }
+}
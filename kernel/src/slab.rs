@@ -0,0 +1,242 @@
+//! Slab caches for fixed-size kernel objects, which sit in front of the heap.
+
+extern crate alloc;
+
+use core::alloc::{Allocator, AllocError, Layout};
+use core::ptr::NonNull;
+use alloc::alloc::Global;
+
+use spin::mutex::Mutex;
+
+use crate::pgmgr::KERNEL_PAGE_SIZE;
+
+/// The size of a slab, which is carved into objects of the same size.
+pub const SLAB_SIZE: usize = KERNEL_PAGE_SIZE;
+
+/// A free object, which holds the link to the next free object.
+/// The link is the first word of the object, or the word after it if the cache has a constructor.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct FreeList {
+    head: Option<NonNull<FreeObject>>,
+    slab_count: usize,
+    in_use: usize,
+}
+
+// free objects are only reachable through the list.
+unsafe impl Send for FreeList {}
+
+/// A cache of objects of a single size.
+pub struct SlabCache<B: Allocator = Global> {
+    name: &'static str,
+    obj_size: usize,
+    obj_align: usize,
+    /// The distance between objects in a slab.
+    stride: usize,
+    /// Constructor hook, which runs once on each object when its slab is added.
+    ctor: Option<fn(NonNull<u8>)>,
+
+    free_list: Mutex<FreeList>,
+    backing: B,
+}
+
+impl<B: Allocator> SlabCache<B> {
+    /// Create a cache for objects of the given size and alignment.
+    /// Slabs are requested from `backing`.
+    pub const fn new(name: &'static str, size: usize, align: usize, backing: B) -> Self {
+        assert!(align.is_power_of_two() && align <= SLAB_SIZE);
+
+        // an object should be able to hold the free list link.
+        let word = core::mem::size_of::<FreeObject>();
+        let size = if size < word { word } else { size };
+        let align = if align < word { word } else { align };
+        let obj_size = (size + align - 1) & !(align - 1);
+        assert!(obj_size <= SLAB_SIZE);
+
+        Self {
+            name,
+            obj_size,
+            obj_align: align,
+            stride: obj_size,
+            ctor: None,
+            free_list: Mutex::new(FreeList {
+                head: None,
+                slab_count: 0,
+                in_use: 0,
+            }),
+            backing,
+        }
+    }
+
+    /// Set the constructor hook.
+    ///
+    /// Objects are constructed once, when their slab is added, so they should be freed in the constructed state.
+    /// The free list link is kept after the object, not to break it.
+    pub const fn with_ctor(mut self, ctor: fn(NonNull<u8>)) -> Self {
+        let word = core::mem::size_of::<FreeObject>();
+        let stride = (self.obj_size + word + self.obj_align - 1) & !(self.obj_align - 1);
+        assert!(stride <= SLAB_SIZE);
+
+        self.stride = stride;
+        self.ctor = Some(ctor);
+        self
+    }
+
+    /// The free list link of an object.
+    fn link(&self, obj: NonNull<u8>) -> NonNull<FreeObject> {
+        let offset = if self.ctor.is_some() { self.obj_size } else { 0 };
+        unsafe { NonNull::new_unchecked(obj.as_ptr().add(offset)).cast::<FreeObject>() }
+    }
+
+    /// The object owning a free list link.
+    fn obj_of(&self, link: NonNull<FreeObject>) -> NonNull<u8> {
+        let offset = if self.ctor.is_some() { self.obj_size } else { 0 };
+        unsafe { NonNull::new_unchecked(link.as_ptr().cast::<u8>().sub(offset)) }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The object size, including padding.
+    pub fn obj_size(&self) -> usize {
+        self.obj_size
+    }
+
+    /// Returns true if an object of the given layout can be served from this cache.
+    pub fn fits(&self, layout: Layout) -> bool {
+        layout.size() <= self.obj_size && layout.align() <= self.obj_align
+    }
+
+    /// Returns (slab count, objects in use).
+    pub fn usage(&self) -> (usize, usize) {
+        let list = self.free_list.lock();
+        (list.slab_count, list.in_use)
+    }
+
+    /// Request a new slab from the backing allocator, and push its objects into the free list.
+    fn grow(&self, list: &mut FreeList) -> Result<(), AllocError> {
+        let slab = self.backing.allocate(
+            Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+        )?.cast::<u8>();
+
+        for i in (0..(SLAB_SIZE / self.stride)).rev() {
+            let obj = unsafe { NonNull::new_unchecked(slab.as_ptr().add(i * self.stride)) };
+            if let Some(ctor) = self.ctor {
+                ctor(obj);
+            }
+
+            let link = self.link(obj);
+            unsafe {
+                link.as_ptr().write(FreeObject { next: list.head });
+            }
+            list.head = Some(link);
+        }
+        list.slab_count += 1;
+
+        Ok(())
+    }
+
+    /// Take an object from the free list.
+    pub fn alloc_obj(&self) -> Result<NonNull<u8>, AllocError> {
+        let mut list = self.free_list.lock();
+        if list.head.is_none() {
+            self.grow(&mut list)?;
+        }
+
+        let link = list.head.unwrap();
+        list.head = unsafe { link.as_ref().next };
+        list.in_use += 1;
+        Ok(self.obj_of(link))
+    }
+
+    /// Return an object into the free list.
+    ///
+    /// # Safety
+    /// The object should have been allocated from this cache, and be back in the constructed state if it has a constructor.
+    pub unsafe fn free_obj(&self, obj: NonNull<u8>) {
+        let link = self.link(obj);
+
+        let mut list = self.free_list.lock();
+        link.as_ptr().write(FreeObject { next: list.head });
+        list.head = Some(link);
+        list.in_use -= 1;
+    }
+}
+
+unsafe impl<B: Allocator> Allocator for SlabCache<B> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.fits(layout) { return Err(AllocError); }
+
+        let obj = self.alloc_obj()?;
+        Ok(NonNull::slice_from_raw_parts(obj, self.obj_size))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        self.free_obj(ptr);
+    }
+}
+
+/// The object sizes of the general-purpose caches.
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Size-class slab caches, falling back to the backing allocator for larger objects.
+///
+/// Each class is aligned to its size, and a slab is aligned to `SLAB_SIZE`,
+/// so an object never crosses a slab boundary.
+pub struct SlabAllocator<B: Allocator + Copy = Global> {
+    caches: [SlabCache<B>; SIZE_CLASSES.len()],
+    backing: B,
+}
+
+impl<B: Allocator + Copy> SlabAllocator<B> {
+    pub const fn new(backing: B) -> Self {
+        Self {
+            caches: [
+                SlabCache::new("slab-16", 16, 16, backing),
+                SlabCache::new("slab-32", 32, 32, backing),
+                SlabCache::new("slab-64", 64, 64, backing),
+                SlabCache::new("slab-128", 128, 128, backing),
+                SlabCache::new("slab-256", 256, 256, backing),
+                SlabCache::new("slab-512", 512, 512, backing),
+                SlabCache::new("slab-1024", 1024, 1024, backing),
+                SlabCache::new("slab-2048", 2048, 2048, backing),
+            ],
+            backing,
+        }
+    }
+
+    /// Find the cache for the given layout.
+    fn cache_for(&self, layout: Layout) -> Option<&SlabCache<B>> {
+        let size = layout.size().max(layout.align());
+        self.caches.iter().find(|cache| cache.obj_size() >= size)
+    }
+
+    /// Returns true if the layout is served by one of the caches, not by the backing allocator.
+    pub fn serves(&self, layout: Layout) -> bool {
+        self.cache_for(layout).is_some()
+    }
+
+    /// The general-purpose caches.
+    pub fn caches(&self) -> &[SlabCache<B>] {
+        &self.caches
+    }
+}
+
+unsafe impl<B: Allocator + Copy> Allocator for SlabAllocator<B> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self.cache_for(layout) {
+            Some(cache) => cache.allocate(layout),
+            None => self.backing.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match self.cache_for(layout) {
+            Some(cache) => cache.deallocate(ptr, layout),
+            None => self.backing.deallocate(ptr, layout),
+        }
+    }
+}
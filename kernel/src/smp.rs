@@ -0,0 +1,295 @@
+//! Application processor startup, and per-CPU data.
+//!
+//! The BSP copies a real-mode trampoline below 1MB, and wakes each AP with INIT-SIPI-SIPI.
+//! The trampoline switches to long mode with the BSP's page table, and calls the entry on its own stack.
+//! https://wiki.osdev.org/Symmetric_Multiprocessing
+
+extern crate alloc;
+
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::registers::control::{Cr0, Cr3, Cr4};
+use x86_64::registers::model_specific::{GsBase, Msr};
+use x86_64::registers::segmentation::SegmentSelector;
+use x86_64::structures::gdt::GlobalDescriptorTable;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+use alloc::boxed::Box;
+
+/// The data of a CPU, reachable through GS.
+#[repr(C)]
+pub struct PerCpu {
+    /// Points to itself, so that `gs:0` gives the address of the data.
+    self_ptr: *const PerCpu,
+    /// The BSP is 0, and the APs follow in the order they are started.
+    pub index: usize,
+    pub apic_id: u32,
+    online: AtomicBool,
+    pub tss: TaskStateSegment,
+    pub gdt: GlobalDescriptorTable,
+    pub tss_selector: SegmentSelector,
+}
+
+/// Set once the BSP installs its data. Only the BSP runs before that.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// the data is written only before the CPU starts, except `online`.
+unsafe impl Sync for PerCpu {}
+unsafe impl Send for PerCpu {}
+
+impl PerCpu {
+    /// Allocate the data of a CPU, which lives forever. The GDT and TSS are empty.
+    pub fn leak(index: usize, apic_id: u32) -> &'static mut Self {
+        let cpu = Box::leak(Box::new(Self {
+            self_ptr: core::ptr::null(),
+            index,
+            apic_id,
+            online: AtomicBool::new(false),
+            tss: TaskStateSegment::new(),
+            gdt: GlobalDescriptorTable::new(),
+            tss_selector: SegmentSelector::NULL,
+        }));
+        cpu.self_ptr = cpu;
+        cpu
+    }
+
+    /// Make this the data of the current CPU.
+    /// Loading the GS selector may clear the base, so this should follow the segment setup.
+    /// An AP should do this before taking any lock, as the locks read the index.
+    pub fn install(&'static self) {
+        GsBase::write(VirtAddr::from_ptr(self));
+        INSTALLED.store(true, Ordering::Release);
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn set_online(&self) {
+        self.online.store(true, Ordering::Release);
+    }
+}
+
+/// The data of the current CPU. This should be called after `PerCpu::install`.
+pub fn current() -> &'static PerCpu {
+    let ptr: *const PerCpu;
+    unsafe {
+        core::arch::asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags));
+        &*ptr
+    }
+}
+
+/// The index of the current CPU. 0 before the BSP installs its data.
+#[inline]
+pub fn current_index() -> usize {
+    if INSTALLED.load(Ordering::Acquire) { current().index } else { 0 }
+}
+
+// local APIC registers, relative to the base address.
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+
+/// INIT, level assert.
+pub const IPI_INIT: u32 = 0x0000_4500;
+/// STARTUP, with the page number of the trampoline in the low byte.
+pub const IPI_STARTUP: u32 = 0x0000_4600;
+
+/// Send an IPI from the current LAPIC, and wait until it is sent.
+///
+/// # Safety
+/// `lapic_base` should be the identity-mapped LAPIC base.
+pub unsafe fn send_ipi(lapic_base: usize, apic_id: u8, command: u32) {
+    let reg = |offset: usize| (lapic_base + offset) as *mut u32;
+    reg(LAPIC_ICR_HIGH).write_volatile((apic_id as u32) << 24);
+    reg(LAPIC_ICR_LOW).write_volatile(command); // this sends.
+    while reg(LAPIC_ICR_LOW).read_volatile() & ICR_SEND_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Software-enable the current LAPIC, which is disabled after INIT.
+///
+/// # Safety
+/// `lapic_base` should be the identity-mapped LAPIC base, and the spurious vector should have a handler.
+pub unsafe fn enable_local_apic(lapic_base: usize, spurious_vector: u8) {
+    ((lapic_base + LAPIC_SVR) as *mut u32).write_volatile(SVR_ENABLE | spurious_vector as u32);
+}
+
+/// Inputs of the trampoline, at `ap_trampoline_params`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TrampolineParams {
+    pub cr0: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+    pub stack_top: u64,
+    /// `extern "sysv64" fn(arg) -> !`
+    pub entry: u64,
+    pub arg: u64,
+}
+
+const EFER: u32 = 0xC000_0080;
+const EFER_LMA: u64 = 1 << 10; // read-only
+const CR4_PCIDE: u64 = 1 << 17; // can't be set outside long mode
+
+impl TrampolineParams {
+    /// The control registers of the current CPU, so that the AP runs in the same environment. (paging, SSE, NX)
+    pub fn current(stack_top: VirtAddr, entry: usize, arg: usize) -> Self {
+        let (frame, flags) = Cr3::read();
+        Self {
+            cr0: Cr0::read_raw(),
+            cr3: frame.start_address().as_u64() | flags.bits(),
+            cr4: Cr4::read_raw() & !CR4_PCIDE,
+            efer: unsafe { Msr::new(EFER).read() } & !EFER_LMA,
+            stack_top: stack_top.as_u64(),
+            entry: entry as u64,
+            arg: arg as u64,
+        }
+    }
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// The trampoline copied to a page below 1MB.
+pub struct Trampoline {
+    base: usize,
+}
+
+impl Trampoline {
+    /// The size of the trampoline, which should fit in a page.
+    pub fn size() -> usize {
+        addr_of!(ap_trampoline_end) as usize - addr_of!(ap_trampoline_start) as usize
+    }
+
+    /// Copy the trampoline.
+    ///
+    /// # Safety
+    /// `page` should be an identity-mapped, page-aligned address below 1MB, which is not used by any other code.
+    pub unsafe fn install(page: usize) -> Self {
+        assert!(page < 0x10_0000 && page.is_multiple_of(0x1000) && Self::size() <= 0x1000);
+        core::ptr::copy_nonoverlapping(addr_of!(ap_trampoline_start), page as *mut u8, Self::size());
+        Self { base: page }
+    }
+
+    /// The STARTUP IPI vector, which is the page number.
+    pub fn vector(&self) -> u8 {
+        (self.base >> 12) as u8
+    }
+
+    /// Set the inputs for the next AP. The previous AP should have reached the entry.
+    pub fn set_params(&mut self, params: TrampolineParams) {
+        unsafe {
+            let offset = addr_of!(ap_trampoline_params) as usize - addr_of!(ap_trampoline_start) as usize;
+            ((self.base + offset) as *mut TrampolineParams).write_volatile(params);
+        }
+    }
+}
+
+// The AP starts in real mode at `vector:0000`. The trampoline patches its own pointers with the base address
+// (`ebx`), enters protected mode, enables long mode with the BSP's registers, and calls the entry.
+// Only label differences are used, so the code runs wherever it is copied.
+core::arch::global_asm!(
+    ".section .text",
+    ".align 16",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_params",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "xor ebx, ebx",
+    "mov bx, ax",
+    "shl ebx, 4",
+    "lea eax, [ebx + AP_GDT]",
+    "mov dword ptr [AP_GDTR + 2], eax",
+    "lea eax, [ebx + AP_ENTRY32]",
+    "mov dword ptr [AP_FAR32], eax",
+    "lea eax, [ebx + AP_ENTRY64]",
+    "mov dword ptr [AP_FAR64], eax",
+    "lgdt [AP_GDTR]",
+    "mov eax, cr0",
+    "or eax, 1",
+    "mov cr0, eax",
+    "jmp fword ptr [AP_FAR32]",
+
+    ".code32",
+    "ap_trampoline_32:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov eax, [ebx + AP_PARAMS + {cr4}]",
+    "mov cr4, eax",
+    "mov eax, [ebx + AP_PARAMS + {cr3}]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "mov eax, [ebx + AP_PARAMS + {efer}]",
+    "mov edx, [ebx + AP_PARAMS + {efer} + 4]",
+    "wrmsr",
+    "mov eax, [ebx + AP_PARAMS + {cr0}]",
+    "mov cr0, eax", // paging on, in compatibility mode.
+    "jmp fword ptr [ebx + AP_FAR64]",
+
+    ".code64",
+    "ap_trampoline_64:",
+    "mov ebx, ebx", // the upper half is undefined after the mode switch.
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov rsp, [rbx + AP_PARAMS + {stack_top}]",
+    "mov rdi, [rbx + AP_PARAMS + {arg}]",
+    "xor ebp, ebp",
+    "call qword ptr [rbx + AP_PARAMS + {entry}]",
+    "ud2",
+
+    ".align 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00CF9A000000FFFF", // 0x08: 32-bit code
+    ".quad 0x00CF92000000FFFF", // 0x10: data
+    ".quad 0x00AF9A000000FFFF", // 0x18: 64-bit code
+    "ap_trampoline_gdtr:",
+    ".word 31",
+    ".long 0",
+    "ap_trampoline_far32:",
+    ".long 0",
+    ".word 0x08",
+    "ap_trampoline_far64:",
+    ".long 0",
+    ".word 0x18",
+    ".align 8",
+    "ap_trampoline_params:",
+    ".space {params_size}",
+    "ap_trampoline_end:",
+    // offsets from the start, which are the addresses in real mode.
+    ".set AP_GDT, ap_trampoline_gdt - ap_trampoline_start",
+    ".set AP_GDTR, ap_trampoline_gdtr - ap_trampoline_start",
+    ".set AP_ENTRY32, ap_trampoline_32 - ap_trampoline_start",
+    ".set AP_FAR32, ap_trampoline_far32 - ap_trampoline_start",
+    ".set AP_ENTRY64, ap_trampoline_64 - ap_trampoline_start",
+    ".set AP_FAR64, ap_trampoline_far64 - ap_trampoline_start",
+    ".set AP_PARAMS, ap_trampoline_params - ap_trampoline_start",
+    cr0 = const core::mem::offset_of!(TrampolineParams, cr0),
+    cr3 = const core::mem::offset_of!(TrampolineParams, cr3),
+    cr4 = const core::mem::offset_of!(TrampolineParams, cr4),
+    efer = const core::mem::offset_of!(TrampolineParams, efer),
+    stack_top = const core::mem::offset_of!(TrampolineParams, stack_top),
+    entry = const core::mem::offset_of!(TrampolineParams, entry),
+    arg = const core::mem::offset_of!(TrampolineParams, arg),
+    params_size = const core::mem::size_of::<TrampolineParams>(),
+);
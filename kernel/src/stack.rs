@@ -0,0 +1,51 @@
+//! Statically allocated kernel stacks, surrounded by guard pages.
+
+use core::cell::UnsafeCell;
+
+use x86_64::VirtAddr;
+
+use crate::pgmgr::{Page, KERNEL_PAGE_SIZE};
+
+/// A stack of `N` pages, with a guard page on each side.
+///
+/// The guard pages are just padding until they are unmapped by `globals::stacks::init()`.
+/// After that, an overflow hits the lower guard page and faults, instead of corrupting adjacent data.
+#[repr(C, align(0x1000))]
+pub struct GuardedStack<const N: usize> {
+    guard_lo: Page,
+    body: UnsafeCell<[Page; N]>,
+    guard_hi: Page,
+}
+
+impl<const N: usize> GuardedStack<N> {
+    pub const SIZE: usize = N * KERNEL_PAGE_SIZE;
+
+    pub const fn new() -> Self {
+        Self {
+            guard_lo: Page::new(),
+            body: UnsafeCell::new([Page::new(); N]),
+            guard_hi: Page::new(),
+        }
+    }
+
+    /// The lowest address of the stack body.
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.body.get())
+    }
+
+    /// The initial stack pointer, which is the end of the stack body.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + Self::SIZE
+    }
+
+    /// The guard pages below and above the stack body.
+    pub fn guard_pages(&self) -> [VirtAddr; 2] {
+        [
+            VirtAddr::from_ptr(&self.guard_lo as *const Page),
+            VirtAddr::from_ptr(&self.guard_hi as *const Page),
+        ]
+    }
+}
+
+// the body is only accessed as a stack by a single CPU.
+unsafe impl<const N: usize> Sync for GuardedStack<N> {}
@@ -0,0 +1,138 @@
+//! A spin mutex which knows the CPU holding it.
+//!
+//! Taking a lock twice on the same CPU (e.g. printing from an interrupt handler
+//! which interrupted printing) never succeeds with a plain spin lock.
+//! `CpuMutex` detects it, so that the caller can take another path instead of hanging.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const NO_OWNER: u32 = u32::MAX;
+
+/// The index of the current CPU, from the per-CPU data.
+#[inline]
+pub fn current_cpu_id() -> u32 {
+    crate::smp::current_index() as u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockError {
+    /// The current CPU already holds the lock.
+    Reentrant,
+    /// Another CPU holds the lock.
+    Busy,
+}
+
+pub struct CpuMutex<T> {
+    /// The CPU holding the lock, which is also the lock word:
+    /// the owner is known from the moment the lock is taken, even to an NMI right after.
+    owner: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for CpuMutex<T> {}
+unsafe impl<T: Send> Send for CpuMutex<T> {}
+
+impl<T> CpuMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            owner: AtomicU32::new(NO_OWNER),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take the lock, spinning while another CPU holds it.
+    ///
+    /// # Panics
+    /// Panics if the current CPU already holds the lock, which would spin forever otherwise.
+    pub fn lock(&self) -> CpuMutexGuard<'_, T> {
+        match self.lock_checked() {
+            Ok(guard) => guard,
+            Err(_) => panic!("Re-entrant lock on CPU {}", current_cpu_id()),
+        }
+    }
+
+    /// Take the lock, spinning while another CPU holds it.
+    /// Fails if the current CPU already holds it.
+    pub fn lock_checked(&self) -> Result<CpuMutexGuard<'_, T>, LockError> {
+        loop {
+            match self.try_lock_checked() {
+                Err(LockError::Busy) => core::hint::spin_loop(),
+                result => return result,
+            }
+        }
+    }
+
+    pub fn try_lock_checked(&self) -> Result<CpuMutexGuard<'_, T>, LockError> {
+        let cpu = current_cpu_id();
+        match self.owner.compare_exchange(NO_OWNER, cpu, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(CpuMutexGuard { mutex: self }),
+            Err(owner) if owner == cpu => Err(LockError::Reentrant),
+            Err(_) => Err(LockError::Busy),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<CpuMutexGuard<'_, T>> {
+        self.try_lock_checked().ok()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) != NO_OWNER
+    }
+
+    /// Returns true if the current CPU holds the lock.
+    pub fn held_by_current_cpu(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == current_cpu_id()
+    }
+
+    /// Release the lock regardless of its holder.
+    ///
+    /// # Safety
+    /// The holder should never touch the data again, e.g. on panic.
+    pub unsafe fn force_unlock(&self) {
+        self.owner.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+pub struct CpuMutexGuard<'a, T> {
+    mutex: &'a CpuMutex<T>,
+}
+
+impl<T> Drop for CpuMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.owner.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+impl<T> Deref for CpuMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for CpuMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_reentrance() {
+        let mutex = CpuMutex::new(1);
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.held_by_current_cpu());
+        assert_eq!(mutex.try_lock_checked().err(), Some(LockError::Reentrant));
+
+        drop(guard);
+        assert!(!mutex.is_locked());
+        assert_eq!(*mutex.lock(), 2);
+    }
+}
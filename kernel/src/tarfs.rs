@@ -0,0 +1,258 @@
+//! A read-only filesystem on a ustar archive, for the initial ramdisk.
+//!
+//! File data is borrowed from the archive, so nothing is copied or allocated.
+//! https://www.gnu.org/software/tar/manual/html_node/Standard.html
+
+use core::fmt;
+
+pub const BLOCK_SIZE: usize = 512;
+
+// header field ranges.
+const NAME: core::ops::Range<usize> = 0..100;
+const SIZE: core::ops::Range<usize> = 124..136;
+const CHECKSUM: core::ops::Range<usize> = 148..156;
+const TYPEFLAG: usize = 156;
+const MAGIC: core::ops::Range<usize> = 257..262; // "ustar", followed by NUL (POSIX) or space (GNU).
+const PREFIX: core::ops::Range<usize> = 345..500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TarError {
+    /// A header or file data runs past the end of the archive.
+    Truncated { offset: usize },
+    /// A header is not a ustar header, or its checksum doesn't match.
+    BadHeader { offset: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// Links, devices, and GNU extensions, which are listed but can't be read.
+    Other,
+}
+
+/// An archive member.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+/// Path components, ignoring empty and `.` components. (e.g. `./etc//motd` is `etc`, `motd`)
+fn components(path: &str) -> impl Iterator<Item = &str> + Clone {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+impl<'a> Entry<'a> {
+    pub fn components(&self) -> impl Iterator<Item = &'a str> + Clone {
+        components(self.prefix).chain(components(self.name))
+    }
+
+    /// The last path component. Empty for the root directory.
+    pub fn file_name(&self) -> &'a str {
+        self.components().last().unwrap_or("")
+    }
+
+    /// Returns true if the entry is at the path.
+    pub fn is_at(&self, path: &str) -> bool {
+        self.components().eq(components(path))
+    }
+
+    /// Returns true if the entry is directly under the directory.
+    pub fn is_in(&self, dir: &str) -> bool {
+        let mut entry = self.components();
+        components(dir).all(|c| entry.next() == Some(c)) && entry.next().is_some() && entry.next().is_none()
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, component) in self.components().enumerate() {
+            if i > 0 { f.write_str("/")?; }
+            f.write_str(component)?;
+        }
+        Ok(())
+    }
+}
+
+/// A NUL-terminated header field.
+fn field(header: &[u8], range: core::ops::Range<usize>) -> &[u8] {
+    let field = &header[range];
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+/// An octal number field, which may be padded with spaces.
+fn octal(field: &[u8]) -> Option<usize> {
+    let digits = field.iter().copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| b != b' ' && b != 0);
+
+    let mut value: usize = 0;
+    for digit in digits {
+        if !(b'0'..=b'7').contains(&digit) { return None; }
+        value = value.checked_mul(8)?.checked_add((digit - b'0') as usize)?;
+    }
+    Some(value)
+}
+
+fn parse_entry(data: &[u8], offset: usize) -> Result<Option<(Entry<'_>, usize)>, TarError> {
+    let header = data.get(offset..offset + BLOCK_SIZE).ok_or(TarError::Truncated { offset })?;
+    if header.iter().all(|&b| b == 0) { return Ok(None); } // end of archive
+
+    let bad_header = TarError::BadHeader { offset };
+    if &header[MAGIC] != b"ustar" { return Err(bad_header); }
+
+    // the checksum is the byte sum of the header, with the checksum field as spaces.
+    let checksum = octal(&header[CHECKSUM]).ok_or(bad_header)?;
+    let sum: usize = header.iter().enumerate()
+        .map(|(i, &b)| if CHECKSUM.contains(&i) { b' ' as usize } else { b as usize })
+        .sum();
+    if sum != checksum { return Err(bad_header); }
+
+    let name = core::str::from_utf8(field(header, NAME)).map_err(|_| bad_header)?;
+    let prefix = core::str::from_utf8(field(header, PREFIX)).map_err(|_| bad_header)?;
+    let size = octal(&header[SIZE]).ok_or(bad_header)?;
+
+    let data_start = offset + BLOCK_SIZE;
+    let data = data.get(data_start..data_start.saturating_add(size))
+        .ok_or(TarError::Truncated { offset })?;
+
+    let kind = match header[TYPEFLAG] {
+        b'0' | 0 => EntryKind::File,
+        b'5' => EntryKind::Directory,
+        _ => EntryKind::Other,
+    };
+
+    let next = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    Ok(Some((Entry { prefix, name, kind, data }, next)))
+}
+
+/// A ustar archive, checked on creation.
+#[derive(Clone, Copy, Debug)]
+pub struct TarFs<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TarFs<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, TarError> {
+        let mut offset = 0;
+        // an archive may end without the terminating zero blocks.
+        while offset < data.len() {
+            match parse_entry(data, offset)? {
+                Some((_, next)) => offset = next,
+                None => break,
+            }
+        }
+        Ok(Self { data })
+    }
+
+    /// The size of the archive in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries { data: self.data, offset: 0 }
+    }
+
+    pub fn find(&self, path: &str) -> Option<Entry<'a>> {
+        self.entries().find(|entry| entry.is_at(path))
+    }
+
+    /// The contents of a file.
+    pub fn read(&self, path: &str) -> Option<&'a [u8]> {
+        self.entries()
+            .find(|entry| entry.kind == EntryKind::File && entry.is_at(path))
+            .map(|entry| entry.data)
+    }
+
+    /// Entries directly under the directory. (`""` for the root)
+    pub fn read_dir<'p>(&self, dir: &'p str) -> impl Iterator<Item = Entry<'a>> + 'p where 'a: 'p {
+        self.entries().filter(move |entry| entry.is_in(dir))
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        if self.offset >= self.data.len() { return None; }
+
+        // the archive is checked on creation.
+        let (entry, next) = parse_entry(self.data, self.offset).ok()??;
+        self.offset = next;
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, typeflag: u8, size: usize) -> [u8; BLOCK_SIZE] {
+        let mut header = [0; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[SIZE][..11].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[TYPEFLAG] = typeflag;
+        header[257..265].copy_from_slice(b"ustar\x0000");
+
+        header[CHECKSUM].fill(b' ');
+        let sum: usize = header.iter().map(|&b| b as usize).sum();
+        header[CHECKSUM][..7].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header
+    }
+
+    fn archive(entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &(name, typeflag, contents) in entries {
+            data.extend_from_slice(&header(name, typeflag, contents.len()));
+            data.extend_from_slice(contents);
+            data.resize(data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        }
+        data.resize(data.len() + 2 * BLOCK_SIZE, 0);
+        data
+    }
+
+    #[test]
+    fn reads_files_and_directories() {
+        let data = archive(&[
+            ("./", b'5', b""),
+            ("./etc/", b'5', b""),
+            ("./etc/motd", b'0', b"hello\n"),
+            ("./apps/", b'5', b""),
+            ("./apps/big", b'0', &[7; 1000]),
+        ]);
+        let fs = TarFs::new(&data).unwrap();
+
+        assert_eq!(fs.read("etc/motd"), Some(&b"hello\n"[..]));
+        assert_eq!(fs.read("/etc/motd"), Some(&b"hello\n"[..]));
+        assert_eq!(fs.read("apps/big").map(|data| data.len()), Some(1000));
+        assert_eq!(fs.read("etc"), None); // a directory
+        assert_eq!(fs.read("etc/none"), None);
+
+        let root: Vec<_> = fs.read_dir("").map(|entry| entry.file_name()).collect();
+        assert_eq!(root, ["etc", "apps"]);
+        let etc: Vec<_> = fs.read_dir("etc").map(|entry| entry.to_string()).collect();
+        assert_eq!(etc, ["etc/motd"]);
+    }
+
+    #[test]
+    fn rejects_broken_archives() {
+        let mut data = archive(&[("motd", b'0', b"hello\n")]);
+        data[0] = b'n'; // checksum mismatch
+        assert_eq!(TarFs::new(&data).unwrap_err(), TarError::BadHeader { offset: 0 });
+
+        let data = archive(&[("motd", b'0', &[1; 600])]);
+        assert_eq!(TarFs::new(&data[..BLOCK_SIZE + 100]).unwrap_err(), TarError::Truncated { offset: 0 });
+
+        assert_eq!(TarFs::new(&[]).unwrap().entries().count(), 0);
+    }
+}
@@ -0,0 +1,97 @@
+//! Kernel-mode test harness, for `custom_test_frameworks`.
+//!
+//! Each integration test under `kernel/tests` is booted as a kernel in QEMU by `cargo xtask test`.
+//! Results are reported over COM1, and QEMU exits through the `isa-debug-exit` device.
+
+use core::panic::PanicInfo;
+
+use x86_64::instructions::port::Port;
+
+use shared::uefi_memory::MemoryMap;
+use shared::KernelArgs;
+
+use crate::{serial_print, serial_println};
+
+/// The I/O port of the `isa-debug-exit` device. (`-device isa-debug-exit,iobase=0xf4,iosize=0x04`)
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// QEMU exits with `(code << 1) | 1`, so success is 33.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe {
+        Port::<u32>::new(ISA_DEBUG_EXIT_PORT).write(code as u32);
+    }
+
+    // not running under QEMU.
+    loop { x86_64::instructions::hlt(); }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{} ... ", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    serial_println!("test result: ok. {} passed", tests.len());
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// The panic handler of test kernels. A panic fails the running test.
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        crate::globals::serial::force_unlock();
+    }
+
+    serial_println!("[failed]");
+    serial_println!("{}", info);
+    serial_println!("{}", crate::globals::symbols::backtrace(None, crate::backtrace::current_rbp()));
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// The body of the entry point of test kernels: initialize globals and run the tests.
+///
+/// Unlike the kernel, this doesn't relocate the stack and runs on the bootloader's stack.
+pub fn test_start(mmap: MemoryMap<'static>, args: KernelArgs, test_main: fn()) -> ! {
+    crate::globals::init(mmap, args);
+    test_main();
+
+    // `test_runner` exits, but `test_main` is empty if there are no tests.
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Define the entry point and the panic handler of a test kernel.
+#[macro_export]
+macro_rules! test_kernel {
+    () => {
+        #[no_mangle]
+        pub extern "sysv64" fn _start(
+            mmap: shared::uefi_memory::MemoryMap<'static>,
+            args: shared::KernelArgs,
+        ) -> ! {
+            $crate::testing::test_start(mmap, args, test_main)
+        }
+
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::testing::test_panic_handler(info)
+        }
+    };
+}
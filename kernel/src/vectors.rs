@@ -0,0 +1,70 @@
+//! Allocation of IDT vectors to device interrupts at runtime.
+
+use core::ops::RangeInclusive;
+
+/// A bitmap of the vectors in a range, which are handed out to interrupt sources.
+pub struct VectorAllocator {
+    range: RangeInclusive<u8>,
+    used: [u64; 4],
+}
+
+impl VectorAllocator {
+    pub const fn new(range: RangeInclusive<u8>) -> Self {
+        Self { range, used: [0; 4] }
+    }
+
+    pub fn is_used(&self, vector: u8) -> bool {
+        self.used[vector as usize / 64] & (1 << (vector % 64)) != 0
+    }
+
+    fn set(&mut self, vector: u8, used: bool) {
+        let (word, bit) = (vector as usize / 64, 1 << (vector % 64));
+        if used { self.used[word] |= bit } else { self.used[word] &= !bit }
+    }
+
+    /// A free vector.
+    pub fn allocate(&mut self) -> Option<u8> {
+        self.allocate_block(1)
+    }
+
+    /// Free vectors `first..first + count`, where `first` is aligned to `count`.
+    /// Multiple-message MSI needs such a block, as the device writes its message number to the low bits.
+    pub fn allocate_block(&mut self, count: u8) -> Option<u8> {
+        if !count.is_power_of_two() { return None; }
+
+        let last = count - 1;
+        let first = self.range.start().checked_next_multiple_of(count)?;
+        let first = (first..=self.range.end().checked_sub(last)?)
+            .step_by(count as usize)
+            .find(|&first| (first..=first + last).all(|vector| !self.is_used(vector)))?;
+        (first..=first + last).for_each(|vector| self.set(vector, true));
+        Some(first)
+    }
+
+    /// Return a vector. Vectors outside the range are ignored.
+    pub fn free(&mut self, vector: u8) {
+        if self.range.contains(&vector) { self.set(vector, false); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_aligned_blocks() {
+        let mut vectors = VectorAllocator::new(0x51..=0x5F);
+        assert_eq!(vectors.allocate(), Some(0x51));
+        assert_eq!(vectors.allocate_block(4), Some(0x54));
+        assert_eq!(vectors.allocate(), Some(0x52));
+        assert_eq!(vectors.allocate_block(8), Some(0x58));
+        assert_eq!(vectors.allocate_block(3), None);
+        assert_eq!(vectors.allocate_block(2), None); // only 0x53 is left.
+
+        vectors.free(0x52);
+        vectors.free(0x40);
+        assert_eq!(vectors.allocate_block(2), Some(0x52));
+        assert!(!vectors.is_used(0x40));
+        assert_eq!(vectors.allocate(), None);
+    }
+}
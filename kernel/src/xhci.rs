@@ -1,13 +1,14 @@
 extern crate alloc;
 
 use crate::pci::{
-    scan_all_brute,
-    // PciAddress,    
-    LegacyPortAccessMethod,
+    PciAddress,
+    PciMatch,
+    PciDevice,
+    BarInfo,
+    PciConfigAccess,
     // DwordAccessMethod,
     DwordAccessor, AccessorTrait,
-    PciHeader, EndpointHeader,
-    Bar,
+    EndpointHeader,
     capability::CapabilityHeader,
     capability::msi::{
         MsiCapabilityInfo,
@@ -16,11 +17,6 @@ use crate::pci::{
         MultipleMessageSupport,
         TriggerMode,
     },
-    device_type::{
-        DeviceType,
-        UsbType,
-    },
-    acc_map_field,
 };
 
 use core::ptr::NonNull;
@@ -32,29 +28,21 @@ pub use usb_xhci::class::{
     SupportedClassListeners
 };
 
-pub fn get_xhci_ep_acc<'a>()
--> Option<impl AccessorTrait<'a, LegacyPortAccessMethod, EndpointHeader>>
-{
-    scan_all_brute()
-        .find(|&addr| {
-            let accessor = DwordAccessor::<'_, _, PciHeader>::new(addr, 0, LegacyPortAccessMethod);
-
-            let revcc = acc_map_field!(accessor.revcc).read();
+/// xHCI controllers: serial bus, USB, xHCI.
+pub const PCI_MATCHES: &[PciMatch] = &[
+    PciMatch::Class { class: 0x0c, subclass: 0x03, interface: Some(0x30) },
+];
 
-            revcc.device_type() == DeviceType::UsbController
-            && UsbType::try_from(revcc.interface) == Ok(UsbType::Xhci)
-        }).map(|addr| {
-            DwordAccessor::<'_, _, EndpointHeader>::new(addr, 0, LegacyPortAccessMethod)
-        })
-
-    // devices.as_slice().iter().find(|&dev| {
-    //     dev.class_code().match_base_sub_interface(0x0c, 0x03, 0x30)
-    // }).map(|dev| *dev)
+/// The endpoint header of the controller, found in the PCI device table.
+pub fn get_xhci_ep_acc<'a>(access: PciConfigAccess, addr: PciAddress)
+-> impl AccessorTrait<'a, PciConfigAccess, EndpointHeader>
+{
+    DwordAccessor::<'_, _, EndpointHeader>::new(addr, 0, access)
 }
 
 pub fn find_msi_cap_acc<'a>(
-    ep_acc: &impl AccessorTrait<'a, LegacyPortAccessMethod, EndpointHeader>
-) -> Option<impl AccessorTrait<'a, LegacyPortAccessMethod, CapabilityHeader>>
+    ep_acc: &impl AccessorTrait<'a, PciConfigAccess, EndpointHeader>
+) -> Option<impl AccessorTrait<'a, PciConfigAccess, CapabilityHeader>>
 {
     EndpointHeader::capabilities(ep_acc)
         .find(|cap| {
@@ -67,7 +55,7 @@ pub fn find_msi_cap_acc<'a>(
 }
 
 pub fn cfg_msi_fixed_dst<'a>(
-    msi_cap_header_acc: &impl AccessorTrait<'a, LegacyPortAccessMethod, CapabilityHeader>,
+    msi_cap_header_acc: &impl AccessorTrait<'a, PciConfigAccess, CapabilityHeader>,
     apic_base: NonNull<u8>,
     apic_id: u8,
     // we have `trigger_mode` here, but we will use `TriggerMode::Level`.
@@ -93,13 +81,10 @@ pub fn cfg_msi_fixed_dst<'a>(
     );
 }
 
-pub fn read_mmio_base<'a>(
-    ep_acc: &impl AccessorTrait<'a, LegacyPortAccessMethod, EndpointHeader>
-) -> Option<u64> {
-    // match EndpointHeader::bar(ep_acc, 0) {
-    match EndpointHeader::bar_base_only(ep_acc, 0) {
-        Some(Bar::Memory64 { address, .. }) => Some(address),
-        Some(Bar::Memory32 { address, .. }) => Some(address as u64),
+/// The MMIO base, from BAR0 sized at the enumeration.
+pub fn read_mmio_base(device: &PciDevice) -> Option<u64> {
+    match device.bars[0] {
+        Some(BarInfo::Memory { address, .. }) => Some(address),
         _ => None
     }
 }
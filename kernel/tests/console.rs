@@ -0,0 +1,61 @@
+#![cfg(target_os = "none")] // kernel tests only run in QEMU
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::fmt::Write;
+
+use kernel::console::{CONSOLE_COLS, CONSOLE_ROWS};
+use kernel::globals::CONSOLE;
+
+kernel::test_kernel!();
+
+fn starts_with(row: &[u8], s: &str) -> bool {
+    row.starts_with(s.as_bytes())
+}
+
+#[test_case]
+fn write_goes_to_last_row() {
+    let mut cell = CONSOLE.lock();
+    let console = cell.get_mut().unwrap();
+
+    write!(console, "\nhello").unwrap();
+    assert!(starts_with(console.row(CONSOLE_ROWS - 1), "hello"));
+    assert_eq!(console.cursor_col(), 5);
+}
+
+#[test_case]
+fn newline_scrolls_up() {
+    let mut cell = CONSOLE.lock();
+    let console = cell.get_mut().unwrap();
+
+    write!(console, "\nfirst\nsecond").unwrap();
+    assert!(starts_with(console.row(CONSOLE_ROWS - 2), "first"));
+    assert!(starts_with(console.row(CONSOLE_ROWS - 1), "second"));
+    assert_eq!(console.cursor_col(), 6);
+}
+
+#[test_case]
+fn long_line_wraps() {
+    let mut cell = CONSOLE.lock();
+    let console = cell.get_mut().unwrap();
+
+    console.newline();
+    for _ in 0..CONSOLE_COLS {
+        console.write_ascii(b'a');
+    }
+    console.write_ascii(b'b');
+
+    assert!(console.row(CONSOLE_ROWS - 2).iter().all(|&ch| ch == b'a'));
+    assert!(starts_with(console.row(CONSOLE_ROWS - 1), "b"));
+    assert_eq!(console.cursor_col(), 1);
+}
+
+#[test_case]
+fn printing_while_holding_screen_does_not_hang() {
+    // the console would lock the screen again, so this should take the emergency path.
+    let _screen = kernel::globals::SCREEN.lock();
+    kernel::console_println!("printed while holding the screen");
+}
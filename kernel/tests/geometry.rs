@@ -0,0 +1,70 @@
+#![cfg(target_os = "none")] // kernel tests only run in QEMU
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use kernel::geometry::{Disp2D, Pos2D, Rect2D};
+
+kernel::test_kernel!();
+
+#[test_case]
+fn pos_disp_arithmetic() {
+    let p: Pos2D = (3, 4).into();
+    let d: Disp2D = (-5, 2).into();
+
+    assert_eq!(p + d, (-2, 6).into());
+    assert_eq!((p + d) - p, d);
+
+    let mut q = p;
+    q += d;
+    q += d;
+    assert_eq!(q, (-7, 8).into());
+    assert_eq!(d + d, (-10, 4).into());
+    assert_eq!(d.width(), 5);
+    assert_eq!(d.height(), 2);
+}
+
+#[test_case]
+fn rect_from_points_is_normalized() {
+    let r = Rect2D::from_points((10, 2).into(), (4, 8).into());
+    assert_eq!(r, Rect2D::from_ranges(4..10, 2..8));
+    assert_eq!(r.size(), (6, 6).into());
+    assert_eq!(r.width(), 6);
+    assert_eq!(r.height(), 6);
+}
+
+#[test_case]
+fn rect_bound_clamps() {
+    let r = Rect2D::from_ranges(-5..5, 3..20);
+    let boundary = Rect2D::from_ranges(0..10, 0..10);
+    assert_eq!(r.bound(boundary), Rect2D::from_ranges(0..5, 3..10));
+
+    // disjoint rectangles are bounded into an empty one.
+    let far = Rect2D::from_ranges(20..30, 20..30);
+    assert_eq!(far.bound(boundary).size(), (0, 0).into());
+}
+
+#[test_case]
+fn iterate_disp_visits_every_point() {
+    let r = Rect2D::from_ranges(1..4, 2..4);
+    let mut count = 0;
+    r.iterate_disp(|d| {
+        assert!(0 <= d.dx && d.dx < 3 && 0 <= d.dy && d.dy < 2);
+        count += 1;
+    });
+    assert_eq!(count, 6);
+}
+
+#[test_case]
+fn iterate_disp_bounded_is_relative_to_rect() {
+    let r = Rect2D::from_ranges(-2..3, -2..3);
+    let boundary = Rect2D::from_ranges(0..10, 0..10);
+    let mut count = 0;
+    r.iterate_disp_bounded(boundary, |d| {
+        assert!(2 <= d.dx && d.dx < 5 && 2 <= d.dy && d.dy < 5);
+        count += 1;
+    });
+    assert_eq!(count, 9);
+}
@@ -0,0 +1,93 @@
+#![cfg(target_os = "none")] // kernel tests only run in QEMU
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::Global;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::{Allocator, Layout};
+
+use kernel::globals::allocator::{heap_stats, dma_allocator};
+use kernel::slab::SlabAllocator;
+use kernel::pgmgr::MB;
+
+kernel::test_kernel!();
+
+#[test_case]
+fn box_and_vec() {
+    let b = Box::new(41u64);
+    assert_eq!(*b + 1, 42);
+
+    let v: Vec<usize> = (0..1000).collect();
+    assert_eq!(v.iter().sum::<usize>(), 999 * 1000 / 2);
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    let before = heap_stats();
+    let v = Vec::<u8>::with_capacity(64 * 1024);
+    let during = heap_stats();
+
+    assert!(during.used >= before.used + v.capacity());
+    assert_eq!(during.alloc_count, before.alloc_count + 1);
+
+    drop(v);
+    assert_eq!(heap_stats().used, before.used);
+}
+
+#[test_case]
+fn heap_grows_on_exhaustion() {
+    let before = heap_stats();
+    let v = Vec::<u8>::with_capacity(before.size + MB);
+    assert!(heap_stats().size > before.size);
+    drop(v);
+}
+
+#[test_case]
+fn many_small_allocations_are_freed() {
+    drop(Box::new([0u8; 24])); // the slab for the size class stays.
+    let before = heap_stats();
+    for i in 0..10_000 {
+        let b = Box::new([i as u8; 24]);
+        assert_eq!(b[23], i as u8);
+    }
+    assert_eq!(heap_stats().used, before.used);
+}
+
+#[test_case]
+fn slab_objects_are_aligned_and_distinct() {
+    static SLAB: SlabAllocator = SlabAllocator::new(Global);
+
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    let a = SLAB.allocate(layout).unwrap().cast::<u8>();
+    let b = SLAB.allocate(layout).unwrap().cast::<u8>();
+
+    assert_ne!(a, b);
+    assert_eq!(a.as_ptr() as usize % 64, 0); // the 64-byte class
+    unsafe {
+        SLAB.deallocate(a, layout);
+        SLAB.deallocate(b, layout);
+    }
+}
+
+#[test_case]
+fn dma_buffers_meet_constraints() {
+    let constraints = dma_allocator().constraints();
+    for size in [64, 1000, 4096, 3 * 4096] {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let buf = dma_allocator().allocate(layout).unwrap().cast::<u8>();
+        let start = dma_allocator().phys_addr(buf).as_u64() as usize;
+        let end = start + size - 1;
+
+        assert_eq!(start % constraints.align, 0);
+        assert_eq!(start / constraints.boundary, end / constraints.boundary);
+        assert!(end < constraints.max_addr);
+        unsafe { dma_allocator().deallocate(buf, layout); }
+    }
+}
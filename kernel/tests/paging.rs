@@ -0,0 +1,78 @@
+#![cfg(target_os = "none")] // kernel tests only run in QEMU
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+use kernel::globals;
+use kernel::paging::{Area, RegionKind, RegionTable};
+use kernel::pgmgr::KERNEL_PAGE_SIZE;
+
+kernel::test_kernel!();
+
+#[test_case]
+fn reserve_advances_within_area() {
+    let mut table = RegionTable::new();
+    let a = table.reserve(Area::ZeroFill, "a", RegionKind::Lazy, 2, PageTableFlags::PRESENT).unwrap();
+    let b = table.reserve(Area::ZeroFill, "b", RegionKind::Lazy, 1, PageTableFlags::PRESENT).unwrap();
+
+    assert_eq!(a.start.as_u64(), Area::ZeroFill.range().start);
+    assert_eq!(a.end - a.start, 2 * KERNEL_PAGE_SIZE as u64);
+    assert_eq!(b.start, a.end);
+    assert_eq!(table.find(a.start + 1u64).unwrap().name, "a");
+    assert_eq!(table.find(b.start).unwrap().name, "b");
+    assert!(table.find(b.end).is_none());
+}
+
+#[test_case]
+fn reserve_guarded_surrounds_with_guards() {
+    let mut table = RegionTable::new();
+    let stack = table.reserve_guarded(Area::TaskStacks, "stack", RegionKind::Lazy, 4, PageTableFlags::PRESENT).unwrap();
+
+    assert_eq!(table.find(stack.start - 1u64).unwrap().kind, RegionKind::Guard);
+    assert_eq!(table.find(stack.end).unwrap().kind, RegionKind::Guard);
+    assert_eq!(table.find(stack.start).unwrap().kind, RegionKind::Lazy);
+}
+
+#[test_case]
+fn reserve_fails_beyond_area() {
+    let mut table = RegionTable::new();
+    let pages = (Area::ZeroFill.range().end - Area::ZeroFill.range().start) as usize / KERNEL_PAGE_SIZE;
+    assert!(table.reserve(Area::ZeroFill, "huge", RegionKind::Lazy, pages + 1, PageTableFlags::PRESENT).is_none());
+}
+
+#[test_case]
+fn lazy_region_is_zero_filled_on_touch() {
+    let region = globals::paging::reserve_lazy(Area::ZeroFill, "test", 2).unwrap();
+    assert!(!globals::paging::is_readable(region.start));
+
+    let ptr = region.start.as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0); // faults, and gets mapped
+        ptr.write_volatile(0xCAFEBABE);
+        assert_eq!(ptr.read_volatile(), 0xCAFEBABE);
+    }
+    assert!(globals::paging::is_readable(region.start));
+
+    // the next page is still unmapped.
+    assert!(!globals::paging::is_readable(region.start + KERNEL_PAGE_SIZE));
+}
+
+#[test_case]
+fn guard_pages_are_not_readable() {
+    for addr in globals::stacks::KERNEL_MAIN_STACK.guard_pages() {
+        assert!(!globals::paging::is_readable(addr));
+        assert_eq!(globals::paging::find_region(addr).unwrap().kind, RegionKind::Guard);
+    }
+    assert!(globals::paging::is_readable(globals::stacks::KERNEL_MAIN_STACK.bottom()));
+}
+
+#[test_case]
+fn identity_mapping() {
+    assert!(globals::paging::is_identity_mapped(VirtAddr::new(0x1000)));
+    assert!(!globals::paging::is_identity_mapped(VirtAddr::new(Area::TaskStacks.range().start)));
+}
@@ -0,0 +1,61 @@
+#![cfg(target_os = "none")] // kernel tests only run in QEMU
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use kernel::globals::pgmgr::PAGE_MANAGER;
+use kernel::pgmgr::{FrameID, PageStat};
+
+kernel::test_kernel!();
+
+#[test_case]
+fn allocate_and_free() {
+    let mut mgr = PAGE_MANAGER.lock();
+    let available = mgr.available_frame_count();
+
+    let frame_id = mgr.allocate(3).unwrap();
+    for i in 0..3 {
+        assert_eq!(mgr.get_stat(FrameID(frame_id.0 + i)), PageStat::Using);
+    }
+    assert_eq!(mgr.available_frame_count(), available - 3);
+
+    mgr.free(frame_id, 3).unwrap();
+    assert_eq!(mgr.get_stat(frame_id), PageStat::Vacant);
+    assert_eq!(mgr.available_frame_count(), available);
+}
+
+#[test_case]
+fn allocations_do_not_overlap() {
+    let mut mgr = PAGE_MANAGER.lock();
+    let a = mgr.allocate(4).unwrap();
+    let b = mgr.allocate(4).unwrap();
+
+    assert!(a.0 + 4 <= b.0 || b.0 + 4 <= a.0);
+
+    mgr.free(a, 4).unwrap();
+    mgr.free(b, 4).unwrap();
+}
+
+#[test_case]
+fn allocate_at_fails_on_used_frames() {
+    let mut mgr = PAGE_MANAGER.lock();
+    let a = mgr.allocate(2).unwrap();
+
+    assert!(mgr.allocate_at(a, 1).is_err());
+    mgr.free(a, 2).unwrap();
+    assert_eq!(mgr.allocate_at(a, 2).unwrap(), a);
+    mgr.free(a, 2).unwrap();
+}
+
+#[test_case]
+fn allocate_constrained_respects_alignment_and_limit() {
+    let mut mgr = PAGE_MANAGER.lock();
+    let limit = FrameID(0x100000); // 4GB
+    let a = mgr.allocate_constrained(3, 16, limit).unwrap();
+
+    assert_eq!(a.0 % 16, 0);
+    assert!(a.0 + 3 <= limit.0);
+    mgr.free(a, 3).unwrap();
+}
@@ -26,18 +26,16 @@ where
     }
 
     pub fn set(&mut self, k: &K, v: V) -> bool {
-        self.arr.iter_mut().find_map(|ent| {
-            if let Some((key, _)) = *ent {
-                if *k == key {
-                    *ent = Some((*k, v));
-                    return Some(());
-                }
-            } else {
-                *ent = Some((*k, v));
-                return Some(());
-            }
-            None
-        }).is_some()
+        // an existing entry may come after a vacant slot, so look for the key first.
+        let ent = match self.arr.iter().position(|ent| matches!(ent, Some((key, _)) if key == k)) {
+            Some(i) => &mut self.arr[i],
+            None => match self.arr.iter_mut().find(|ent| ent.is_none()) {
+                Some(ent) => ent,
+                None => return false,
+            },
+        };
+        *ent = Some((*k, v));
+        true
     }
 
     pub fn delete(&mut self, k: &K) -> bool {
@@ -64,4 +62,77 @@ where
             None
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn set_get_delete() {
+        let mut map = ArrayMap::<u8, u32, 2>::new();
+        assert!(map.set(&1, 10));
+        assert!(map.set(&2, 20));
+        assert!(!map.set(&3, 30)); // full
+        assert!(map.set(&1, 11)); // overwrite
+
+        assert_eq!(map.get(&1), Some(11));
+        assert_eq!(map.get(&3), None);
+        assert!(map.delete(&1));
+        assert!(!map.delete(&1));
+        assert_eq!(map.take(2), Some(20));
+        assert_eq!(map.get(&2), None);
+    }
+
+    #[test]
+    fn overwrite_after_vacant_slot() {
+        let mut map = ArrayMap::<u8, u32, 4>::new();
+        map.set(&1, 10);
+        map.set(&2, 20);
+        map.delete(&1);
+        map.set(&2, 21); // slot 0 is vacant, but key 2 lives in slot 1.
+
+        assert!(map.delete(&2));
+        assert_eq!(map.get(&2), None);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Set(u8, u32),
+        Delete(u8),
+        Take(u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..8u8, any::<u32>()).prop_map(|(k, v)| Op::Set(k, v)),
+            (0..8u8).prop_map(Op::Delete),
+            (0..8u8).prop_map(Op::Take),
+        ]
+    }
+
+    proptest! {
+        /// The map behaves like a bounded `BTreeMap`.
+        #[test]
+        fn behaves_like_a_map(ops in proptest::collection::vec(op(), 0..64)) {
+            let mut map = ArrayMap::<u8, u32, 4>::new();
+            let mut model = std::collections::BTreeMap::new();
+
+            for op in ops {
+                match op {
+                    Op::Set(k, v) => {
+                        let fits = model.contains_key(&k) || model.len() < 4;
+                        prop_assert_eq!(map.set(&k, v), fits);
+                        if fits { model.insert(k, v); }
+                    },
+                    Op::Delete(k) => prop_assert_eq!(map.delete(&k), model.remove(&k).is_some()),
+                    Op::Take(k) => prop_assert_eq!(map.take(k), model.remove(&k)),
+                }
+                for k in 0..8u8 {
+                    prop_assert_eq!(map.get(&k), model.get(&k).copied());
+                }
+            }
+        }
+    }
+}
@@ -304,4 +304,64 @@ where
         },
         _ => None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn mouse_report() {
+        let packet = MousePacket { buttons: 0b101, x: -3, y: 7 };
+        let (report, ()) = packet.create_report(());
+        assert_eq!(report, MouseReport { buttons: 0b101, disp: (-3, 7) });
+    }
+
+    fn keyboard_packet(modifier: u8, keys: [u8; 6]) -> KeyboardPacket {
+        KeyboardPacket { modifier, _reserved: 0, keys }
+    }
+
+    #[test]
+    fn keyboard_report_tracks_previous_keys() {
+        let (first, info) = keyboard_packet(0x02, [0x04, 0, 0, 0, 0, 0]).create_report(Default::default());
+        assert_eq!(first.modifier, 0x02);
+        assert!(first.cur_keys[0x04]);
+        assert!(!first.cur_keys[0]); // no-event slots are not keys
+        assert!(first.prev_keys.not_any());
+
+        let (second, _) = keyboard_packet(0, [0x05, 0x04, 0, 0, 0, 0]).create_report(info);
+        assert!(second.cur_keys[0x04] && second.cur_keys[0x05]);
+        assert_eq!(second.prev_keys, first.cur_keys);
+    }
+
+    #[test]
+    fn packet_sizes() {
+        assert_eq!(core::mem::size_of::<MousePacket>(), 3);
+        assert_eq!(core::mem::size_of::<KeyboardPacket>(), 8);
+    }
+
+    proptest! {
+        /// The pressed keys are exactly the nonzero keys in the packet.
+        #[test]
+        fn keyboard_keys(modifier: u8, keys: [u8; 6], prev: [u8; 6]) {
+            let (_, info) = keyboard_packet(0, prev).create_report(Default::default());
+            let (report, next) = keyboard_packet(modifier, keys).create_report(info);
+
+            prop_assert_eq!(report.modifier, modifier);
+            prop_assert_eq!(report.prev_keys, info);
+            prop_assert_eq!(next, report.cur_keys);
+            for key in 0..256usize {
+                let pressed = key != 0 && keys.contains(&(key as u8));
+                prop_assert_eq!(report.cur_keys[key], pressed);
+            }
+        }
+
+        #[test]
+        fn mouse_displacement(buttons: u8, x: i8, y: i8) {
+            let (report, ()) = MousePacket { buttons, x, y }.create_report(());
+            prop_assert_eq!(report.buttons, buttons);
+            prop_assert_eq!(report.disp, (x as isize, y as isize));
+        }
+    }
+}
@@ -67,34 +67,38 @@ impl<'a> Iterator for DescriptorIterator<'a> {
     type Item = Descriptor<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.idx >= self.buf.len() {
-            None
-        } else {
-            let (len, ty, desc_body_ptr) = unsafe {
-                let desc_base_ptr = self.buf.as_ptr().add(self.idx);
-                let header = core::mem::transmute::<_, &DescriptorHeader>(desc_base_ptr);
-
-                (header.b_length as usize, header.b_descriptor_type.into(), desc_base_ptr.byte_add(core::mem::size_of::<DescriptorHeader>()))
-            };
-            self.idx += len;
-
-            Some(
-                match ty {
-                    // 0x01 => Descriptor::Device(unsafe {
-                    //     core::mem::transmute::<_, &DeviceDescriptorBody>(desc_body_ptr) as _
-                    // }),
-                    DescriptorType::Device => desc_body_cast!(Device, DeviceDescriptorBody, desc_body_ptr),
-                    DescriptorType::Configuration => desc_body_cast!(Configuration, ConfigurationDescriptorBody, desc_body_ptr),
-                    DescriptorType::Interface => desc_body_cast!(Interface, InterfaceDescriptorBody, desc_body_ptr),
-                    DescriptorType::Endpoint => desc_body_cast!(Endpoint, EndpointDescriptorBody, desc_body_ptr),
-                    _ => Descriptor::Unsupported,
-                }
-            )
-        }
+        // a malformed buffer ends the iteration, instead of reading beyond it.
+        let rest = self.buf.get(self.idx..)?;
+        if rest.len() < core::mem::size_of::<DescriptorHeader>() { return None; }
+
+        let len = rest[0] as usize;
+        let ty: DescriptorType = rest[1].into();
+        if len < core::mem::size_of::<DescriptorHeader>() || len > rest.len() { return None; }
+        self.idx += len;
+
+        let body = &rest[core::mem::size_of::<DescriptorHeader>()..len];
+        let desc_body_ptr = body.as_ptr();
+
+        // body structs are packed, so any address is aligned for them. only the length should be checked.
+        Some(
+            match ty {
+                // 0x01 => Descriptor::Device(unsafe {
+                //     core::mem::transmute::<_, &DeviceDescriptorBody>(desc_body_ptr) as _
+                // }),
+                DescriptorType::Device if body.len() >= core::mem::size_of::<DeviceDescriptorBody>()
+                    => desc_body_cast!(Device, DeviceDescriptorBody, desc_body_ptr),
+                DescriptorType::Configuration if body.len() >= core::mem::size_of::<ConfigurationDescriptorBody>()
+                    => desc_body_cast!(Configuration, ConfigurationDescriptorBody, desc_body_ptr),
+                DescriptorType::Interface if body.len() >= core::mem::size_of::<InterfaceDescriptorBody>()
+                    => desc_body_cast!(Interface, InterfaceDescriptorBody, desc_body_ptr),
+                DescriptorType::Endpoint if body.len() >= core::mem::size_of::<EndpointDescriptorBody>()
+                    => desc_body_cast!(Endpoint, EndpointDescriptorBody, desc_body_ptr),
+                _ => Descriptor::Unsupported,
+            }
+        )
     }
 }
 
-
 /// USB device descriptor body, excluding header.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(C, packed)]
@@ -172,4 +176,103 @@ impl EndpointDescriptorBody {
     pub fn usage_type(&self) -> u8 {
         self.bm_attributes.get_bits(4..=5)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A configuration descriptor set of a boot keyboard: configuration, interface, HID, endpoint.
+    const KEYBOARD_CONFIG: [u8; 34] = [
+        9, 0x02, 34, 0, 1, 1, 0, 0xa0, 50,
+        9, 0x04, 0, 0, 1, 3, 1, 1, 0,
+        9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0,
+        7, 0x05, 0x81, 0x03, 8, 0, 10,
+    ];
+
+    #[test]
+    fn iterates_configuration() {
+        let descs: Vec<_> = DescriptorIterator::from_buf(&KEYBOARD_CONFIG).collect();
+        assert_eq!(descs.len(), 4);
+
+        let Descriptor::Configuration(config) = descs[0] else { panic!("{:?}", descs[0]) };
+        assert_eq!({ config.w_total_length }, 34);
+        assert_eq!(config.b_num_interfaces, 1);
+        assert!(config.remote_wakeup());
+        assert!(!config.self_powered());
+
+        let Descriptor::Interface(interface) = descs[1] else { panic!("{:?}", descs[1]) };
+        assert_eq!(
+            (interface.b_interface_class, interface.b_interface_sub_class, interface.b_interface_protocol),
+            (3, 1, 1)
+        );
+
+        assert!(matches!(descs[2], Descriptor::Unsupported)); // HID
+
+        let Descriptor::Endpoint(endpoint) = descs[3] else { panic!("{:?}", descs[3]) };
+        assert_eq!(endpoint.b_endpoint_address, 0x81);
+        assert_eq!(endpoint.transfer_type(), EndpointType::Interrupt);
+        assert_eq!({ endpoint.w_max_packet_size }, 8);
+        assert_eq!(endpoint.b_interval, 10);
+    }
+
+    #[test]
+    fn device_descriptor() {
+        let buf = [18, 0x01, 0x00, 0x02, 0, 0, 0, 64, 0x27, 0x06, 0x01, 0x00, 0x00, 0x01, 1, 2, 3, 1];
+        let descs: Vec<_> = DescriptorIterator::from_buf(&buf).collect();
+
+        let [Descriptor::Device(device)] = descs[..] else { panic!("{:?}", descs) };
+        assert_eq!({ device.bcd_usb }, 0x0200);
+        assert_eq!(device.b_max_packet_size_0, 64);
+        assert_eq!({ device.id_vendor }, 0x0627);
+        assert_eq!(device.b_num_configurations, 1);
+    }
+
+    #[test]
+    fn zero_length_descriptor_terminates() {
+        let buf = [0, 0x04, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(DescriptorIterator::from_buf(&buf).count(), 0);
+    }
+
+    #[test]
+    fn truncated_descriptor_is_dropped() {
+        // the endpoint descriptor claims 7 bytes, but only 5 are left.
+        let buf = &KEYBOARD_CONFIG[..32];
+        assert_eq!(DescriptorIterator::from_buf(buf).count(), 3);
+
+        // a lone byte can't even hold a header.
+        assert_eq!(DescriptorIterator::from_buf(&[9]).count(), 0);
+    }
+
+    #[test]
+    fn short_body_is_unsupported() {
+        // an interface descriptor with 2 body bytes.
+        let buf = [4, 0x04, 0, 0];
+        let descs: Vec<_> = DescriptorIterator::from_buf(&buf).collect();
+        assert!(matches!(descs[..], [Descriptor::Unsupported]));
+    }
+
+    proptest! {
+        /// Any buffer is iterated within its bounds, and each descriptor consumes at least a header.
+        #[test]
+        fn arbitrary_buffers_terminate(buf in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let count = DescriptorIterator::from_buf(&buf).count();
+            prop_assert!(count <= buf.len() / core::mem::size_of::<DescriptorHeader>());
+        }
+
+        /// Well-formed descriptor sequences are iterated one by one.
+        #[test]
+        fn well_formed_sequences(
+            descs in proptest::collection::vec((2u8..32, any::<u8>()), 0..16)
+        ) {
+            let mut buf = Vec::new();
+            for &(len, ty) in descs.iter() {
+                buf.push(len);
+                buf.push(ty);
+                buf.extend(core::iter::repeat(0xa5).take(len as usize - 2));
+            }
+            prop_assert_eq!(DescriptorIterator::from_buf(&buf).count(), descs.len());
+        }
+    }
+}
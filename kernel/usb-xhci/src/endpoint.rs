@@ -126,4 +126,52 @@ impl EndpointConfig {
     pub fn ep_type_with_dir(&self) -> EndpointDirectedType {
         self.ep_type.with_dir(self.addr.is_in())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn control_endpoint() {
+        let control = EndpointAddress::control();
+        assert_eq!(control.byte(), 0x80);
+        assert_eq!(control.index(), 0);
+        assert!(control.is_in());
+        assert_eq!(control.dci(), 1);
+    }
+
+    #[test]
+    fn dci_examples() {
+        // DCI = 2 * index + direction(IN = 1).
+        assert_eq!(EndpointAddress::from_byte(0x81).dci(), 3);
+        assert_eq!(EndpointAddress::from_byte(0x02).dci(), 4);
+        assert_eq!(EndpointAddress::from_dci(3), EndpointAddress::from_byte(0x81));
+    }
+
+    #[test]
+    fn type_with_direction() {
+        assert_eq!(EndpointType::Control.with_dir(true), EndpointDirectedType::Control);
+        assert_eq!(EndpointType::Interrupt.with_dir(true), EndpointDirectedType::InterruptIn);
+        assert_eq!(EndpointType::Interrupt.with_dir(false), EndpointDirectedType::InterruptOut);
+        assert_eq!(EndpointType::Bulk.with_dir(true), EndpointDirectedType::BulkIn);
+        assert_eq!(EndpointType::Isochronous.with_dir(false), EndpointDirectedType::IsochOut);
+    }
+
+    proptest! {
+        #[test]
+        fn parts_round_trip(index in 0usize..16, is_in: bool) {
+            let addr = EndpointAddress::from_parts(index, is_in);
+            prop_assert_eq!(addr.index(), index);
+            prop_assert_eq!(addr.is_in(), is_in);
+            prop_assert_eq!(addr.dci(), 2 * index + is_in as usize);
+        }
+
+        #[test]
+        fn dci_round_trip(index in 0usize..16, is_in: bool) {
+            let addr = EndpointAddress::from_parts(index, is_in);
+            prop_assert_eq!(EndpointAddress::from_dci(addr.dci() as u8), addr);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)] // host tests use std
 #![feature(allocator_api)]
 #![feature(slice_ptr_get)]
 #![feature(box_patterns)]
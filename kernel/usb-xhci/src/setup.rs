@@ -193,4 +193,92 @@ pub mod requests {
             0
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::DescriptorType;
+    use proptest::prelude::*;
+
+    fn bytes(req: SetupRequest) -> [u8; 8] {
+        unsafe { core::mem::transmute(req) }
+    }
+
+    #[test]
+    fn layout_is_8_bytes() {
+        assert_eq!(core::mem::size_of::<SetupRequest>(), 8);
+    }
+
+    #[test]
+    fn get_descriptor_packing() {
+        let req = requests::get_descriptor(DescriptorType::Configuration, 0, 256);
+        // bmRequestType 0x80, GET_DESCRIPTOR, wValue 0x0200, wIndex 0, wLength 256, little endian.
+        assert_eq!(bytes(req), [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn set_protocol_packing() {
+        let req = requests::set_protocol(2);
+        // host-to-device, class, interface.
+        assert_eq!(bytes(req), [0x21, 0x0b, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn set_configuration_packing() {
+        let req = requests::set_configuration(1);
+        assert_eq!(bytes(req), [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    fn request_type() -> impl Strategy<Value = RequestType> {
+        prop_oneof![
+            Just(RequestType::Standard),
+            Just(RequestType::Class),
+            Just(RequestType::Vendor),
+        ]
+    }
+
+    fn recipient() -> impl Strategy<Value = Recipient> {
+        prop_oneof![
+            Just(Recipient::Device),
+            Just(Recipient::Interface),
+            Just(Recipient::Endpoint),
+            Just(Recipient::Other),
+        ]
+    }
+
+    proptest! {
+        /// Each field reads back what was packed, without disturbing the others.
+        #[test]
+        fn fields_round_trip(
+            is_in: bool,
+            req_ty in request_type(),
+            recipient in recipient(),
+            code: u8, value: u16, index: u16, length: u16,
+        ) {
+            let req = SetupRequest::new(is_in, req_ty, recipient, code, value, index, length);
+
+            prop_assert_eq!(req.is_in(), is_in);
+            prop_assert_eq!(req.request_type(), req_ty);
+            prop_assert_eq!(req.recipient(), recipient);
+            prop_assert_eq!({ req.request }, code);
+            prop_assert_eq!({ req.value }, value);
+            prop_assert_eq!({ req.index }, index);
+            prop_assert_eq!({ req.length }, length);
+        }
+
+        /// Setters overwrite a field regardless of its previous value.
+        #[test]
+        fn setters_overwrite(
+            first in (any::<bool>(), request_type(), recipient()),
+            second in (any::<bool>(), request_type(), recipient()),
+        ) {
+            let mut req = SetupRequest::new(first.0, first.1, first.2, 0, 0, 0, 0);
+            req.set_is_in(second.0)
+                .set_request_type(second.1)
+                .set_recipient(second.2);
+
+            prop_assert_eq!(req, SetupRequest::new(second.0, second.1, second.2, 0, 0, 0, 0));
+        }
+    }
+}
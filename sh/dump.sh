@@ -1 +0,0 @@
-objdump -d ./target/x86_64-gyur/debug/kernel > ./dump.txt
\ No newline at end of file
@@ -22,6 +22,122 @@ pub mod uefi_gop {
     };
 }
 
+/// The kernel ELF symbol table, copied into `LOADER_DATA` pages by the bootloader.
+/// Null pointers mean the kernel has no symbol table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KernelSymbols {
+    /// The `.symtab` section, an array of `Elf64_Sym`.
+    pub symtab: *const u8,
+    pub symtab_size: usize,
+    /// The `.strtab` section, which the symbol names refer to.
+    pub strtab: *const u8,
+    pub strtab_size: usize,
+}
+
+impl KernelSymbols {
+    pub const fn empty() -> Self {
+        Self {
+            symtab: core::ptr::null(),
+            symtab_size: 0,
+            strtab: core::ptr::null(),
+            strtab_size: 0,
+        }
+    }
+}
+
+/// Where the kernel image was loaded.
+/// A position-independent kernel can be loaded anywhere, so the bootloader tells where it is.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KernelImage {
+    /// The start of the loaded segments.
+    pub base: u64,
+    pub size: u64,
+    /// The load address minus the link address, which is zero for a non-relocatable kernel.
+    /// Symbol values are link addresses, so this is added to them.
+    pub bias: u64,
+}
+
+/// The initial ramdisk, a tar archive loaded into `LOADER_DATA` pages by the bootloader.
+/// A null pointer means there's no initrd.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Initrd {
+    pub base: *const u8,
+    pub size: usize,
+}
+
+impl Initrd {
+    pub const fn empty() -> Self {
+        Self {
+            base: core::ptr::null(),
+            size: 0,
+        }
+    }
+}
+
+/// The maximum length of a kernel file name in `BootOptions`.
+pub const MAX_KERNEL_NAME_LEN: usize = 32;
+
+/// The default log level of the kernel, chosen in the boot menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [Self; 6] = [Self::Off, Self::Error, Self::Warn, Self::Info, Self::Debug, Self::Trace];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// Options chosen in the boot menu, or their defaults.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct BootOptions {
+    /// The file name of the loaded kernel image, in ASCII.
+    pub kernel_name: [u8; MAX_KERNEL_NAME_LEN],
+    pub kernel_name_len: usize,
+    /// The GOP mode set by the bootloader, or None if the firmware's mode was kept.
+    pub gop_mode: Option<u32>,
+    pub log_level: LogLevel,
+    /// Whether the kernel was loaded at a randomized address.
+    pub kaslr: bool,
+}
+
+impl BootOptions {
+    pub const fn new() -> Self {
+        Self {
+            kernel_name: [0; MAX_KERNEL_NAME_LEN],
+            kernel_name_len: 0,
+            gop_mode: None,
+            log_level: LogLevel::Info,
+            kaslr: false,
+        }
+    }
+
+    pub fn kernel_name(&self) -> &str {
+        let len = self.kernel_name_len.min(MAX_KERNEL_NAME_LEN);
+        core::str::from_utf8(&self.kernel_name[..len]).unwrap_or("?")
+    }
+}
+
 /// The kernel argument type, which can be provided from bootloading process.
 /// This doesn't include memory map.
 #[derive(Debug, /* Copy, Clone, PartialEq, Eq */)]
@@ -29,4 +145,10 @@ pub mod uefi_gop {
 pub struct KernelArgs {
     pub gop_frame_buffer: uefi_gop::FrameBuffer<'static>,
     pub gop_mode_info: uefi_gop::ModeInfo,
+    pub image: KernelImage,
+    pub symbols: KernelSymbols,
+    pub initrd: Initrd,
+    /// The physical address of the ACPI RSDP from the UEFI configuration table, or zero if not found.
+    pub rsdp: u64,
+    pub options: BootOptions,
 }
\ No newline at end of file
@@ -0,0 +1,71 @@
+//! FAT disk images, written without mounting.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+
+use crate::Result;
+
+/// The size of a disk image. (200MB, as `create_disk.sh` did)
+const IMAGE_SIZE: u64 = 200 * 1024 * 1024;
+
+const VOLUME_LABEL: [u8; 11] = *b"GYUR OS    ";
+
+/// Write a FAT image at `path`, containing the given files.
+/// Each file is a pair of its path in the image (e.g. `efi/boot/BOOTX64.EFI`) and the source path.
+pub fn create(path: &Path, files: &[(&str, &Path)]) -> Result<()> {
+    let mut image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    image.set_len(IMAGE_SIZE)?;
+
+    fatfs::format_volume(&mut image, FormatVolumeOptions::new().fat_type(FatType::Fat32).volume_label(VOLUME_LABEL))?;
+
+    let fs = FileSystem::new(&mut image, FsOptions::new())?;
+    for &(dest, src) in files {
+        // create parent directories.
+        let mut dir = fs.root_dir();
+        let components: Vec<&str> = dest.split('/').collect();
+        let (name, parents) = components.split_last().unwrap();
+        for parent in parents {
+            dir = match dir.open_dir(parent) {
+                Ok(dir) => dir,
+                Err(_) => dir.create_dir(parent)?,
+            };
+        }
+
+        let mut file = dir.create_file(name)?;
+        file.truncate()?;
+        file.write_all(&fs::read(src).map_err(|err| format!("{}: {}", src.display(), err))?)?;
+    }
+    fs.unmount()?;
+
+    Ok(())
+}
+
+/// A temporary file, which is removed on drop.
+pub struct TempPath(pub PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("gyur-{}-{}", std::process::id(), name)))
+    }
+
+    /// A temporary copy of the file, e.g. OVMF variables which QEMU writes into.
+    pub fn copy_of(src: &Path, name: &str) -> Result<Self> {
+        let temp = Self::new(name);
+        fs::copy(src, &temp.0)?;
+        Ok(temp)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
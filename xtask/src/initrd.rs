@@ -0,0 +1,61 @@
+//! The initial ramdisk: a ustar archive of the `initrd` directory, which the kernel mounts as its root filesystem.
+
+use std::fs;
+use std::path::Path;
+
+use crate::Result;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Archive the directory, with paths relative to it.
+pub fn create(dir: &Path) -> Result<Vec<u8>> {
+    let mut archive = Vec::new();
+    append_dir(&mut archive, dir, "")?;
+    // the end of an archive is two zero blocks.
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    Ok(archive)
+}
+
+fn append_dir(archive: &mut Vec<u8>, dir: &Path, prefix: &str) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name()); // reproducible archives
+
+    for entry in entries {
+        let name = entry.file_name().into_string().map_err(|name| format!("non-UTF-8 file name {:?}", name))?;
+        let path = format!("{}{}", prefix, name);
+
+        if entry.file_type()?.is_dir() {
+            append_entry(archive, &format!("{}/", path), b'5', &[])?;
+            append_dir(archive, &entry.path(), &format!("{}/", path))?;
+        } else {
+            append_entry(archive, &path, b'0', &fs::read(entry.path())?)?;
+        }
+    }
+    Ok(())
+}
+
+fn append_entry(archive: &mut Vec<u8>, path: &str, typeflag: u8, data: &[u8]) -> Result<()> {
+    if path.len() > 100 {
+        return Err(format!("{}: path too long for ustar", path).into());
+    }
+
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..path.len()].copy_from_slice(path.as_bytes());
+    header[100..108].copy_from_slice(if typeflag == b'5' { b"0000755\0" } else { b"0000644\0" });
+    header[108..116].copy_from_slice(b"0000000\0"); // uid
+    header[116..124].copy_from_slice(b"0000000\0"); // gid
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0"); // mtime, zero for reproducible archives
+    header[156] = typeflag;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+
+    // the checksum is computed with the checksum field as spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    Ok(())
+}
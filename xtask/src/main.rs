@@ -0,0 +1,231 @@
+//! Build, run and test tasks for GYUR OS, replacing the shell scripts.
+//!
+//! Run with `cargo xtask <command>` from anywhere in the workspace.
+
+mod image;
+mod initrd;
+mod qemu;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::time::{Duration, Instant};
+
+use image::TempPath;
+use qemu::Profile;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+usage: cargo xtask <command> [--release]
+
+commands:
+    build            build the bootloader, the kernel and the initrd, and write disk.img
+    run [profile]    build, and boot disk.img in QEMU
+                     profiles: default, pci, xhci, trace, headless, ps2
+    test             run host tests, and boot each kernel test in QEMU
+    check            compile check of xtask, shared, the bootloader and the kernel
+    dump             disassemble the kernel into dump.txt
+";
+
+/// Time limit of a single test kernel.
+const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The workspace root.
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+/// Run cargo in the given directory, where its `.cargo/config.toml` selects the target.
+fn cargo(dir: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new(env!("CARGO")).current_dir(dir).args(args).status()?;
+    if !status.success() {
+        return Err(format!("`cargo {}` failed in {}", args.join(" "), dir.display()).into());
+    }
+    Ok(())
+}
+
+struct Tasks {
+    root: PathBuf,
+    release: bool,
+}
+
+impl Tasks {
+    fn profile_dir(&self) -> &'static str {
+        if self.release { "release" } else { "debug" }
+    }
+
+    fn cargo_args<'a>(&self, args: &[&'a str]) -> Vec<&'a str> {
+        let mut args = args.to_vec();
+        if self.release { args.push("--release"); }
+        args
+    }
+
+    fn bootloader_efi(&self) -> PathBuf {
+        self.root.join("target/x86_64-unknown-uefi").join(self.profile_dir()).join("bootloader.efi")
+    }
+
+    fn kernel_elf(&self) -> PathBuf {
+        self.root.join("target/x86_64-gyur").join(self.profile_dir()).join("kernel")
+    }
+
+    fn disk_image(&self) -> PathBuf {
+        self.root.join("disk.img")
+    }
+
+    /// Archive the `initrd` directory into `target/initrd.tar`.
+    fn build_initrd(&self) -> Result<PathBuf> {
+        let path = self.root.join("target/initrd.tar");
+        std::fs::write(&path, initrd::create(&self.root.join("initrd"))?)?;
+        Ok(path)
+    }
+
+    fn build_bootloader(&self) -> Result<()> {
+        cargo(&self.root.join("bootloader"), &self.cargo_args(&["build"]))
+    }
+
+    fn build(&self) -> Result<()> {
+        self.build_bootloader()?;
+        cargo(&self.root.join("kernel"), &self.cargo_args(&["build"]))?;
+
+        image::create(&self.disk_image(), &[
+            ("efi/boot/BOOTX64.EFI", &self.bootloader_efi()),
+            ("kernel.elf", &self.kernel_elf()),
+            ("initrd.tar", &self.build_initrd()?),
+        ])?;
+        println!("wrote {}", self.disk_image().display());
+        Ok(())
+    }
+
+    fn run(&self, profile: Profile) -> Result<()> {
+        self.build()?;
+
+        let status = qemu::command(&self.root, &self.disk_image(), &self.root.join("OVMF_VARS.fd"), profile)
+            .status()?;
+        if !status.success() {
+            return Err(format!("QEMU exited with {}", status).into());
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<()> {
+        // host crates, from the workspace root.
+        cargo(&self.root, &["check", "-p", "xtask", "-p", "shared"])?;
+        cargo(&self.root.join("bootloader"), &["check"])?;
+        cargo(&self.root.join("kernel"), &["check"])
+    }
+
+    fn dump(&self) -> Result<()> {
+        cargo(&self.root.join("kernel"), &self.cargo_args(&["build"]))?;
+
+        let output = Command::new("objdump").arg("-d").arg(self.kernel_elf()).output()?;
+        if !output.status.success() {
+            return Err("objdump failed".into());
+        }
+        std::fs::write(self.root.join("dump.txt"), output.stdout)?;
+        Ok(())
+    }
+
+    /// Build kernel tests, and returns the paths of the test kernels.
+    fn build_kernel_tests(&self) -> Result<Vec<PathBuf>> {
+        let output = Command::new(env!("CARGO"))
+            .current_dir(self.root.join("kernel"))
+            .args(self.cargo_args(&["test", "--no-run", "--message-format=json"]))
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err("failed to build kernel tests".into());
+        }
+
+        let mut tests = Vec::new();
+        for line in String::from_utf8(output.stdout)?.lines() {
+            let Ok(message) = serde_json::from_str::<serde_json::Value>(line) else { continue };
+            if message["reason"] != "compiler-artifact" || message["profile"]["test"] != true { continue; }
+            if let Some(executable) = message["executable"].as_str() {
+                tests.push(PathBuf::from(executable));
+            }
+        }
+        Ok(tests)
+    }
+
+    /// Boot a test kernel, and returns true if it passed.
+    fn run_kernel_test(&self, kernel: &Path) -> Result<bool> {
+        let name = kernel.file_name().unwrap().to_string_lossy().into_owned();
+        let disk = TempPath::new(&format!("{}.img", name));
+        let ovmf_vars = TempPath::copy_of(&self.root.join("OVMF_VARS.fd"), &format!("{}.vars.fd", name))?;
+
+        image::create(&disk.0, &[
+            ("efi/boot/BOOTX64.EFI", &self.bootloader_efi()),
+            ("kernel.elf", kernel),
+            ("initrd.tar", &self.build_initrd()?),
+        ])?;
+
+        let mut child = qemu::command(&self.root, &disk.0, &ovmf_vars.0, Profile::Test).spawn()?;
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.code() == Some(qemu::TEST_SUCCESS_STATUS));
+            }
+            if start.elapsed() > TEST_TIMEOUT {
+                child.kill()?;
+                child.wait()?;
+                println!("{}: timed out", name);
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn test(&self) -> Result<()> {
+        // host tests run on the host target, so they are invoked from the workspace root.
+        cargo(&self.root, &self.cargo_args(&["test", "-p", "usb-xhci", "-p", "kernel", "--lib"]))?;
+
+        self.build_bootloader()?;
+        let mut failed = Vec::new();
+        for kernel in self.build_kernel_tests()? {
+            println!("booting {}", kernel.display());
+            if !self.run_kernel_test(&kernel)? {
+                failed.push(kernel);
+            }
+        }
+
+        if !failed.is_empty() {
+            for kernel in failed.iter() {
+                println!("failed: {}", kernel.display());
+            }
+            return Err(format!("{} kernel test(s) failed", failed.len()).into());
+        }
+        println!("all kernel tests passed");
+        Ok(())
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let release = args.iter().any(|arg| arg == "--release");
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|&arg| arg != "--release").collect();
+
+    let tasks = Tasks { root: root(), release };
+    let result = match args[..] {
+        ["build"] => tasks.build(),
+        ["run"] => tasks.run(Profile::Default),
+        ["run", profile] => match Profile::from_name(profile) {
+            Some(profile) => tasks.run(profile),
+            None => Err(format!("unknown profile; one of {}", Profile::NAMES.join(", ")).into()),
+        },
+        ["test"] => tasks.test(),
+        ["check"] => tasks.check(),
+        ["dump"] => tasks.dump(),
+        _ => {
+            eprint!("{}", USAGE);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,95 @@
+//! QEMU launch profiles, which replace the `run_qemu_*.sh` variants.
+
+use std::path::Path;
+use std::process::Command;
+
+/// The `isa-debug-exit` device, through which test kernels exit QEMU.
+/// QEMU exits with `(code << 1) | 1`, so the success code 0x10 of the kernel becomes 33.
+pub const TEST_SUCCESS_STATUS: i32 = 33;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// The screen, with the QEMU monitor on stdio.
+    Default,
+    /// Traces PCI configuration accesses.
+    Pci,
+    /// Traces xHCI rings.
+    Xhci,
+    /// Traces PCI configuration writes, xHCI register writes and MSIs.
+    Trace,
+    /// No display, with COM1 on stdio.
+    Headless,
+    /// Without the USB keyboard and mouse, so that input comes from PS/2.
+    Ps2,
+    /// Headless, with the exit device for test kernels.
+    Test,
+}
+
+impl Profile {
+    pub const NAMES: [&'static str; 6] = ["default", "pci", "xhci", "trace", "headless", "ps2"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "default" => Self::Default,
+            "pci" => Self::Pci,
+            "xhci" => Self::Xhci,
+            "trace" => Self::Trace,
+            "headless" => Self::Headless,
+            "ps2" => Self::Ps2,
+            _ => return None,
+        })
+    }
+
+    fn args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Default => &["-monitor", "stdio"],
+            Self::Pci => &[
+                "-trace", "pci_cfg_read",
+                "-trace", "pci_cfg_write",
+                "-monitor", "stdio",
+            ],
+            Self::Xhci => &[
+                "-trace", "usb_xhci_queue_event",
+                "-trace", "usb_xhci_fetch_trb",
+                "-monitor", "stdio",
+            ],
+            Self::Trace => &[
+                "-trace", "pci_cfg_write",
+                "-trace", "usb_xhci_oper_write",
+                "-trace", "usb_xhci_irq_msi",
+                "-monitor", "stdio",
+            ],
+            Self::Headless => &[
+                "-display", "none",
+                "-serial", "stdio",
+                "-monitor", "none",
+            ],
+            Self::Ps2 => &["-monitor", "stdio"],
+            Self::Test => &[
+                "-device", "isa-debug-exit,iobase=0xf4,iosize=0x04",
+                "-display", "none",
+                "-serial", "stdio",
+                "-monitor", "none",
+            ],
+        }
+    }
+}
+
+/// The QEMU command booting the disk image with the given OVMF variables file.
+pub fn command(root: &Path, disk: &Path, ovmf_vars: &Path, profile: Profile) -> Command {
+    let mut cmd = Command::new("qemu-system-x86_64");
+    cmd.args(["-machine", "q35", "-smp", "4"]) // q35 has PCIe, and an MCFG for ECAM.
+        .arg("-drive")
+        .arg(format!("if=pflash,format=raw,readonly=on,file={}", root.join("OVMF_CODE.fd").display()))
+        .arg("-drive")
+        .arg(format!("if=pflash,format=raw,file={}", ovmf_vars.display()));
+    if profile != Profile::Ps2 {
+        cmd.args(["-device", "nec-usb-xhci,id=xhci"])
+            .args(["-device", "usb-mouse,bus=xhci.0"])
+            .args(["-device", "usb-kbd,bus=xhci.0"]);
+    }
+    cmd.args(profile.args())
+        .arg("-drive")
+        .arg(format!("format=raw,file={}", disk.display()));
+    cmd
+}